@@ -1,2 +1,7 @@
-from .kerndeterminer import KernDeterminer
-
+from .kerndeterminer import (
+    BatchResults,
+    KernDeterminer,
+    Outline,
+    determine_kern_for_outlines,
+    determine_kerns_for_family,
+)
@@ -0,0 +1,96 @@
+"""Command line interface.
+
+    python -m kerndeterminer watch MyFont.glyphs pairs.txt 120 0 0.8
+
+kerns the pairs listed in ``pairs.txt`` (one ``left right`` pair of glyph
+names per line) in every master, then watches the font and kerns again the
+pairs whose glyphs change each time it is saved. Kerns are printed as
+tab-separated ``left right master kern`` lines.
+"""
+import argparse
+import os
+import sys
+import time
+
+from . import KernDeterminer
+
+
+def read_pairs(path):
+    pairs = []
+    with open(path) as f:
+        for line in f:
+            fields = line.split()
+            if len(fields) == 2:
+                pairs.append(tuple(fields))
+    return pairs
+
+
+def modified(path):
+    """When a font file, or the newest file in a font directory, was saved."""
+    if not os.path.isdir(path):
+        return os.stat(path).st_mtime
+    return max(
+        (
+            os.stat(os.path.join(root, name)).st_mtime
+            for root, _, names in os.walk(path)
+            for name in names
+        ),
+        default=0,
+    )
+
+
+def emit(results):
+    for result in results.results:
+        print(f"{result.left}\t{result.right}\t{result.master}\t{result.kern:.0f}")
+    sys.stdout.flush()
+
+
+def watch(args):
+    kerner = KernDeterminer(args.font)
+    pairs = read_pairs(args.pairs)
+    kern = lambda pairs: kerner.determine_kerns_for_masters(
+        pairs, args.master, args.target_distance, args.height, args.max_tuck
+    )
+    emit(kern(pairs))
+    last = modified(args.font)
+    while True:
+        time.sleep(args.interval)
+        saved = modified(args.font)
+        if saved == last:
+            continue
+        last = saved
+        try:
+            changed = set(kerner.reload())
+        except OSError as e:
+            # Editors may be half way through writing the file.
+            print(e, file=sys.stderr)
+            continue
+        affected = [p for p in pairs if p[0] in changed or p[1] in changed]
+        if affected:
+            emit(kern(affected))
+
+
+def main():
+    parser = argparse.ArgumentParser(prog="kerndeterminer")
+    commands = parser.add_subparsers(dest="command", required=True)
+    parser_watch = commands.add_parser(
+        "watch", help="Kern pairs, and kern them again as their glyphs change"
+    )
+    parser_watch.add_argument("font")
+    parser_watch.add_argument("pairs", help="File of pairs, one per line")
+    parser_watch.add_argument("target_distance", type=float)
+    parser_watch.add_argument("height", type=int)
+    parser_watch.add_argument("max_tuck", type=float)
+    parser_watch.add_argument(
+        "--master", action="append", help="Master to kern (default: all)"
+    )
+    parser_watch.add_argument(
+        "--interval", type=float, default=0.5, help="Seconds between checks"
+    )
+    args = parser.parse_args()
+    if args.command == "watch":
+        watch(args)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,91 @@
+use crate::distance::path_distance;
+use crate::outline::Outline;
+use crate::overlap::overlaps;
+use crate::parameters::Parameters;
+use kurbo::{Point, Vec2};
+
+/// How close (in font units) the search gets to the offset which meets the
+/// target clearance.
+const PRECISION: f64 = 0.5;
+/// How many times the search may double its step looking for an offset on
+/// the other side of the target.
+const MAX_EXPANSIONS: usize = 20;
+
+/// Where a mark's anchor on a base should go.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Placement {
+    /// The anchor position on the base
+    pub anchor: Point,
+    /// How far the anchor moved from where it was, along the direction
+    pub offset: f64,
+    /// The clearance between mark and base ink at the new position
+    pub distance: f64,
+    /// Whether the target clearance was met
+    pub converged: bool,
+}
+
+/// The ink clearance between base and mark, with the mark moved by
+/// `shift`: the closest approach, or -1 if they touch or overlap.
+fn clearance(base: &Outline, mark: &Outline, shift: Vec2, parameters: &Parameters) -> Option<f64> {
+    let (x, y) = (shift.x as f32, -shift.y as f32);
+    if overlaps(&base.paths, &mark.paths, x, y) {
+        return Some(-1.0);
+    }
+    path_distance(&base.paths, &mark.paths, None, x, y, parameters).map(|m| m.distance)
+}
+
+/// Slides a mark along `direction` (a unit vector pointing away from the
+/// base) to where its ink clears the base's by the target distance, and
+/// returns where the base's anchor should be for the mark to sit there.
+/// The mark starts attached at `start`, the base anchor's current
+/// position; its own attaching anchor is at `mark_anchor`. Returns `None`
+/// if either glyph has no ink.
+pub(crate) fn place_mark(
+    base: &Outline,
+    mark: &Outline,
+    start: Point,
+    mark_anchor: Point,
+    direction: Vec2,
+    parameters: &Parameters,
+) -> Option<Placement> {
+    let target = parameters.target_distance as f64;
+    let attached = start - mark_anchor;
+    let at = |offset: f64| clearance(base, mark, attached + direction * offset, parameters);
+
+    // Find offsets either side of the target: `near` too close, `far` clear.
+    let mut step = target.max(PRECISION * 2.0);
+    let (mut near, mut far) = if at(0.0)? >= target {
+        let mut near = -step;
+        let mut expansions = 0;
+        while at(near)? >= target && expansions < MAX_EXPANSIONS {
+            step *= 2.0;
+            near -= step;
+            expansions += 1;
+        }
+        (near, near + step)
+    } else {
+        let mut far = step;
+        let mut expansions = 0;
+        while at(far)? < target && expansions < MAX_EXPANSIONS {
+            step *= 2.0;
+            far += step;
+            expansions += 1;
+        }
+        (far - step, far)
+    };
+    let converged = at(near)? < target && at(far)? >= target;
+    while converged && far - near > PRECISION {
+        let middle = (near + far) / 2.0;
+        if at(middle)? >= target {
+            far = middle;
+        } else {
+            near = middle;
+        }
+    }
+    Some(Placement {
+        anchor: start + direction * far,
+        offset: far,
+        distance: at(far)?,
+        converged,
+    })
+}
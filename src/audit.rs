@@ -0,0 +1,91 @@
+use crate::outline::Outline;
+use crate::overlap::{overlap_area, overlaps};
+use crate::parameters::{Mode, Parameters};
+use crate::solver::{measure_at, solve, Status};
+
+/// How bad a collision is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Severity {
+    /// The outlines come closer than the threshold
+    Tight,
+    /// The outlines touch or overlap
+    Collision,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Tight => "tight",
+            Severity::Collision => "collision",
+        }
+    }
+}
+
+/// What was wrong with a pair at its kern.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Finding {
+    pub severity: Severity,
+    /// The closest approach between the outlines, if they don't overlap
+    pub distance: Option<f64>,
+    /// The area the outlines share, if they overlap
+    pub area: f64,
+}
+
+/// Checks a pair at the kern it already has (in font units), as the
+/// parameters place it, and reports it if its outlines overlap or come
+/// closer than `threshold`. Pairs without ink to measure are never
+/// reported.
+pub(crate) fn audit_pair(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    kern: f32,
+    threshold: f64,
+) -> Option<Finding> {
+    let x_offset = kern + left.width;
+    let y_offset = parameters.left_offset(left) as f32;
+    if overlaps(&left.paths, &right.paths, x_offset, y_offset) {
+        return Some(Finding {
+            severity: Severity::Collision,
+            distance: None,
+            area: overlap_area(&left.paths, &right.paths, x_offset, y_offset),
+        });
+    }
+    let distance = measure_at(left, right, parameters, kern)?.distance;
+    if distance < threshold {
+        Some(Finding {
+            severity: Severity::Tight,
+            distance: Some(distance),
+            area: 0.0,
+        })
+    } else {
+        None
+    }
+}
+
+/// How much negative tracking (in font units, so usually negative) a pair
+/// can take on top of its kern before its outlines come closer than
+/// `threshold`: the most negative kern which keeps them clear, found as
+/// the tuck mode does, less the kern. A pair which is already too close
+/// has positive room. Pairs which stay clear down to the kern floor are
+/// given the room to the floor. Returns `None` if there is nothing to
+/// measure.
+pub(crate) fn tracking_room(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    kern: f32,
+    threshold: f32,
+) -> Option<f32> {
+    let mut parameters = parameters.clone();
+    parameters.mode = Mode::Tuck;
+    parameters.max_tuck = 0.0;
+    parameters.target_distance = threshold;
+    parameters.zones.clear();
+    parameters.target_curve.clear();
+    let solution = solve(left, right, &parameters, kern);
+    if solution.status == Status::NoDistance {
+        return None;
+    }
+    Some(solution.kern - kern)
+}
@@ -0,0 +1,212 @@
+use babelfont::{
+    Anchor, Axis, Component, Font, Glyph, GlyphCategory, Layer, Location, Master, Node, NodeType,
+    Path, PathDirection, Shape,
+};
+use kurbo::Affine;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Builds a font from babelfont's JSON serialization (as written to
+/// `.babelfont` files), so that a font another tool has already parsed
+/// can be handed over without reading it from disk again. Only what
+/// kerning needs is read: axes, masters and their kerning, and each
+/// glyph's master layers.
+pub(crate) fn load(data: &Value) -> Result<Font, String> {
+    let mut font = Font::new();
+    if let Some(upm) = data["upm"].as_u64() {
+        font.upm = upm as u16;
+    }
+
+    // Locations may be keyed by axis tag or by name; we key them by name.
+    let mut axis_names: HashMap<String, String> = HashMap::new();
+    for a in data["axes"].as_array().into_iter().flatten() {
+        let name = text(&a["name"]);
+        let tag = a["tag"].as_str().unwrap_or_default().to_string();
+        let mut axis = Axis::new(name.clone(), tag.clone());
+        axis.min = a["min"].as_f64().map(|v| v as f32);
+        axis.default = a["default"].as_f64().map(|v| v as f32);
+        axis.max = a["max"].as_f64().map(|v| v as f32);
+        axis_names.insert(tag, name.clone());
+        axis_names.insert(name.clone(), name);
+        font.axes.push(axis);
+    }
+
+    let masters = data["masters"]
+        .as_array()
+        .ok_or("The font has no masters")?;
+    for m in masters {
+        let id = m["id"].as_str().ok_or("A master has no ID")?;
+        let location = m["location"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(axis, value)| {
+                let name = axis_names.get(axis).unwrap_or(axis);
+                Some((name.clone(), value.as_f64()? as f32))
+            })
+            .collect();
+        let mut master = Master::new(&text(&m["name"]), id, Location(location));
+        master.kerning = kerning(&m["kerning"]);
+        font.masters.push(master);
+    }
+
+    for g in data["glyphs"].as_array().into_iter().flatten() {
+        font.glyphs.0.push(load_glyph(g));
+    }
+    Ok(font)
+}
+
+/// A name, which may be a plain string or a dictionary of translations.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Object(translations) => translations
+            .get("dflt")
+            .or_else(|| translations.get("en"))
+            .or_else(|| translations.values().next())
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Kerning given as a dictionary keyed by `left//right`, or as a list of
+/// `[left, right, value]` triples.
+fn kerning(value: &Value) -> HashMap<(String, String), i16> {
+    let mut kerning = HashMap::new();
+    if let Some(pairs) = value.as_object() {
+        for (key, kern) in pairs {
+            let mut names = key.splitn(2, "//");
+            if let (Some(left), Some(right), Some(kern)) =
+                (names.next(), names.next(), kern.as_f64())
+            {
+                kerning.insert((left.to_string(), right.to_string()), kern.round() as i16);
+            }
+        }
+    }
+    for triple in value.as_array().into_iter().flatten() {
+        if let (Some(left), Some(right), Some(kern)) =
+            (triple[0].as_str(), triple[1].as_str(), triple[2].as_f64())
+        {
+            kerning.insert((left.to_string(), right.to_string()), kern.round() as i16);
+        }
+    }
+    kerning
+}
+
+fn load_glyph(data: &Value) -> Glyph {
+    let layers = data["layers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(load_layer)
+        .collect();
+    Glyph {
+        name: data["name"].as_str().unwrap_or_default().to_string(),
+        production_name: data["production_name"].as_str().map(|n| n.to_string()),
+        category: match data["category"].as_str() {
+            Some("mark") => GlyphCategory::Mark,
+            _ => GlyphCategory::Base,
+        },
+        codepoints: data["codepoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|c| c.as_u64().map(|c| c as usize))
+            .collect(),
+        layers,
+        exported: data["exported"].as_bool().unwrap_or(true),
+        direction: None,
+    }
+}
+
+fn load_layer(data: &Value) -> Layer {
+    let mut layer = Layer::new(data["width"].as_f64().unwrap_or(0.0).round() as i32);
+    // A glyph's layer for a master is the one carrying its ID.
+    layer.id = data["_master"]
+        .as_str()
+        .or_else(|| data["master"].as_str())
+        .or_else(|| data["id"].as_str())
+        .map(|id| id.to_string());
+    layer.name = data["name"].as_str().map(|n| n.to_string());
+    for shape in data["shapes"].as_array().into_iter().flatten() {
+        if let Some(reference) = shape["ref"]
+            .as_str()
+            .or_else(|| shape["reference"].as_str())
+        {
+            layer.shapes.push(Shape::ComponentShape(Component {
+                reference: reference.to_string(),
+                transform: transform(&shape["transform"]),
+            }));
+        } else {
+            layer.push_path(load_path(shape));
+        }
+    }
+    for anchor in data["anchors"].as_array().into_iter().flatten() {
+        layer.anchors.push(Anchor {
+            name: anchor["name"].as_str().unwrap_or_default().to_string(),
+            x: anchor["x"].as_f64().unwrap_or(0.0).round() as i32,
+            y: anchor["y"].as_f64().unwrap_or(0.0).round() as i32,
+        });
+    }
+    layer
+}
+
+/// A path's nodes are `[x, y, type]` lists, or a single string of
+/// space-separated `x y type` triples, where the type is `m`, `l`, `o`,
+/// `c` or `q` (with an `s` after it for smooth nodes, which we ignore).
+fn load_path(shape: &Value) -> Path {
+    let mut nodes = vec![];
+    let mut push = |x: f64, y: f64, nodetype: &str| {
+        let nodetype = match nodetype.trim_end_matches('s') {
+            "m" => NodeType::Move,
+            "o" => NodeType::OffCurve,
+            "c" => NodeType::Curve,
+            "q" => NodeType::QCurve,
+            _ => NodeType::Line,
+        };
+        nodes.push(Node {
+            x: x as f32,
+            y: y as f32,
+            nodetype,
+        });
+    };
+    match &shape["nodes"] {
+        Value::String(s) => {
+            let fields: Vec<&str> = s.split_whitespace().collect();
+            for triple in fields.chunks_exact(3) {
+                if let (Ok(x), Ok(y)) = (triple[0].parse(), triple[1].parse()) {
+                    push(x, y, triple[2]);
+                }
+            }
+        }
+        Value::Array(list) => {
+            for node in list {
+                if let (Some(x), Some(y)) = (node[0].as_f64(), node[1].as_f64()) {
+                    push(x, y, node[2].as_str().unwrap_or("l"));
+                }
+            }
+        }
+        _ => {}
+    }
+    Path {
+        nodes,
+        closed: shape["closed"].as_bool().unwrap_or(true),
+        direction: PathDirection::Clockwise,
+    }
+}
+
+/// A component transform, as the six numbers of an affine matrix.
+fn transform(value: &Value) -> Affine {
+    let coefficients: Vec<f64> = value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|c| c.as_f64())
+        .collect();
+    match coefficients.as_slice() {
+        [a, b, c, d, e, f] => Affine::new([*a, *b, *c, *d, *e, *f]),
+        _ => Affine::IDENTITY,
+    }
+}
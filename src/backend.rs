@@ -0,0 +1,363 @@
+use crate::master_name;
+use crate::outline::{babelfont_anchors, babelfont_layer, measurement_layer, Outline};
+use babelfont::{Font, GlyphCategory, Master};
+use kurbo::{BezPath, Point};
+use std::collections::HashMap;
+
+/// A glyph's drawing in one master, as a backend reads it: its contours
+/// with components decomposed, its advance width and its anchors.
+#[derive(Debug, Clone)]
+pub(crate) struct GlyphLayer {
+    pub paths: Vec<BezPath>,
+    pub width: f32,
+    pub anchors: HashMap<String, Point>,
+}
+
+/// Where the fonts we measure come from: glyphs, masters and axes, the
+/// masters' metrics and kerning, kerning groups, and each glyph's layers
+/// with their advance widths and anchors. The solver only asks this
+/// trait, so another source of fonts (a faster UFO reader, binary fonts,
+/// synthetic fonts built in code) only needs to implement it.
+pub(crate) trait Backend: Sync {
+    /// The names of the font's glyphs, in order.
+    fn glyph_names(&self) -> Vec<String>;
+
+    /// Whether the font has a glyph by this name.
+    fn has_glyph(&self, glyph_name: &str) -> bool {
+        self.glyph_names().iter().any(|name| name == glyph_name)
+    }
+
+    /// The characters a glyph encodes, as code points, or `None` if there
+    /// is no such glyph.
+    fn codepoints(&self, glyph_name: &str) -> Option<&[usize]>;
+
+    /// Whether a glyph is a mark.
+    fn is_mark(&self, glyph_name: &str) -> bool;
+
+    /// Whether a glyph will ship in the built font. Glyphs the font
+    /// doesn't have count as exported.
+    fn is_exported(&self, _glyph_name: &str) -> bool {
+        true
+    }
+
+    /// The size of the font's em square, in font units.
+    fn units_per_em(&self) -> u16;
+
+    /// The font's masters, in order.
+    fn masters(&self) -> &[Master];
+
+    /// The master with the given name.
+    fn master_named(&self, name: &str) -> Option<&Master> {
+        self.masters().iter().find(|m| master_name(m) == name)
+    }
+
+    /// The master at every axis's default, or failing that the first.
+    fn default_master(&self) -> Option<&Master> {
+        self.masters().first()
+    }
+
+    /// The name masters' locations use for an axis, given its tag or its
+    /// name, or `None` if the font has no such axis.
+    fn axis_name(&self, _tag_or_name: &str) -> Option<String> {
+        None
+    }
+
+    /// The height of one of a master's metrics (`xHeight`, `capHeight`...)
+    /// in font units, if the master has it.
+    fn metric(&self, master_id: &str, metric: &str) -> Option<f32>;
+
+    /// The kerning groups for the first (left) side of pairs, or the
+    /// second, by group name.
+    fn kern_groups(&self, first: bool) -> &HashMap<String, Vec<String>>;
+
+    /// A master's kerning, by pair of glyph or group names, or `None` if
+    /// there is no such master.
+    fn kerning(&self, master_id: &str) -> Option<&HashMap<(String, String), i16>>;
+
+    /// A glyph's layer for measuring in a master (given by ID). Returns
+    /// `None` if the glyph or master doesn't exist or the glyph has no
+    /// layer for the master, and fails if the layer can't be read.
+    fn layer(&self, glyph_name: &str, master_id: &str) -> Option<Result<GlyphLayer, String>>;
+
+    /// A glyph's layer with the given ID, whatever it is for, with its
+    /// components decomposed in a master (given by ID). Backends whose
+    /// glyphs have no layers beyond their masters' needn't implement it.
+    fn layer_with_id(
+        &self,
+        _glyph_name: &str,
+        _layer_id: &str,
+        _master_id: &str,
+    ) -> Option<Result<GlyphLayer, String>> {
+        None
+    }
+
+    /// A glyph's anchors in a master (given by ID), by name.
+    fn anchors(&self, glyph_name: &str, master_id: &str) -> Option<HashMap<String, Point>> {
+        Some(self.layer(glyph_name, master_id)?.ok()?.anchors)
+    }
+
+    /// A glyph's outline in a master (given by ID), made from its layer
+    /// (see `layer`). Returns `None` as `layer` does, and fails if the
+    /// layer can't be read or measured.
+    fn outline(&self, glyph_name: &str, master_id: &str) -> Option<Result<Outline, String>> {
+        let master = self.masters().iter().find(|m| m.id == master_id)?;
+        let layer = self.layer(glyph_name, master_id)?;
+        Some(Outline::from_glyph_layer(glyph_name, master, layer))
+    }
+}
+
+impl Backend for Font {
+    fn glyph_names(&self) -> Vec<String> {
+        self.glyphs.0.iter().map(|g| g.name.clone()).collect()
+    }
+
+    fn has_glyph(&self, glyph_name: &str) -> bool {
+        self.glyphs.get(glyph_name).is_some()
+    }
+
+    fn codepoints(&self, glyph_name: &str) -> Option<&[usize]> {
+        Some(self.glyphs.get(glyph_name)?.codepoints.as_slice())
+    }
+
+    fn is_mark(&self, glyph_name: &str) -> bool {
+        self.glyphs
+            .get(glyph_name)
+            .map_or(false, |g| matches!(g.category, GlyphCategory::Mark))
+    }
+
+    fn is_exported(&self, glyph_name: &str) -> bool {
+        self.glyphs.get(glyph_name).map_or(true, |g| g.exported)
+    }
+
+    fn units_per_em(&self) -> u16 {
+        self.upm
+    }
+
+    fn masters(&self) -> &[Master] {
+        &self.masters
+    }
+
+    fn master_named(&self, name: &str) -> Option<&Master> {
+        self.master(name)
+    }
+
+    fn default_master(&self) -> Option<&Master> {
+        self.masters
+            .iter()
+            .find(|m| {
+                self.axes.iter().all(|a| {
+                    let name = a.name.get_default().map(|n| n.to_string());
+                    name.and_then(|n| m.location.0.get(&n).copied()) == a.default
+                })
+            })
+            .or_else(|| self.masters.first())
+    }
+
+    fn axis_name(&self, tag_or_name: &str) -> Option<String> {
+        self.axes
+            .iter()
+            .filter_map(|a| Some((a, a.name.get_default()?.to_string())))
+            .find(|(a, name)| a.tag == tag_or_name || name == tag_or_name)
+            .map(|(_, name)| name)
+    }
+
+    fn metric(&self, master_id: &str, metric: &str) -> Option<f32> {
+        let master = self.masters.iter().find(|m| m.id == master_id)?;
+        master.metrics.get(metric).map(|&height| height as f32)
+    }
+
+    fn kern_groups(&self, first: bool) -> &HashMap<String, Vec<String>> {
+        if first {
+            &self.first_kern_groups
+        } else {
+            &self.second_kern_groups
+        }
+    }
+
+    fn kerning(&self, master_id: &str) -> Option<&HashMap<(String, String), i16>> {
+        Some(&self.masters.iter().find(|m| m.id == master_id)?.kerning)
+    }
+
+    fn layer(&self, glyph_name: &str, master_id: &str) -> Option<Result<GlyphLayer, String>> {
+        let master = self.masters.iter().find(|m| m.id == master_id)?;
+        let layer = self.master_layer_for(glyph_name, master)?;
+        let measured = measurement_layer(self, glyph_name, master, layer);
+        Some(babelfont_layer(self, master, measured, layer))
+    }
+
+    fn layer_with_id(
+        &self,
+        glyph_name: &str,
+        layer_id: &str,
+        master_id: &str,
+    ) -> Option<Result<GlyphLayer, String>> {
+        let master = self.masters.iter().find(|m| m.id == master_id)?;
+        let layer = self
+            .glyphs
+            .get(glyph_name)?
+            .layers
+            .iter()
+            .find(|l| l.id.as_deref() == Some(layer_id))?;
+        Some(babelfont_layer(self, master, layer, layer))
+    }
+
+    fn anchors(&self, glyph_name: &str, master_id: &str) -> Option<HashMap<String, Point>> {
+        let master = self.masters.iter().find(|m| m.id == master_id)?;
+        Some(babelfont_anchors(
+            self.master_layer_for(glyph_name, master)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::OutlineCache;
+    use crate::classes::existing_kern;
+    use crate::parameters::Parameters;
+    use crate::solver::determine_kern;
+    use babelfont::Location;
+    use kurbo::{Rect, Shape};
+    use serde_json::json;
+
+    /// A font built in code: every glyph is a 100 unit square on a 100 unit
+    /// advance, in a single master.
+    struct Squares {
+        masters: Vec<Master>,
+        glyphs: Vec<String>,
+        groups: HashMap<String, Vec<String>>,
+        kerning: HashMap<(String, String), i16>,
+    }
+
+    impl Squares {
+        fn new(glyphs: &[&str]) -> Self {
+            Squares {
+                masters: vec![Master::new("Regular", "m", Location(HashMap::new()))],
+                glyphs: glyphs.iter().map(|g| g.to_string()).collect(),
+                groups: HashMap::new(),
+                kerning: HashMap::new(),
+            }
+        }
+    }
+
+    impl Backend for Squares {
+        fn glyph_names(&self) -> Vec<String> {
+            self.glyphs.clone()
+        }
+
+        fn codepoints(&self, glyph_name: &str) -> Option<&[usize]> {
+            if self.has_glyph(glyph_name) {
+                Some(&[])
+            } else {
+                None
+            }
+        }
+
+        fn is_mark(&self, _glyph_name: &str) -> bool {
+            false
+        }
+
+        fn units_per_em(&self) -> u16 {
+            1000
+        }
+
+        fn masters(&self) -> &[Master] {
+            &self.masters
+        }
+
+        fn metric(&self, _master_id: &str, _metric: &str) -> Option<f32> {
+            None
+        }
+
+        fn kern_groups(&self, _first: bool) -> &HashMap<String, Vec<String>> {
+            &self.groups
+        }
+
+        fn kerning(&self, master_id: &str) -> Option<&HashMap<(String, String), i16>> {
+            if master_id == "m" {
+                Some(&self.kerning)
+            } else {
+                None
+            }
+        }
+
+        fn layer(&self, glyph_name: &str, master_id: &str) -> Option<Result<GlyphLayer, String>> {
+            if master_id != "m" || !self.has_glyph(glyph_name) {
+                return None;
+            }
+            Some(Ok(GlyphLayer {
+                paths: vec![Rect::new(0.0, 0.0, 100.0, 100.0).to_path(0.1)],
+                width: 100.0,
+                anchors: HashMap::new(),
+            }))
+        }
+    }
+
+    #[test]
+    fn the_solver_runs_against_any_backend() {
+        let font = Squares::new(&["a", "b"]);
+        let master = &font.masters()[0];
+        let parameters = Parameters::new(100.0, 0, 0.0).for_master(&font, master);
+        let parameters = parameters.for_pair(&font, "a", "b");
+        let outlines = OutlineCache::new(false);
+        let solution =
+            determine_kern(&font, &outlines, master, "a", "b", &parameters, 0.0).unwrap();
+        assert!(solution.status.converged());
+        // The squares' edges are straight, so the kern is only out by as
+        // much as the analytic engine may overestimate the distance.
+        assert!((solution.kern - 100.0).abs() <= parameters.epsilon);
+    }
+
+    #[test]
+    fn existing_kerns_come_through_the_backend_groups() {
+        let mut font = Squares::new(&["a", "b", "c"]);
+        font.groups
+            .insert("round".to_string(), vec!["b".to_string(), "c".to_string()]);
+        font.kerning
+            .insert(("a".to_string(), "@round".to_string()), -40);
+        font.kerning.insert(("a".to_string(), "c".to_string()), -20);
+        let master = &font.masters()[0];
+        assert_eq!(existing_kern(&font, master, "a", "b"), Some(-40.0));
+        assert_eq!(existing_kern(&font, master, "a", "c"), Some(-20.0));
+        assert_eq!(existing_kern(&font, master, "b", "a"), None);
+    }
+
+    #[test]
+    fn babelfont_layers_and_anchors_come_through_the_backend() {
+        let square = json!({
+            "nodes": [[0, 0, "l"], [100, 0, "l"], [100, 100, "l"], [0, 100, "l"]],
+            "closed": true
+        });
+        let font = crate::babelfont_json::load(&json!({
+            "masters": [{"id": "m", "name": "Regular"}],
+            "glyphs": [{
+                "name": "a",
+                "exported": false,
+                "layers": [
+                    {
+                        "id": "m",
+                        "width": 100,
+                        "anchors": [{"name": "top", "x": 50, "y": 100}],
+                        "shapes": [square]
+                    },
+                    {"id": "alternate", "width": 200, "shapes": [square]}
+                ]
+            }]
+        }))
+        .unwrap();
+        assert_eq!(
+            font.master_named("Regular").map(|m| m.id.as_str()),
+            Some("m")
+        );
+        assert_eq!(
+            font.anchors("a", "m").unwrap().get("top"),
+            Some(&Point::new(50.0, 100.0))
+        );
+        let layer = font.layer_with_id("a", "alternate", "m").unwrap().unwrap();
+        assert_eq!(layer.width, 200.0);
+        assert_eq!(layer.paths.len(), 1);
+        assert!(font.layer_with_id("a", "missing", "m").is_none());
+        assert!(!font.is_exported("a"));
+        assert!(font.is_exported("missing"));
+    }
+}
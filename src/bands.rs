@@ -0,0 +1,70 @@
+use kurbo::{BezPath, Shape};
+
+/// Height (in font units) of each band of a profile.
+const BAND_HEIGHT: f64 = 32.0;
+
+/// How far a contour reaches left and right within each of a stack of
+/// fixed-height horizontal bands, which gives a cheap lower bound on its
+/// distance from another contour before any exact segment work. Extents
+/// come from the segments' bounding boxes, so they always contain the ink.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BandProfile {
+    /// The index of the lowest band, counting up from the baseline
+    first: i64,
+    /// The leftmost and rightmost ink in each band from the lowest, or
+    /// `None` for bands the contour skips
+    extents: Vec<Option<(f64, f64)>>,
+}
+
+impl BandProfile {
+    pub fn new(path: &BezPath) -> Self {
+        let boxes: Vec<_> = path.segments().map(|s| s.bounding_box()).collect();
+        let band = |y: f64| (y / BAND_HEIGHT).floor() as i64;
+        let first = match boxes.iter().map(|b| band(b.y0)).min() {
+            Some(first) => first,
+            None => return BandProfile::default(),
+        };
+        let last = boxes.iter().map(|b| band(b.y1)).max().unwrap_or(first);
+        let mut extents: Vec<Option<(f64, f64)>> = vec![None; (last - first + 1) as usize];
+        for b in boxes.iter() {
+            for index in band(b.y0)..=band(b.y1) {
+                let extent = &mut extents[(index - first) as usize];
+                *extent = Some(match *extent {
+                    Some((low, high)) => (low.min(b.x0), high.max(b.x1)),
+                    None => (b.x0, b.x1),
+                });
+            }
+        }
+        BandProfile { first, extents }
+    }
+
+    /// The closest this contour, raised by `dy`, can come to `other` moved
+    /// right by `dx`: the least distance between the ink extents of any
+    /// two of their bands. Returns `None` if either has no ink.
+    pub fn lower_bound(&self, other: &BandProfile, dx: f64, dy: f64) -> Option<f64> {
+        let mut bound: Option<f64> = None;
+        for (i, mine) in self.extents.iter().enumerate() {
+            let (low, high) = match mine {
+                Some(extent) => *extent,
+                None => continue,
+            };
+            let bottom = (self.first + i as i64) as f64 * BAND_HEIGHT + dy;
+            for (j, theirs) in other.extents.iter().enumerate() {
+                let (other_low, other_high) = match theirs {
+                    Some(extent) => *extent,
+                    None => continue,
+                };
+                let other_bottom = (other.first + j as i64) as f64 * BAND_HEIGHT;
+                let gap_x = (other_low + dx - high).max(low - other_high - dx).max(0.0);
+                let gap_y = (other_bottom - bottom - BAND_HEIGHT)
+                    .max(bottom - other_bottom - BAND_HEIGHT)
+                    .max(0.0);
+                let distance = gap_x.hypot(gap_y);
+                if bound.map_or(true, |b| distance < b) {
+                    bound = Some(distance);
+                }
+            }
+        }
+        bound
+    }
+}
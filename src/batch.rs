@@ -0,0 +1,624 @@
+use crate::classes::flatten;
+use crate::fea::{contextual_fea, rise_fea};
+use crate::parameters::TraceOutput;
+use crate::solver::{Solution, Status, Step};
+use crate::timing::PairTimings;
+use crate::ttx::{kern_table_ttx, pair_pos_ttx};
+use core::cmp::Ordering;
+use numpy::PyArray1;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::{BTreeMap, HashMap};
+
+/// Width (in font units) of each bucket in the summary histogram.
+const HISTOGRAM_BUCKET: f32 = 50.0;
+/// How many pairs to report at each extreme of the distribution.
+const EXTREMES: usize = 10;
+
+/// The kern determined for a single pair in a batch run.
+#[pyclass]
+#[derive(Debug, Clone)]
+pub(crate) struct KernResult {
+    #[pyo3(get, set)]
+    pub left: String,
+    #[pyo3(get, set)]
+    pub right: String,
+    #[pyo3(get)]
+    pub master: String,
+    #[pyo3(get)]
+    pub height: i32,
+    #[pyo3(get)]
+    pub kern: f32,
+    #[pyo3(get)]
+    pub iterations: usize,
+    pub status: Status,
+    /// Where the time went, if timings were asked for
+    pub timings: Option<PairTimings>,
+    /// How far the right glyph would have to rise for the pair to reach
+    /// its target, if that was asked for and kerning alone couldn't
+    #[pyo3(get)]
+    pub rise: Option<f32>,
+    /// The kern without the optional marks, if the run was measured with
+    /// marks and asked for both
+    #[pyo3(get)]
+    pub bare_kern: Option<f32>,
+    /// What the master's target distances were scaled by, if the run
+    /// scaled them by weight
+    #[pyo3(get)]
+    pub target_scale: Option<f32>,
+    /// Each step the solver took, if the run was asked for a trace
+    pub trace: Option<Vec<TraceStep>>,
+    /// For a pair of classes kerned for its worst case, the pair of
+    /// members whose kern was kept
+    #[pyo3(get)]
+    pub decided_by: Option<(String, String)>,
+}
+
+/// A step of the solver's trace, in the units the kern is reported in.
+#[derive(Debug, Clone)]
+pub(crate) struct TraceStep {
+    kern: f32,
+    distance: f64,
+    target: f64,
+    decision: &'static str,
+}
+
+impl KernResult {
+    pub fn new(
+        left: String,
+        right: String,
+        master: String,
+        height: i32,
+        solution: Solution,
+    ) -> Self {
+        KernResult {
+            left,
+            right,
+            master,
+            height,
+            kern: solution.kern,
+            iterations: solution.iterations,
+            status: solution.status,
+            timings: None,
+            rise: None,
+            bare_kern: None,
+            target_scale: None,
+            trace: None,
+            decided_by: None,
+        }
+    }
+
+    /// A copy of the result for another pair which measures the same.
+    /// Since the pair wasn't solved, it reports zero iterations and no
+    /// timings or trace.
+    pub fn aliased(&self, left: String, right: String) -> Self {
+        KernResult {
+            left,
+            right,
+            iterations: 0,
+            timings: None,
+            trace: None,
+            ..self.clone()
+        }
+    }
+
+    /// Keeps or logs the solver's steps for the pair, as `output` says,
+    /// converting them to the units reported (`scale` font units each).
+    pub fn record_trace(
+        &mut self,
+        trace: Option<Vec<Step>>,
+        output: Option<TraceOutput>,
+        scale: f32,
+    ) {
+        let (trace, output) = match (trace, output) {
+            (Some(trace), Some(output)) => (trace, output),
+            _ => return,
+        };
+        let steps: Vec<TraceStep> = trace
+            .iter()
+            .map(|step| TraceStep {
+                kern: step.kern / scale,
+                distance: step.measurement.distance / scale as f64,
+                target: step.measurement.target / scale as f64,
+                decision: step.decision.as_str(),
+            })
+            .collect();
+        match output {
+            TraceOutput::Return => self.trace = Some(steps),
+            TraceOutput::Log => {
+                let steps: Vec<serde_json::Value> = steps
+                    .iter()
+                    .map(|s| {
+                        serde_json::json!({
+                            "kern": s.kern,
+                            "distance": s.distance,
+                            "target": s.target,
+                            "decision": s.decision,
+                        })
+                    })
+                    .collect();
+                let line = serde_json::json!({
+                    "left": self.left,
+                    "right": self.right,
+                    "master": self.master,
+                    "height": self.height,
+                    "kern": self.kern,
+                    "status": self.status.as_str(),
+                    "trace": steps,
+                });
+                log::info!(target: "kerndeterminer::trace", "{}", line);
+            }
+        }
+    }
+}
+
+#[pymethods]
+impl KernResult {
+    #[getter]
+    fn status(&self) -> &'static str {
+        self.status.as_str()
+    }
+
+    /// Each step the solver took for this pair, if the run was asked for a
+    /// `trace`: dictionaries of the `kern` tried, the `distance` measured
+    /// there and the `target`, and the `decision` the solver made.
+    #[getter]
+    fn trace<'py>(&self, py: Python<'py>) -> PyResult<Option<Vec<&'py PyDict>>> {
+        self.trace
+            .as_ref()
+            .map(|trace| {
+                trace
+                    .iter()
+                    .map(|step| {
+                        let dict = PyDict::new(py);
+                        dict.set_item("kern", step.kern)?;
+                        dict.set_item("distance", step.distance)?;
+                        dict.set_item("target", step.target)?;
+                        dict.set_item("decision", step.decision)?;
+                        Ok(dict)
+                    })
+                    .collect()
+            })
+            .transpose()
+    }
+
+    /// The seconds spent on `conversion`, `measurement` and `solving` for
+    /// this pair, if the run was asked for `timings`.
+    #[getter]
+    fn timings(&self) -> Option<HashMap<&'static str, f64>> {
+        self.timings.as_ref().map(timings_dict)
+    }
+}
+
+/// What the designer wants for a pair instead of the computed kern.
+enum Override {
+    Kern(f32),
+    Skip,
+}
+
+/// The results of a batch run, in the order the pairs were given.
+#[pyclass]
+pub(crate) struct BatchResults {
+    pub results: Vec<KernResult>,
+}
+
+#[pymethods]
+impl BatchResults {
+    #[new]
+    fn new(results: Vec<KernResult>) -> Self {
+        BatchResults { results }
+    }
+
+    /// Joins several sets of results, such as runs at different heights.
+    #[staticmethod]
+    fn concat(batches: Vec<PyRef<BatchResults>>) -> BatchResults {
+        BatchResults {
+            results: batches
+                .iter()
+                .flat_map(|b| b.results.iter().cloned())
+                .collect(),
+        }
+    }
+
+    #[getter]
+    fn results(&self) -> Vec<KernResult> {
+        self.results.clone()
+    }
+
+    /// The factor each master's target distances were scaled by, keyed by
+    /// master name, for runs which scaled them by weight.
+    #[getter]
+    fn target_scales(&self) -> HashMap<String, f32> {
+        self.results
+            .iter()
+            .filter_map(|r| Some((r.master.clone(), r.target_scale?)))
+            .collect()
+    }
+
+    /// Replaces computed kerns with the designer's own. `overrides` maps
+    /// `(left, right, master)` or `(left, right)` (for every master) to a
+    /// kern, or to `"skip"` to leave the pair out altogether; a key naming
+    /// the master takes precedence. Overridden results get the status
+    /// `"overridden"`, and since every exporter works from the results,
+    /// the overrides carry through to all of them.
+    fn with_overrides(&self, overrides: &PyDict) -> PyResult<BatchResults> {
+        let mut parsed: HashMap<(String, String, Option<String>), Override> = HashMap::new();
+        for (key, value) in overrides.iter() {
+            let key = match key.extract::<(String, String, String)>() {
+                Ok((left, right, master)) => (left, right, Some(master)),
+                Err(_) => {
+                    let (left, right): (String, String) = key.extract()?;
+                    (left, right, None)
+                }
+            };
+            let value = match value.extract::<&str>() {
+                Ok("skip") => Override::Skip,
+                Ok(other) => {
+                    return Err(PyValueError::new_err(format!(
+                        "Overrides should be kerns or \"skip\", not {:?}",
+                        other
+                    )))
+                }
+                Err(_) => Override::Kern(value.extract()?),
+            };
+            parsed.insert(key, value);
+        }
+        let results = self
+            .results
+            .iter()
+            .filter_map(|r| {
+                let (left, right) = (r.left.clone(), r.right.clone());
+                let specific = parsed.get(&(left.clone(), right.clone(), Some(r.master.clone())));
+                match specific.or_else(|| parsed.get(&(left, right, None))) {
+                    None => Some(r.clone()),
+                    Some(Override::Skip) => None,
+                    Some(Override::Kern(kern)) => Some(KernResult {
+                        kern: *kern,
+                        status: Status::Overridden,
+                        ..r.clone()
+                    }),
+                }
+            })
+            .collect();
+        Ok(BatchResults { results })
+    }
+
+    /// The results with their glyphs renamed by `names`, a dictionary of
+    /// new names by old, such as `KernDeterminer.production_names()`.
+    /// Glyphs not in it keep their names. Every exporter works from the
+    /// results, so they all write the new names.
+    fn renamed(&self, names: HashMap<String, String>) -> BatchResults {
+        let rename = |name: &String| names.get(name).unwrap_or(name).clone();
+        let results = self
+            .results
+            .iter()
+            .map(|r| KernResult {
+                left: rename(&r.left),
+                right: rename(&r.right),
+                ..r.clone()
+            })
+            .collect();
+        BatchResults { results }
+    }
+
+    /// The seconds spent on each stage, added up over the pairs which
+    /// were timed (their number is given as `pairs`), or `None` if the
+    /// run was not asked for `timings`. Pairs are kerned in parallel, so
+    /// the total may be more than the run took.
+    fn timings(&self) -> Option<HashMap<&'static str, f64>> {
+        let mut total = PairTimings::default();
+        let mut pairs = 0;
+        for timings in self.results.iter().filter_map(|r| r.timings.as_ref()) {
+            total.add(timings);
+            pairs += 1;
+        }
+        if pairs == 0 {
+            return None;
+        }
+        let mut dict = timings_dict(&total);
+        dict.insert("pairs", pairs as f64);
+        Some(dict)
+    }
+
+    /// Maps `(left, right, master)` to the kern value. Fine for small runs;
+    /// for large ones use `to_numpy`.
+    fn to_dict(&self) -> HashMap<(String, String, String), f32> {
+        self.results
+            .iter()
+            .map(|r| ((r.left.clone(), r.right.clone(), r.master.clone()), r.kern))
+            .collect()
+    }
+
+    /// Returns a dictionary of NumPy arrays (`kern`, `height` and
+    /// `iterations`), one row per result in the order the pairs were given.
+    /// The `left`, `right` and `master` arrays give each row's glyphs and
+    /// master as indices into the `left_names`, `right_names` and
+    /// `masters` lists, so rows can be matched to pairs without building a
+    /// string for every row.
+    fn to_numpy<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let dict = PyDict::new(py);
+        for (column, names, values) in [
+            (
+                "left",
+                "left_names",
+                index_names(self.results.iter().map(|r| &r.left)),
+            ),
+            (
+                "right",
+                "right_names",
+                index_names(self.results.iter().map(|r| &r.right)),
+            ),
+            (
+                "master",
+                "masters",
+                index_names(self.results.iter().map(|r| &r.master)),
+            ),
+        ]
+        .iter()
+        {
+            dict.set_item(*column, PyArray1::from_slice(py, &values.0))?;
+            dict.set_item(*names, &values.1)?;
+        }
+        dict.set_item(
+            "kern",
+            PyArray1::from_vec(py, self.results.iter().map(|r| r.kern).collect()),
+        )?;
+        dict.set_item(
+            "height",
+            PyArray1::from_vec(py, self.results.iter().map(|r| r.height).collect()),
+        )?;
+        dict.set_item(
+            "iterations",
+            PyArray1::from_vec(
+                py,
+                self.results.iter().map(|r| r.iterations as u32).collect(),
+            ),
+        )?;
+        Ok(dict)
+    }
+
+    /// Writes the results of runs at several heights as a contextual FEA
+    /// lookup, choosing each kern by how many stack glyphs follow the left
+    /// glyph. `depths` maps each stack depth to the height it implies.
+    /// Only the results for `master` are written, if given.
+    #[args(name = "\"kern_stack\"", master = "None")]
+    fn contextual_fea(
+        &self,
+        depths: HashMap<usize, i32>,
+        stack_glyphs: Vec<String>,
+        name: &str,
+        master: Option<&str>,
+    ) -> String {
+        contextual_fea(&self.results, master, &depths, &stack_glyphs, name)
+    }
+
+    /// Writes the results for a master (or all the results, if no master
+    /// is given) as an FEA pair positioning lookup which raises the right
+    /// glyph of each pair with a suggested `rise` as well as kerning it.
+    /// Rules are in logical order for right-to-left text unless
+    /// `right_to_left` is false.
+    #[args(master = "None", name = "\"kern_rise\"", right_to_left = "true")]
+    fn rise_fea(&self, master: Option<&str>, name: &str, right_to_left: bool) -> String {
+        rise_fea(&self.results, master, name, right_to_left)
+    }
+
+    /// Writes the results for a master (or all the results, if no master
+    /// is given) as a TTX `kern` table, for legacy environments or for
+    /// patching a binary font quickly with `ttx -m`. Results for classes
+    /// should be flattened first.
+    #[args(master = "None")]
+    fn to_ttx_kern(&self, master: Option<&str>) -> String {
+        kern_table_ttx(&self.results, master)
+    }
+
+    /// Writes the results for a master (or all the results) as a TTX
+    /// fragment holding a GPOS pair positioning lookup, for pipelines which
+    /// patch binary fonts through a `ttx` round trip. Pass the font's
+    /// `glyph_order` so the coverage table comes out in glyph ID order.
+    /// Results for classes should be flattened first.
+    #[args(master = "None", glyph_order = "None", index = "0")]
+    fn to_ttx_gpos(
+        &self,
+        master: Option<&str>,
+        glyph_order: Option<Vec<String>>,
+        index: usize,
+    ) -> String {
+        pair_pos_ttx(&self.results, master, glyph_order.as_deref(), index)
+    }
+
+    /// Expands results for kerning classes into a flat table of glyph
+    /// pairs. `classes` maps class names to their member glyphs.
+    fn flatten(&self, classes: HashMap<String, Vec<String>>) -> BatchResults {
+        BatchResults {
+            results: flatten(&self.results, &classes),
+        }
+    }
+
+    fn summary(&self) -> Summary {
+        Summary::new(&self.results)
+    }
+}
+
+/// Statistics describing a batch run, for checking that it is sane.
+#[pyclass]
+#[derive(Debug, Clone)]
+pub(crate) struct Summary {
+    #[pyo3(get)]
+    count: usize,
+    #[pyo3(get)]
+    mean: Option<f32>,
+    #[pyo3(get)]
+    median: Option<f32>,
+    #[pyo3(get)]
+    minimum: Option<f32>,
+    #[pyo3(get)]
+    maximum: Option<f32>,
+    /// (bucket start, number of pairs) for each non-empty bucket
+    #[pyo3(get)]
+    histogram: Vec<(f32, usize)>,
+    #[pyo3(get)]
+    tuck_limited: Vec<KernResult>,
+    #[pyo3(get)]
+    kern_limited: Vec<KernResult>,
+    #[pyo3(get)]
+    floor_limited: Vec<KernResult>,
+    #[pyo3(get)]
+    dot_limited: Vec<KernResult>,
+    #[pyo3(get)]
+    not_converged: Vec<KernResult>,
+    #[pyo3(get)]
+    no_distance: Vec<KernResult>,
+    #[pyo3(get)]
+    needs_remeasure: Vec<KernResult>,
+    #[pyo3(get)]
+    kinked: Vec<KernResult>,
+    #[pyo3(get)]
+    largest_positive: Vec<KernResult>,
+    #[pyo3(get)]
+    largest_negative: Vec<KernResult>,
+}
+
+impl Summary {
+    /// Pairs with no distance to measure are only listed under
+    /// `no_distance`: their kern of zero is a placeholder, so they are
+    /// left out of the count and every statistic.
+    pub fn new(results: &[KernResult]) -> Self {
+        let mut sorted: Vec<&KernResult> = results
+            .iter()
+            .filter(|r| r.status != Status::NoDistance)
+            .collect();
+        sorted.sort_by(|a, b| a.kern.partial_cmp(&b.kern).unwrap_or(Ordering::Equal));
+        let count = sorted.len();
+
+        let mut buckets: BTreeMap<i32, usize> = BTreeMap::new();
+        for r in &sorted {
+            *buckets
+                .entry((r.kern / HISTOGRAM_BUCKET).floor() as i32)
+                .or_insert(0) += 1;
+        }
+        let with_status = |status: Status| -> Vec<KernResult> {
+            results
+                .iter()
+                .filter(|r| r.status == status)
+                .cloned()
+                .collect()
+        };
+
+        Summary {
+            count,
+            mean: if count > 0 {
+                Some(sorted.iter().map(|r| r.kern).sum::<f32>() / count as f32)
+            } else {
+                None
+            },
+            median: match count {
+                0 => None,
+                _ if count % 2 == 0 => {
+                    Some((sorted[count / 2 - 1].kern + sorted[count / 2].kern) / 2.0)
+                }
+                _ => Some(sorted[count / 2].kern),
+            },
+            minimum: sorted.first().map(|r| r.kern),
+            maximum: sorted.last().map(|r| r.kern),
+            histogram: buckets
+                .into_iter()
+                .map(|(bucket, n)| (bucket as f32 * HISTOGRAM_BUCKET, n))
+                .collect(),
+            tuck_limited: with_status(Status::TuckLimited),
+            kern_limited: with_status(Status::KernLimited),
+            floor_limited: with_status(Status::FloorLimited),
+            dot_limited: with_status(Status::DotLimited),
+            not_converged: with_status(Status::NotConverged),
+            no_distance: with_status(Status::NoDistance),
+            needs_remeasure: with_status(Status::NeedsRemeasure),
+            kinked: with_status(Status::Kinked),
+            largest_positive: sorted
+                .iter()
+                .rev()
+                .take_while(|r| r.kern > 0.0)
+                .take(EXTREMES)
+                .map(|r| (*r).clone())
+                .collect(),
+            largest_negative: sorted
+                .iter()
+                .take_while(|r| r.kern < 0.0)
+                .take(EXTREMES)
+                .map(|r| (*r).clone())
+                .collect(),
+        }
+    }
+}
+
+fn timings_dict(timings: &PairTimings) -> HashMap<&'static str, f64> {
+    let mut dict = HashMap::new();
+    dict.insert("conversion", timings.conversion);
+    dict.insert("measurement", timings.measurement);
+    dict.insert("solving", timings.solving);
+    dict
+}
+
+/// Numbers each distinct name in order of first appearance, returning the
+/// number of each name given and the names in number order.
+fn index_names<'a>(names: impl Iterator<Item = &'a String>) -> (Vec<u32>, Vec<String>) {
+    let mut numbers: HashMap<&str, u32> = HashMap::new();
+    let mut distinct: Vec<String> = vec![];
+    let indices = names
+        .map(|name| {
+            *numbers.entry(name.as_str()).or_insert_with(|| {
+                distinct.push(name.clone());
+                distinct.len() as u32 - 1
+            })
+        })
+        .collect();
+    (indices, distinct)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(right: &str, kern: f32, status: Status) -> KernResult {
+        KernResult::new(
+            "a".to_string(),
+            right.to_string(),
+            "Regular".to_string(),
+            0,
+            Solution {
+                kern,
+                status,
+                iterations: 1,
+            },
+        )
+    }
+
+    #[test]
+    fn pairs_with_no_distance_stay_out_of_the_statistics() {
+        let results = vec![
+            result("b", -40.0, Status::Converged),
+            result("c", -20.0, Status::Converged),
+            result("d", 0.0, Status::NoDistance),
+            result("e", 10.0, Status::Converged),
+            result("f", 30.0, Status::Converged),
+        ];
+        let summary = Summary::new(&results);
+        assert_eq!(summary.count, 4);
+        assert_eq!(summary.median, Some(-5.0));
+        assert_eq!(summary.mean, Some(-5.0));
+        assert_eq!(summary.minimum, Some(-40.0));
+        assert_eq!(summary.maximum, Some(30.0));
+        assert_eq!(summary.histogram.iter().map(|(_, n)| n).sum::<usize>(), 4);
+        assert_eq!(summary.no_distance.len(), 1);
+        assert_eq!(summary.no_distance[0].right, "d");
+    }
+
+    #[test]
+    fn the_median_of_an_odd_count_is_the_middle_kern() {
+        let results = vec![
+            result("b", -40.0, Status::Converged),
+            result("c", -20.0, Status::Converged),
+            result("e", 10.0, Status::Converged),
+        ];
+        assert_eq!(Summary::new(&results).median, Some(-20.0));
+    }
+}
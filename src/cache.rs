@@ -0,0 +1,220 @@
+use crate::backend::Backend;
+use crate::outline::{normalize_direction, Outline};
+use crate::parameters::Parameters;
+use babelfont::Master;
+use kurbo::PathEl;
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::mem::size_of;
+use std::sync::{Arc, RwLock};
+
+/// Outlines converted from the font's master layers (through its
+/// `Backend`), keyed by glyph name, master ID and any shear and vertical
+/// scale applied. The cache can be shared freely between threads.
+#[derive(Debug, Default)]
+pub(crate) struct OutlineCache {
+    outlines: RwLock<HashMap<(String, String, (u64, u64)), Arc<Outline>>>,
+    /// Whether to rewind contours to a consistent direction
+    normalize_direction: bool,
+}
+
+impl OutlineCache {
+    pub fn new(normalize_direction: bool) -> Self {
+        OutlineCache {
+            normalize_direction,
+            ..Default::default()
+        }
+    }
+
+    /// The glyph's outline in the master, or `None` if the font has no
+    /// layer for it there. An outline which can't be converted is passed
+    /// over with a warning; use `try_get` to have the error instead.
+    pub fn get<B: Backend + ?Sized>(
+        &self,
+        font: &B,
+        master: &Master,
+        glyph_name: &str,
+    ) -> Option<Arc<Outline>> {
+        warn_on_error(self.try_get(font, master, glyph_name)?)
+    }
+
+    /// As `get`, but an outline which can't be converted (one with a
+    /// coordinate which is not a number, say) is an error naming the glyph
+    /// and master.
+    pub fn try_get<B: Backend + ?Sized>(
+        &self,
+        font: &B,
+        master: &Master,
+        glyph_name: &str,
+    ) -> Option<Result<Arc<Outline>, String>> {
+        let key = (
+            glyph_name.to_string(),
+            master.id.clone(),
+            (0.0f64.to_bits(), 1.0f64.to_bits()),
+        );
+        if let Some(outline) = self.outlines.read().unwrap().get(&key) {
+            return Some(Ok(outline.clone()));
+        }
+        let mut outline = match font.outline(glyph_name, &master.id)? {
+            Ok(outline) => outline,
+            Err(e) => return Some(Err(e)),
+        };
+        if self.normalize_direction {
+            outline.paths = normalize_direction(outline.paths);
+        }
+        let outline = Arc::new(outline);
+        self.outlines.write().unwrap().insert(key, outline.clone());
+        Some(Ok(outline))
+    }
+
+    /// The outline as the parameters measure it: sheared upright and
+    /// scaled vertically (see `Outline::transformed`), with any of the
+    /// parameters' marks attached (unless it is one of them), and with any
+    /// ink beyond the parameters' cutoffs cut away, which is cached
+    /// separately. Outlines which can't be converted are passed over with
+    /// a warning, as for `get`.
+    pub fn get_measured<B: Backend + ?Sized>(
+        &self,
+        font: &B,
+        master: &Master,
+        glyph_name: &str,
+        parameters: &Parameters,
+    ) -> Option<Arc<Outline>> {
+        warn_on_error(self.try_get_measured(font, master, glyph_name, parameters)?)
+    }
+
+    /// As `get_measured`, but an outline which can't be converted is an
+    /// error, as for `try_get`.
+    pub fn try_get_measured<B: Backend + ?Sized>(
+        &self,
+        font: &B,
+        master: &Master,
+        glyph_name: &str,
+        parameters: &Parameters,
+    ) -> Option<Result<Arc<Outline>, String>> {
+        let (shear, vertical_scale) = (parameters.shear, parameters.vertical_scale());
+        let marked =
+            !parameters.marks.is_empty() && !parameters.marks.iter().any(|m| m == glyph_name);
+        let (below, above) = (parameters.ignore_below, parameters.ignore_above);
+        let clipped = below.is_some() || above.is_some();
+        if shear == 0.0 && vertical_scale == 1.0 && !marked && !clipped {
+            return self.try_get(font, master, glyph_name);
+        }
+        // Marked and clipped outlines are kept apart from bare ones by the
+        // marks they carry and where they were cut.
+        let mut name = glyph_name.to_string();
+        if marked {
+            name.push_str(&format!("+{}", parameters.marks.join("+")));
+        }
+        if clipped {
+            name.push_str(&format!("@{:?}:{:?}", below, above));
+        }
+        let key = (
+            name,
+            master.id.clone(),
+            (shear.to_bits(), vertical_scale.to_bits()),
+        );
+        if let Some(outline) = self.outlines.read().unwrap().get(&key) {
+            return Some(Ok(outline.clone()));
+        }
+        let mut outline = match self.try_get(font, master, glyph_name)? {
+            Ok(outline) => outline.transformed(shear, vertical_scale),
+            Err(e) => return Some(Err(e)),
+        };
+        if marked {
+            let marks: Vec<Arc<Outline>> = parameters
+                .marks
+                .iter()
+                .filter_map(|mark| self.get(font, master, mark))
+                .map(|mark| Arc::new(mark.transformed(shear, vertical_scale)))
+                .collect();
+            outline = outline.with_marks(&marks, vertical_scale);
+        }
+        if clipped {
+            let scaled = |cutoff: Option<f32>| cutoff.map(|c| c as f64 * vertical_scale);
+            outline = outline.clipped(scaled(below), scaled(above));
+        }
+        let outline = Arc::new(outline);
+        self.outlines.write().unwrap().insert(key, outline.clone());
+        Some(Ok(outline))
+    }
+
+    /// Whether outlines are rewound to a consistent direction.
+    pub fn normalizes_direction(&self) -> bool {
+        self.normalize_direction
+    }
+
+    /// An empty cache which converts outlines as this one does.
+    pub fn fresh(&self) -> Self {
+        OutlineCache::new(self.normalize_direction)
+    }
+
+    /// Converts every glyph's outline in the given masters up front, in
+    /// parallel, rather than as pairs come to need them.
+    pub fn precompute<B: Backend + ?Sized>(&self, font: &B, masters: &[&Master]) {
+        font.glyph_names().par_iter().for_each(|name| {
+            for master in masters {
+                self.get(font, master, name);
+            }
+        });
+    }
+
+    /// Reports how many outlines are cached and roughly how much memory
+    /// their paths occupy.
+    pub fn stats(&self) -> HashMap<&'static str, usize> {
+        let outlines = self.outlines.read().unwrap();
+        let elements: usize = outlines
+            .values()
+            .flat_map(|o| o.paths.iter())
+            .map(|p| p.elements().len())
+            .sum();
+        let mut stats = HashMap::new();
+        stats.insert("outlines", outlines.len());
+        stats.insert("path_elements", elements);
+        stats.insert("bytes", elements * size_of::<PathEl>());
+        stats
+    }
+}
+
+/// The outline, or `None` with a warning if it couldn't be converted.
+fn warn_on_error(outline: Result<Arc<Outline>, String>) -> Option<Arc<Outline>> {
+    outline
+        .map_err(|e| log::warn!("Skipping outline: {}", e))
+        .ok()
+}
+
+/// The glyphs which measure differently in a new version of a font: those
+/// whose outline, advance width or anchors differ in any master (masters
+/// are matched by ID), including glyphs which were added or removed.
+/// Since outlines are decomposed, a glyph changes along with any of its
+/// components.
+pub(crate) fn changed_glyphs<B: Backend + ?Sized>(
+    old_font: &B,
+    old_outlines: &OutlineCache,
+    new_font: &B,
+    new_outlines: &OutlineCache,
+) -> BTreeSet<String> {
+    let names: BTreeSet<String> = old_font
+        .glyph_names()
+        .into_iter()
+        .chain(new_font.glyph_names())
+        .collect();
+    names
+        .into_par_iter()
+        .filter(|name| {
+            new_font.masters().iter().any(|new_master| {
+                let old = old_font
+                    .masters()
+                    .iter()
+                    .find(|m| m.id == new_master.id)
+                    .and_then(|m| old_outlines.get(old_font, m, name));
+                let new = new_outlines.get(new_font, new_master, name);
+                match (old, new) {
+                    (Some(old), Some(new)) => !old.same_as(&new),
+                    (None, None) => false,
+                    _ => true,
+                }
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,416 @@
+use crate::backend::Backend;
+use crate::batch::KernResult;
+use crate::outline::Outline;
+use crate::solver::Status;
+use babelfont::{Font, Master};
+use core::cmp::Ordering;
+use kurbo::Rect;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Expands results for kerning classes into results for every pair of
+/// glyphs they cover. Names in `classes` are treated as classes; anything
+/// else is a glyph. Where several results cover the same pair, the most
+/// specific wins, in the order glyph-glyph, glyph-class, class-glyph and
+/// then class-class, as in UFO kerning and the subtable order feaLib
+/// writes. Between results of the same kind, the first one given wins, as
+/// the first matching subtable would in OpenType.
+pub(crate) fn flatten(
+    results: &[KernResult],
+    classes: &HashMap<String, Vec<String>>,
+) -> Vec<KernResult> {
+    let members = |name: &String| -> Vec<String> {
+        classes
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| vec![name.clone()])
+    };
+    let mut positions: HashMap<(String, String, String, i32), usize> = HashMap::new();
+    let mut flat: Vec<(u8, KernResult)> = vec![];
+    for result in results {
+        let precedence = match (
+            classes.contains_key(&result.left),
+            classes.contains_key(&result.right),
+        ) {
+            (false, false) => 0,
+            (false, true) => 1,
+            (true, false) => 2,
+            (true, true) => 3,
+        };
+        for left in members(&result.left) {
+            for right in members(&result.right) {
+                let key = (
+                    left.clone(),
+                    right.clone(),
+                    result.master.clone(),
+                    result.height,
+                );
+                let expanded = KernResult {
+                    left,
+                    right,
+                    ..result.clone()
+                };
+                match positions.get(&key) {
+                    Some(&i) if flat[i].0 <= precedence => {}
+                    Some(&i) => flat[i] = (precedence, expanded),
+                    None => {
+                        positions.insert(key, flat.len());
+                        flat.push((precedence, expanded));
+                    }
+                }
+            }
+        }
+    }
+    flat.into_iter().map(|(_, r)| r).collect()
+}
+
+/// The members of a class worth measuring on one side of a pair, so that
+/// a class pair can be kerned for its worst case rather than for a single
+/// representative: the first member (its usual representative), and those
+/// with the widest ink, the ink reaching furthest towards the other glyph
+/// of the pair, the lowest descender, the highest ascender and the dot
+/// (see `Outline::split_dots`) reaching furthest towards the other glyph.
+/// `left` is whether the class is on the left of the pair. Members without
+/// ink are only measured if they come first.
+pub(crate) fn extreme_members(
+    members: &[(String, Arc<Outline>)],
+    left: bool,
+    dot_size: f64,
+) -> Vec<String> {
+    // How far a shape reaches towards the other glyph of the pair
+    let reach = |outline: &Outline, bounds: Rect| {
+        if left {
+            bounds.x1 - outline.width as f64
+        } else {
+            -bounds.x0
+        }
+    };
+    let inked: Vec<(&String, &Outline, Rect)> = members
+        .iter()
+        .filter_map(|(name, outline)| Some((name, outline.as_ref(), outline.bounds()?)))
+        .collect();
+    let most = |measure: &dyn Fn(&Outline, Rect) -> Option<f64>| {
+        inked
+            .iter()
+            .filter_map(|(name, outline, bounds)| Some((*name, measure(outline, *bounds)?)))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+            .map(|(name, _)| name.clone())
+    };
+    let extremes = [
+        members.first().map(|(name, _)| name.clone()),
+        most(&|_, b| Some(b.width())),
+        most(&|o, b| Some(reach(o, b))),
+        most(&|_, b| Some(-b.y0)),
+        most(&|_, b| Some(b.y1)),
+        most(&|o, _| {
+            let (dots, _) = o.split_dots(dot_size);
+            Some(reach(o, dots.bounds()?))
+        }),
+    ];
+    let mut chosen: Vec<String> = vec![];
+    for name in extremes.iter().flatten() {
+        if !chosen.contains(name) {
+            chosen.push(name.clone());
+        }
+    }
+    chosen
+}
+
+/// The classes a glyph belongs to when looking up a per-class target
+/// distance, most specific first: any classes it was given, then its
+/// kerning group for that side of the pair, then its category.
+pub(crate) fn target_classes<B: Backend + ?Sized>(
+    font: &B,
+    glyph_name: &str,
+    left: bool,
+    given: &HashMap<String, Vec<String>>,
+) -> Vec<String> {
+    let mut classes: Vec<String> = given
+        .iter()
+        .filter(|(_, members)| members.iter().any(|m| m == glyph_name))
+        .map(|(class, _)| class.clone())
+        .collect();
+    classes.sort();
+    let mut grouped: Vec<String> = font
+        .kern_groups(left)
+        .iter()
+        .filter(|(_, members)| members.iter().any(|m| m == glyph_name))
+        .map(|(group, _)| group.clone())
+        .collect();
+    grouped.sort();
+    classes.extend(grouped);
+    if let Some(category) = category(font, glyph_name) {
+        classes.push(category.to_string());
+    }
+    classes
+}
+
+/// The kern the master already has for a pair, whether it is kerned as
+/// glyphs or through the glyphs' kerning groups. As with `flatten`, a
+/// glyph-glyph kern beats glyph-group, which beats group-glyph, which beats
+/// group-group.
+pub(crate) fn existing_kern<B: Backend + ?Sized>(
+    font: &B,
+    master: &Master,
+    left_glyph: &str,
+    right_glyph: &str,
+) -> Option<f32> {
+    let kerning = font.kerning(&master.id)?;
+    if kerning.is_empty() {
+        return None;
+    }
+    let names = |glyph_name: &str, groups: &HashMap<String, Vec<String>>| -> Vec<String> {
+        let mut groups: Vec<String> = groups
+            .iter()
+            .filter(|(_, members)| members.iter().any(|m| m == glyph_name))
+            .flat_map(|(group, _)| vec![group.clone(), format!("@{:}", group)])
+            .collect();
+        groups.sort();
+        groups
+    };
+    let left_groups = names(left_glyph, font.kern_groups(true));
+    let right_groups = names(right_glyph, font.kern_groups(false));
+    let lefts = [vec![left_glyph.to_string()], left_groups];
+    let rights = [vec![right_glyph.to_string()], right_groups];
+    for (l, r) in &[(0, 0), (0, 1), (1, 0), (1, 1)] {
+        for left in lefts[*l].iter() {
+            for right in rights[*r].iter() {
+                if let Some(kern) = kerning.get(&(left.clone(), right.clone())) {
+                    return Some(*kern as f32);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Stores the results in the font's kerning, for the master named (or
+/// each result's own master), so that saving the font or handing it on
+/// keeps them. With `as_classes`, each glyph is kerned through its kerning
+/// group for that side of the pair, if it has one; otherwise pairs are
+/// kerned as glyphs, as exceptions to any group kerning, and only where
+/// the kern the font already has for them differs. Results which already
+/// name a group stay with it. Where several results land on the same
+/// kerning pair (several members of a group, or several heights), the
+/// loosest kern is kept, so that none of them collide. Results with
+/// nothing measured are skipped. Returns how many kerning pairs were set.
+pub(crate) fn apply_kerning(
+    font: &mut Font,
+    results: &[KernResult],
+    master_name: Option<&str>,
+    as_classes: bool,
+) -> usize {
+    let masters: Vec<String> = font.masters.iter().map(crate::master_name).collect();
+    // Sides of the kerning already there, to see how it names groups
+    let sides: HashSet<&str> = font
+        .masters
+        .iter()
+        .flat_map(|m| m.kerning.keys())
+        .flat_map(|(left, right)| vec![left.as_str(), right.as_str()])
+        .collect();
+    let mut kerns: HashMap<(usize, String, String), f32> = HashMap::new();
+    for result in results {
+        if result.status == Status::NoDistance
+            || master_name.map_or(false, |name| name != result.master)
+        {
+            continue;
+        }
+        let index = match masters.iter().position(|m| *m == result.master) {
+            Some(index) => index,
+            None => continue,
+        };
+        let side = |name: &String, groups: &HashMap<String, Vec<String>>| {
+            if groups.contains_key(name) {
+                return group_key(&sides, name);
+            }
+            if as_classes {
+                if let Some(group) = group_of(name, groups) {
+                    return group_key(&sides, &group);
+                }
+            }
+            name.clone()
+        };
+        let key = (
+            index,
+            side(&result.left, &font.first_kern_groups),
+            side(&result.right, &font.second_kern_groups),
+        );
+        let kern = kerns.entry(key).or_insert(result.kern);
+        *kern = kern.max(result.kern);
+    }
+
+    let mut changes: Vec<(usize, (String, String), i16)> = vec![];
+    for ((index, left, right), kern) in kerns {
+        let kern = kern.round() as i16;
+        let master = &font.masters[index];
+        let glyphs = font.glyphs.get(&left).is_some() && font.glyphs.get(&right).is_some();
+        let current = if glyphs {
+            existing_kern(&*font, master, &left, &right).map(|k| k as i16)
+        } else {
+            master.kerning.get(&(left.clone(), right.clone())).copied()
+        };
+        if current.unwrap_or(0) != kern {
+            changes.push((index, (left, right), kern));
+        }
+    }
+    let count = changes.len();
+    for (index, pair, kern) in changes {
+        font.masters[index].kerning.insert(pair, kern);
+    }
+    count
+}
+
+/// The first (by name) of the groups a glyph belongs to.
+fn group_of(glyph_name: &str, groups: &HashMap<String, Vec<String>>) -> Option<String> {
+    groups
+        .iter()
+        .filter(|(_, members)| members.iter().any(|m| m == glyph_name))
+        .map(|(group, _)| group)
+        .min()
+        .cloned()
+}
+
+/// How the font's kerning names a group, given the sides of the pairs it
+/// kerns: bare if its kerning already does so, and otherwise with an `@`,
+/// as `existing_kern` accepts either.
+fn group_key(sides: &HashSet<&str>, group: &str) -> String {
+    if sides.contains(group) {
+        group.to_string()
+    } else {
+        format!("@{:}", group)
+    }
+}
+
+/// The band of the pair a glyph wants measured in, with `reference_bands`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Band {
+    /// Figures, measured within the figures' bounds
+    Figure,
+    /// Punctuation sitting on the baseline, measured up to the x-height
+    Low,
+    /// Quotes and other raised marks, measured above the x-height
+    High,
+}
+
+/// Which band a glyph wants its pairs measured in, from the first
+/// character it encodes: quotes (but not low quotes) and other raised
+/// marks are high, periods, commas, colons, low quotes and dashes are
+/// low, and figures keep to the figures' bounds. Other glyphs have no band.
+pub(crate) fn reference_band<B: Backend + ?Sized>(font: &B, glyph_name: &str) -> Option<Band> {
+    let c = font
+        .codepoints(glyph_name)?
+        .first()
+        .and_then(|&cp| std::char::from_u32(cp as u32))?;
+    match c {
+        c if "\"'*°‘’‛“”‟′″".contains(c) => Some(Band::High),
+        c if ".,:;…‚„-‐‑‒–—_".contains(c) => Some(Band::Low),
+        c if c.is_numeric() => Some(Band::Figure),
+        _ => None,
+    }
+}
+
+/// A broad category for a glyph, from its glyph category or the first
+/// character it encodes.
+fn category<B: Backend + ?Sized>(font: &B, glyph_name: &str) -> Option<&'static str> {
+    let codepoints = font.codepoints(glyph_name)?;
+    if font.is_mark(glyph_name) {
+        return Some("marks");
+    }
+    let c = codepoints
+        .first()
+        .and_then(|&cp| std::char::from_u32(cp as u32))?;
+    Some(match c {
+        '\u{0300}'..='\u{036F}' => "marks",
+        c if c.is_alphabetic() => "letters",
+        c if c.is_numeric() => "figures",
+        c if c.is_whitespace() => "spaces",
+        c if is_punctuation(c) => "punctuation",
+        _ => "symbols",
+    })
+}
+
+/// Whether a character is punctuation rather than some other symbol: the
+/// ASCII, Latin-1, Arabic, general and CJK punctuation marks.
+fn is_punctuation(c: char) -> bool {
+    "!\"#%&'()*,-./:;?@[\\]_{}¡§«¶·»¿،؛؟۔".contains(c)
+        || ('\u{2010}'..='\u{2027}').contains(&c)
+        || ('\u{2030}'..='\u{205E}').contains(&c)
+        || ('\u{3001}'..='\u{3011}').contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Solution;
+
+    fn result(left: &str, right: &str, master: &str, kern: f32) -> KernResult {
+        KernResult::new(
+            left.to_string(),
+            right.to_string(),
+            master.to_string(),
+            0,
+            Solution {
+                kern,
+                status: Status::Converged,
+                iterations: 1,
+            },
+        )
+    }
+
+    fn kerns(results: &[KernResult]) -> HashMap<(&str, &str, &str), f32> {
+        results
+            .iter()
+            .map(|r| {
+                (
+                    (r.left.as_str(), r.right.as_str(), r.master.as_str()),
+                    r.kern,
+                )
+            })
+            .collect()
+    }
+
+    fn classes() -> HashMap<String, Vec<String>> {
+        vec![
+            (
+                "@A".to_string(),
+                vec!["A".to_string(), "Aacute".to_string()],
+            ),
+            ("@V".to_string(), vec!["V".to_string(), "W".to_string()]),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn flatten_prefers_the_more_specific_result() {
+        // Given least specific first, so that order alone can't decide.
+        let results = vec![
+            result("@A", "@V", "Regular", -10.0),
+            result("@A", "W", "Regular", -20.0),
+            result("A", "@V", "Regular", -30.0),
+            result("A", "V", "Regular", -40.0),
+        ];
+        let flat = flatten(&results, &classes());
+        let kerns = kerns(&flat);
+        assert_eq!(flat.len(), 4);
+        assert_eq!(kerns[&("A", "V", "Regular")], -40.0);
+        assert_eq!(kerns[&("A", "W", "Regular")], -30.0);
+        assert_eq!(kerns[&("Aacute", "W", "Regular")], -20.0);
+        assert_eq!(kerns[&("Aacute", "V", "Regular")], -10.0);
+    }
+
+    #[test]
+    fn flatten_keeps_the_first_of_the_same_kind() {
+        let results = vec![
+            result("A", "@V", "Regular", -30.0),
+            result("A", "@V", "Regular", -50.0),
+            result("A", "V", "Bold", -60.0),
+        ];
+        let flat = flatten(&results, &classes());
+        let kerns = kerns(&flat);
+        assert_eq!(flat.len(), 3);
+        assert_eq!(kerns[&("A", "V", "Regular")], -30.0);
+        assert_eq!(kerns[&("A", "W", "Regular")], -30.0);
+        assert_eq!(kerns[&("A", "V", "Bold")], -60.0);
+    }
+}
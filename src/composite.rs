@@ -0,0 +1,152 @@
+use crate::batch::KernResult;
+use crate::cache::OutlineCache;
+use crate::parameters::Parameters;
+use crate::solver::{measure_at, Solution, Status};
+use babelfont::{Font, Master};
+use std::collections::{HashMap, HashSet};
+
+/// A glyph built on top of another glyph, its base, which is its first
+/// component.
+struct Composite {
+    name: String,
+    /// How far the base is moved right within the composite
+    offset: f32,
+    /// How much wider the composite is than its base
+    extra_width: f32,
+    /// Whether the base is only moved horizontally, so that the base's kern
+    /// can be carried over
+    translated: bool,
+}
+
+/// The composites in a master of the source font, keyed by base name.
+fn composites(source: &Font, master: &Master) -> HashMap<String, Vec<Composite>> {
+    let mut composites: HashMap<String, Vec<Composite>> = HashMap::new();
+    for glyph in source.glyphs.0.iter() {
+        let layer = match source.master_layer_for(&glyph.name, master) {
+            Some(layer) => layer,
+            None => continue,
+        };
+        let base = match layer.components().next() {
+            Some(base) => base,
+            None => continue,
+        };
+        let base_layer = match source.master_layer_for(&base.reference, master) {
+            Some(layer) => layer,
+            None => continue,
+        };
+        let [a, b, c, d, e, f] = base.transform.as_coeffs();
+        composites
+            .entry(base.reference.clone())
+            .or_default()
+            .push(Composite {
+                name: glyph.name.clone(),
+                offset: e as f32,
+                extra_width: (layer.width - base_layer.width) as f32,
+                translated: a == 1.0 && b == 0.0 && c == 0.0 && d == 1.0 && f == 0.0,
+            });
+    }
+    composites
+}
+
+/// Carries the kerns of base glyph pairs over to the pairs of composites
+/// built from them, adjusting for where the base sits in each composite.
+/// Each propagated pair is measured once at its new kern; if the marks
+/// bring the outlines closer than the target (or the base was transformed
+/// other than by moving it sideways, or its own kern was not converged) it
+/// is flagged as needing remeasurement rather than trusted. Pairs already
+/// in the results are not overwritten.
+pub(crate) fn propagate(
+    font: &Font,
+    source: &Font,
+    outlines: &OutlineCache,
+    results: &[KernResult],
+    parameters: &HashMap<i32, Parameters>,
+    include: impl Fn(&str, &str) -> bool,
+) -> Vec<KernResult> {
+    let known: HashSet<(&str, &str, &str, i32)> = results
+        .iter()
+        .map(|r| {
+            (
+                r.left.as_str(),
+                r.right.as_str(),
+                r.master.as_str(),
+                r.height,
+            )
+        })
+        .collect();
+    let mut by_master: HashMap<&str, HashMap<String, Vec<Composite>>> = HashMap::new();
+    let mut propagated = vec![];
+
+    for result in results {
+        let (master, parameters) =
+            match (font.master(&result.master), parameters.get(&result.height)) {
+                (Some(m), Some(p)) => (m, p.for_master(font, m)),
+                _ => continue,
+            };
+        let composites = by_master
+            .entry(result.master.as_str())
+            .or_insert_with(|| composites(source, master));
+        // (glyph, kern adjustment, carried over unchanged)
+        let side = |name: &str, left: bool| -> Vec<(String, f32, bool)> {
+            let mut options = vec![(name.to_string(), 0.0, true)];
+            for c in composites.get(name).into_iter().flatten() {
+                let adjustment = if left {
+                    c.offset - c.extra_width
+                } else {
+                    -c.offset
+                };
+                options.push((c.name.clone(), adjustment, c.translated));
+            }
+            options
+        };
+        let lefts = side(&result.left, true);
+        let rights = side(&result.right, false);
+
+        for (i, (left, left_adjustment, left_translated)) in lefts.iter().enumerate() {
+            for (j, (right, right_adjustment, right_translated)) in rights.iter().enumerate() {
+                if (i == 0 && j == 0)
+                    || known.contains(&(
+                        left.as_str(),
+                        right.as_str(),
+                        result.master.as_str(),
+                        result.height,
+                    ))
+                    || !include(left, right)
+                {
+                    continue;
+                }
+                let parameters = parameters.for_pair(font, left, right);
+                let (left_outline, right_outline) = match (
+                    outlines.get_measured(font, master, left, &parameters),
+                    outlines.get_measured(font, master, right, &parameters),
+                ) {
+                    (Some(l), Some(r)) => (l, r),
+                    _ => continue,
+                };
+                let kern = result.kern * parameters.scale + left_adjustment + right_adjustment;
+                let trusted = result.status.converged()
+                    && *left_translated
+                    && *right_translated
+                    && measure_at(&left_outline, &right_outline, &parameters, kern)
+                        .map_or(true, |m| m.shortfall() <= parameters.kern_tolerance as f64);
+                let solution = Solution {
+                    kern,
+                    status: if trusted {
+                        Status::Propagated
+                    } else {
+                        Status::NeedsRemeasure
+                    },
+                    iterations: 0,
+                };
+                propagated.push(KernResult::new(
+                    left.clone(),
+                    right.clone(),
+                    result.master.clone(),
+                    result.height,
+                    parameters.output(solution),
+                ));
+            }
+        }
+    }
+    propagated
+}
@@ -0,0 +1,260 @@
+use crate::bands::BandProfile;
+use crate::parameters::Parameters;
+use core::cmp::Ordering;
+use kurbo::{Affine, BezPath, ParamCurve, PathSeg, Point, Rect, Vec2};
+
+/// The closest approach between two positioned outlines, and the target
+/// distance which applies at that point.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Measurement {
+    pub distance: f64,
+    pub target: f64,
+    /// How much falling short of the target counts where it was measured
+    pub weight: f64,
+}
+
+impl Measurement {
+    /// How much further apart the outlines need to move to meet the
+    /// target, weighted.
+    pub fn shortfall(&self) -> f64 {
+        (self.target - self.distance) * self.weight
+    }
+}
+
+/// Where the closest approach between two outlines was found: the
+/// contours (by index) and segments involved, in the positioned outlines.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Approach {
+    pub measurement: Measurement,
+    pub left_contour: usize,
+    pub right_contour: usize,
+    pub left_segment: PathSeg,
+    pub right_segment: PathSeg,
+}
+
+pub(crate) fn path_distance(
+    left_paths: &[BezPath],
+    right_paths: &[BezPath],
+    bands: Option<(&[BandProfile], &[BandProfile])>,
+    x_offset: f32,
+    y_offset: f32,
+    parameters: &Parameters,
+) -> Option<Measurement> {
+    closest_approach(
+        left_paths,
+        right_paths,
+        bands,
+        x_offset,
+        y_offset,
+        parameters,
+    )
+    .map(|a| a.measurement)
+}
+
+/// The closest approach between the left contours, raised by `y_offset`,
+/// and the right ones, moved right by `x_offset`. Given the contours' band
+/// profiles, pairs of contours are measured in order of how close their
+/// profiles say they could come, and the rest are skipped once none of
+/// them could fall further short than the worst found.
+pub(crate) fn closest_approach(
+    left_paths: &[BezPath],
+    right_paths: &[BezPath],
+    bands: Option<(&[BandProfile], &[BandProfile])>,
+    x_offset: f32,
+    y_offset: f32,
+    parameters: &Parameters,
+) -> Option<Approach> {
+    let offset1 = Affine::translate(Vec2 {
+        x: 0.0,
+        y: y_offset.into(),
+    });
+    let offset2 = Affine::translate(Vec2 {
+        x: x_offset as f64,
+        y: 0.0,
+    });
+    let mut pairs: Vec<(f64, usize, usize)> = vec![];
+    for i in 0..left_paths.len() {
+        for j in 0..right_paths.len() {
+            let bound = match bands {
+                Some((left_bands, right_bands)) => {
+                    match left_bands[i].lower_bound(
+                        &right_bands[j],
+                        x_offset as f64,
+                        y_offset as f64,
+                    ) {
+                        Some(bound) => bound,
+                        None => continue,
+                    }
+                }
+                None => 0.0,
+            };
+            pairs.push((bound, i, j));
+        }
+    }
+    pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+    let max_target = parameters.max_target() as f64;
+    let (min_weight, max_weight) = parameters.weight_range();
+    let mut critical: Option<Approach> = None;
+    for (lower_bound, i, j) in pairs {
+        let unweighted = max_target - lower_bound;
+        let bound = unweighted
+            * if unweighted > 0.0 {
+                max_weight
+            } else {
+                min_weight
+            };
+        if critical.map_or(false, |c| bound <= c.measurement.shortfall()) {
+            break;
+        }
+        let moved_p1 = offset1 * &left_paths[i];
+        let moved_p2 = offset2 * &right_paths[j];
+        let (m, left_segment, right_segment) =
+            match min_distance_bezpath(&moved_p1, &moved_p2, parameters) {
+                Some(closest) => closest,
+                None => continue,
+            };
+        log::debug!("  d={:?}", m);
+        if critical.map_or(true, |c| m.shortfall() > c.measurement.shortfall()) {
+            log::debug!("    (new record)");
+            critical = Some(Approach {
+                measurement: m,
+                left_contour: i,
+                right_contour: j,
+                left_segment,
+                right_segment,
+            })
+        } else {
+            log::debug!("    (ignored)");
+        }
+    }
+    critical
+}
+
+/// The measurement between two contours which falls furthest short of
+/// its target, and the segments it was between, or `None` if either
+/// contour has no segments.
+fn min_distance_bezpath(
+    one: &BezPath,
+    other: &BezPath,
+    parameters: &Parameters,
+) -> Option<(Measurement, PathSeg, PathSeg)> {
+    let epsilon = parameters.epsilon as f64;
+    // No pair can fall further short than its bounding boxes allow for the
+    // largest target in play.
+    let max_target = parameters.max_target() as f64;
+    let others: Vec<(PathSeg, Rect)> = other.segments().map(|s| (s, control_box(s))).collect();
+    let mut candidates: Vec<(f64, PathSeg, PathSeg)> = vec![];
+    for s1 in one.segments() {
+        let box1 = control_box(s1);
+        for (s2, box2) in others.iter() {
+            candidates.push((rect_distance(box1, *box2), s1, *s2));
+        }
+    }
+    candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+    let (min_weight, max_weight) = parameters.weight_range();
+    let mut best: Option<(Measurement, PathSeg, PathSeg)> = None;
+    for (lower_bound, s1, s2) in candidates {
+        let unweighted = max_target - lower_bound;
+        let bound = unweighted
+            * if unweighted > 0.0 {
+                max_weight
+            } else {
+                min_weight
+            };
+        if best.map_or(false, |b| bound <= b.0.shortfall()) {
+            break;
+        }
+        let (distance, p1, p2) = segment_distance(s1, s2, epsilon);
+        let m = match parameters.measurement(distance, (p1.y + p2.y) / 2.0) {
+            Some(m) => m,
+            None => continue,
+        };
+        if best.map_or(true, |b| m.shortfall() > b.0.shortfall()) {
+            best = Some((m, s1, s2));
+        }
+    }
+    if let Some((_, s1, s2)) = best {
+        log::debug!("Best pair was {:?}, {:?}", s1, s2);
+    }
+    best
+}
+
+/// The bounding box of a segment's control points, which always contains
+/// the segment.
+fn control_box(segment: PathSeg) -> Rect {
+    let points = match segment {
+        PathSeg::Line(l) => vec![l.p0, l.p1],
+        PathSeg::Quad(q) => vec![q.p0, q.p1, q.p2],
+        PathSeg::Cubic(c) => vec![c.p0, c.p1, c.p2, c.p3],
+    };
+    points
+        .iter()
+        .skip(1)
+        .fold(Rect::from_points(points[0], points[0]), |r, p| {
+            r.union_pt(*p)
+        })
+}
+
+/// The distance between the nearest points of two rectangles, zero if
+/// they overlap.
+fn rect_distance(a: Rect, b: Rect) -> f64 {
+    let dx = (a.x0 - b.x1).max(b.x0 - a.x1).max(0.0);
+    let dy = (a.y0 - b.y1).max(b.y0 - a.y1).max(0.0);
+    dx.hypot(dy)
+}
+
+/// The distance between two segments, to within `epsilon`, along with a
+/// point on each which are that far apart. The result is never more than
+/// `epsilon` above the true distance. Pieces of the segments are split in
+/// half until either their control boxes show they can't come closer than
+/// the best distance found so far, or they are small enough that the two
+/// agree.
+pub(crate) fn segment_distance(one: PathSeg, other: PathSeg, epsilon: f64) -> (f64, Point, Point) {
+    let mut best = (
+        one.start().distance(other.start()),
+        one.start(),
+        other.start(),
+    );
+    let consider = |p: Point, q: Point, best: &mut (f64, Point, Point)| {
+        let distance = p.distance(q);
+        if distance < best.0 {
+            *best = (distance, p, q);
+        }
+    };
+    for t in [0.0, 0.5, 1.0].iter() {
+        for u in [0.0, 0.5, 1.0].iter() {
+            consider(one.eval(*t), other.eval(*u), &mut best);
+        }
+    }
+    let mut pieces = vec![(one, other)];
+    while let Some((a, b)) = pieces.pop() {
+        // Nothing can be closer than touching.
+        if best.0 <= epsilon {
+            break;
+        }
+        let (box_a, box_b) = (control_box(a), control_box(b));
+        if rect_distance(box_a, box_b) >= best.0 - epsilon {
+            continue;
+        }
+        consider(a.eval(0.5), b.eval(0.5), &mut best);
+        let size_a = box_a.width().hypot(box_a.height());
+        let size_b = box_b.width().hypot(box_b.height());
+        // The gap between the boxes is within the two pieces' sizes of the
+        // distance between any of their points.
+        if size_a + size_b <= epsilon {
+            continue;
+        }
+        if size_a >= size_b {
+            let (a1, a2) = a.subdivide();
+            pieces.push((a1, b));
+            pieces.push((a2, b));
+        } else {
+            let (b1, b2) = b.subdivide();
+            pieces.push((a, b1));
+            pieces.push((a, b2));
+        }
+    }
+    best
+}
@@ -0,0 +1,124 @@
+use crate::distance::{closest_approach, Approach};
+use crate::outline::Outline;
+use crate::parameters::Parameters;
+use crate::solver::{minimum_kern, solve_traced, Solution, Step};
+use core::cmp::Ordering;
+use kurbo::{Affine, PathSeg, Point, Vec2};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+/// Everything that went into the kern for one pair.
+pub(crate) struct Explanation {
+    solution: Solution,
+    /// Font units per unit reported
+    scale: f32,
+    trace: Vec<Step>,
+    minimum_kern: f32,
+    /// The vertical offset of the left glyph at the closest approach
+    offset: f64,
+    approach: Option<Approach>,
+    left_contour: Option<String>,
+    right_contour: Option<String>,
+}
+
+impl Explanation {
+    /// Solves the pair, then finds where its outlines come closest at the
+    /// chosen kern. The closest segments are always found analytically,
+    /// whichever engine the solver used.
+    pub fn new(left: &Outline, right: &Outline, parameters: &Parameters) -> Self {
+        let (solution, trace) = solve_traced(left, right, parameters, 0.0);
+        let x_offset = solution.kern + left.width;
+        let closest = closest_at(left, right, parameters, solution.kern);
+        let offset = closest.map_or(0.0, |(y, _)| y);
+        let approach = closest.map(|(_, a)| a);
+        let left_transform = Affine::translate(Vec2::new(0.0, offset));
+        let right_transform = Affine::translate(Vec2::new(x_offset as f64, 0.0));
+        Explanation {
+            solution,
+            scale: parameters.scale,
+            trace,
+            minimum_kern: minimum_kern(left, right, parameters),
+            offset,
+            approach,
+            left_contour: approach.map(|a| (left_transform * &left.paths[a.left_contour]).to_svg()),
+            right_contour: approach
+                .map(|a| (right_transform * &right.paths[a.right_contour]).to_svg()),
+        }
+    }
+
+    pub fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let dict = PyDict::new(py);
+        let scale = self.scale as f64;
+        dict.set_item("kern", self.solution.kern / self.scale)?;
+        dict.set_item("status", self.solution.status.as_str())?;
+        dict.set_item("iterations", self.solution.iterations)?;
+        dict.set_item("minimum_kern", self.minimum_kern / self.scale)?;
+        dict.set_item(
+            "trace",
+            self.trace
+                .iter()
+                .map(|s| {
+                    (
+                        s.kern / self.scale,
+                        s.measurement.distance / scale,
+                        s.measurement.target / scale,
+                    )
+                })
+                .collect::<Vec<(f32, f64, f64)>>(),
+        )?;
+        dict.set_item("offset", self.offset)?;
+        if let Some(a) = self.approach {
+            dict.set_item("distance", a.measurement.distance / scale)?;
+            dict.set_item("target", a.measurement.target / scale)?;
+            dict.set_item("left_contour_index", a.left_contour)?;
+            dict.set_item("right_contour_index", a.right_contour)?;
+            dict.set_item("left_segment", segment_points(a.left_segment))?;
+            dict.set_item("right_segment", segment_points(a.right_segment))?;
+        }
+        dict.set_item("left_contour", &self.left_contour)?;
+        dict.set_item("right_contour", &self.right_contour)?;
+        Ok(dict)
+    }
+}
+
+/// Where the outlines come closest at a kern (in font units), over all the
+/// heights measured, along with the left glyph's vertical offset there.
+/// This is found analytically, whichever engine is in use.
+pub(crate) fn closest_at(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    kern: f32,
+) -> Option<(f64, Approach)> {
+    let x_offset = kern + left.width;
+    parameters
+        .heights(parameters.left_offset(left))
+        .into_iter()
+        .filter_map(|y| {
+            closest_approach(
+                &left.right_side,
+                &right.left_side,
+                Some((&left.right_bands, &right.left_bands)),
+                x_offset,
+                y as f32,
+                parameters,
+            )
+            .map(|a| (y, a))
+        })
+        .max_by(|a, b| {
+            a.1.measurement
+                .shortfall()
+                .partial_cmp(&b.1.measurement.shortfall())
+                .unwrap_or(Ordering::Equal)
+        })
+}
+
+/// The end and control points of a segment, as `(x, y)` tuples.
+fn segment_points(segment: PathSeg) -> Vec<(f64, f64)> {
+    let points: Vec<Point> = match segment {
+        PathSeg::Line(l) => vec![l.p0, l.p1],
+        PathSeg::Quad(q) => vec![q.p0, q.p1, q.p2],
+        PathSeg::Cubic(c) => vec![c.p0, c.p1, c.p2, c.p3],
+    };
+    points.into_iter().map(|p| (p.x, p.y)).collect()
+}
@@ -0,0 +1,179 @@
+use crate::batch::KernResult;
+use std::collections::{BTreeMap, HashMap};
+
+/// Writes a chained contextual positioning lookup for Nastaliq-style
+/// stacks. Each result's height is looked up in `depths` (stack depth to
+/// height) and the kern is applied only when the left glyph is followed by
+/// that many glyphs from `stack_glyphs`; results at other heights are
+/// skipped. Only the results for `master` are written, if given, since
+/// results for several masters would give conflicting rules.
+///
+/// Rules are written in logical order, so the right glyph of the pair comes
+/// first, and the value goes on the left glyph, whose advance controls the
+/// gap in right-to-left text.
+pub(crate) fn contextual_fea(
+    results: &[KernResult],
+    master: Option<&str>,
+    depths: &HashMap<usize, i32>,
+    stack_glyphs: &[String],
+    name: &str,
+) -> String {
+    let depth_of: HashMap<i32, usize> = depths.iter().map(|(d, h)| (*h, *d)).collect();
+    let mut rules: Vec<(usize, &KernResult)> = results
+        .iter()
+        .filter(|r| master.map_or(true, |m| r.master == m))
+        .filter_map(|r| depth_of.get(&r.height).map(|d| (*d, r)))
+        .collect();
+    // Rules with longer contexts have to be tried first.
+    rules.sort_by(|a, b| b.0.cmp(&a.0));
+
+    let class = format!("@{}", name);
+    let mut fea = format!(
+        "{} = [{}];\n\nlookup {} {{\n    lookupflag IgnoreMarks;\n",
+        class,
+        stack_glyphs.join(" "),
+        name
+    );
+    for (depth, r) in rules {
+        let mut rule = format!("    pos {} {}' {}", r.right, r.left, r.kern.round() as i32);
+        for _ in 0..depth {
+            rule.push(' ');
+            rule.push_str(&class);
+        }
+        rule.push_str(";\n");
+        fea.push_str(&rule);
+    }
+    fea.push_str(&format!("}} {};\n", name));
+    fea
+}
+
+/// Writes a pair positioning lookup which ships the rises suggested for
+/// pairs kerning alone couldn't separate: the right glyph of each such
+/// pair is raised by its rise alongside the kern, and other pairs are
+/// kerned as usual. Pairs with neither are left out. Only the results for
+/// `master` are written, if given.
+///
+/// With `right_to_left`, rules are written in logical order, so the right
+/// glyph comes first and the kern goes on the left glyph's advance, as in
+/// `contextual_fea`.
+pub(crate) fn rise_fea(
+    results: &[KernResult],
+    master: Option<&str>,
+    name: &str,
+    right_to_left: bool,
+) -> String {
+    let mut fea = format!("lookup {} {{\n", name);
+    for r in results
+        .iter()
+        .filter(|r| master.map_or(true, |m| r.master == m))
+    {
+        let kern = r.kern.round() as i32;
+        let rise = r.rise.map_or(0, |rise| rise.round() as i32);
+        if rise == 0 && kern == 0 {
+            continue;
+        }
+        let kerned = format!("<0 0 {} 0>", kern);
+        let raised = format!("<0 {} 0 0>", rise);
+        // Every rule carries a value for both glyphs, so that raised and
+        // plain pairs can share the lookup.
+        let rule = if right_to_left {
+            format!("pos {} {} {} {}", r.right, raised, r.left, kerned)
+        } else {
+            format!("pos {} {} {} {}", r.left, kerned, r.right, raised)
+        };
+        fea.push_str(&format!("    {};\n", rule));
+    }
+    fea.push_str(&format!("}} {};\n", name));
+    fea
+}
+
+/// Writes `lfbd` and `rtbd` features from each glyph's optical bounds
+/// (see `KernDeterminer.optical_bounds`): at the start of a line a glyph
+/// is pulled left by its left bound, and at the end its advance is cut by
+/// its right bound, so its optical edges meet the margins. Glyphs with no
+/// adjustment on a side are left out of that feature.
+pub(crate) fn optical_bounds_fea(bounds: &BTreeMap<String, (f32, f32)>) -> String {
+    let mut lfbd = String::from("feature lfbd {\n");
+    let mut rtbd = String::from("feature rtbd {\n");
+    for (glyph, (left, right)) in bounds {
+        let (left, right) = (left.round() as i32, right.round() as i32);
+        if left != 0 {
+            lfbd.push_str(&format!("    pos {} <{} 0 {} 0>;\n", glyph, -left, -left));
+        }
+        if right != 0 {
+            rtbd.push_str(&format!("    pos {} <0 0 {} 0>;\n", glyph, -right));
+        }
+    }
+    lfbd.push_str("} lfbd;\n");
+    rtbd.push_str("} rtbd;\n");
+    format!("{}\n{}", lfbd, rtbd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::{Solution, Status};
+
+    fn result(left: &str, right: &str, master: &str, height: i32, kern: f32) -> KernResult {
+        KernResult::new(
+            left.to_string(),
+            right.to_string(),
+            master.to_string(),
+            height,
+            Solution {
+                kern,
+                status: Status::Converged,
+                iterations: 1,
+            },
+        )
+    }
+
+    #[test]
+    fn contextual_rules_put_longer_contexts_first() {
+        let results = vec![
+            result("beh", "reh", "Regular", 100, -20.4),
+            result("beh", "noon", "Regular", 200, -40.0),
+            result("beh", "seen", "Regular", 0, -60.0),
+            result("beh", "reh", "Bold", 100, -30.0),
+        ];
+        let depths: HashMap<usize, i32> = vec![(1, 100), (2, 200)].into_iter().collect();
+        let stack = vec!["beh.init".to_string(), "jeem.init".to_string()];
+        assert_eq!(
+            contextual_fea(&results, Some("Regular"), &depths, &stack, "stack"),
+            "@stack = [beh.init jeem.init];\n\nlookup stack {\n    lookupflag IgnoreMarks;\n    pos noon beh' -40 @stack @stack;\n    pos reh beh' -20 @stack;\n} stack;\n"
+        );
+    }
+
+    #[test]
+    fn rise_rules_carry_values_for_both_glyphs() {
+        let mut raised = result("f", "quoteright", "Regular", 0, -30.0);
+        raised.rise = Some(20.4);
+        let results = vec![
+            raised,
+            result("A", "V", "Regular", 0, -50.0),
+            result("H", "H", "Regular", 0, 0.2),
+        ];
+        assert_eq!(
+            rise_fea(&results, None, "rise", false),
+            "lookup rise {\n    pos f <0 0 -30 0> quoteright <0 20 0 0>;\n    pos A <0 0 -50 0> V <0 0 0 0>;\n} rise;\n"
+        );
+        assert_eq!(
+            rise_fea(&results[..1], None, "rise", true),
+            "lookup rise {\n    pos quoteright <0 20 0 0> f <0 0 -30 0>;\n} rise;\n"
+        );
+    }
+
+    #[test]
+    fn optical_bounds_leave_out_zero_adjustments() {
+        let bounds: BTreeMap<String, (f32, f32)> = vec![
+            ("A".to_string(), (10.2, 0.0)),
+            ("T".to_string(), (0.0, -8.0)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            optical_bounds_fea(&bounds),
+            "feature lfbd {\n    pos A <-10 0 -10 0>;\n} lfbd;\n\nfeature rtbd {\n    pos T <0 0 8 0>;\n} rtbd;\n"
+        );
+    }
+}
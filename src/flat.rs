@@ -0,0 +1,204 @@
+use crate::distance::Measurement;
+use crate::parameters::Parameters;
+use core::cmp::Ordering;
+use kurbo::{Affine, BezPath, PathEl, Point, Vec2};
+use wide::f64x4;
+
+const LANES: usize = 4;
+/// Coordinate used to pad out the last batch of edges, far enough away
+/// never to be the closest.
+const FAR: f64 = 1e10;
+
+/// Flattens a path into straight edges, closing every subpath.
+pub(crate) fn flatten_edges(path: &BezPath, tolerance: f64) -> Vec<(Point, Point)> {
+    let mut edges = vec![];
+    let mut start: Option<Point> = None;
+    let mut last: Option<Point> = None;
+    path.flatten(tolerance, |el| match el {
+        PathEl::MoveTo(p) => {
+            if let (Some(s), Some(l)) = (start, last) {
+                if l != s {
+                    edges.push((l, s));
+                }
+            }
+            start = Some(p);
+            last = Some(p);
+        }
+        PathEl::LineTo(p) => {
+            if let Some(l) = last {
+                edges.push((l, p));
+            }
+            last = Some(p);
+        }
+        PathEl::ClosePath => {
+            if let (Some(s), Some(l)) = (start, last) {
+                if l != s {
+                    edges.push((l, s));
+                }
+            }
+            last = start;
+        }
+        _ => {}
+    });
+    if let (Some(s), Some(l)) = (start, last) {
+        if l != s {
+            edges.push((l, s));
+        }
+    }
+    edges
+}
+
+/// The horizontal spans inside the edges along the line at height `y`,
+/// using the non-zero winding rule.
+pub(crate) fn spans(edges: &[(Point, Point)], y: f64) -> Vec<(f64, f64)> {
+    let mut crossings: Vec<(f64, i32)> = edges
+        .iter()
+        .filter(|(a, b)| (a.y <= y) != (b.y <= y))
+        .map(|(a, b)| {
+            let x = a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x);
+            (x, if b.y > a.y { 1 } else { -1 })
+        })
+        .collect();
+    crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+    let mut spans = vec![];
+    let mut winding = 0;
+    for span in crossings.windows(2) {
+        winding += span[0].1;
+        if winding != 0 {
+            spans.push((span[0].0, span[1].0));
+        }
+    }
+    spans
+}
+
+/// Edges laid out a batch of lanes at a time for the vectorised kernels.
+struct EdgeBatches {
+    x0: Vec<f64x4>,
+    y0: Vec<f64x4>,
+    x1: Vec<f64x4>,
+    y1: Vec<f64x4>,
+}
+
+impl EdgeBatches {
+    fn new(edges: &[(Point, Point)]) -> Self {
+        let mut batches = EdgeBatches {
+            x0: vec![],
+            y0: vec![],
+            x1: vec![],
+            y1: vec![],
+        };
+        for chunk in edges.chunks(LANES) {
+            let mut x0 = [FAR; LANES];
+            let mut y0 = [FAR; LANES];
+            let mut x1 = [FAR; LANES];
+            let mut y1 = [FAR; LANES];
+            for (i, (a, b)) in chunk.iter().enumerate() {
+                x0[i] = a.x;
+                y0[i] = a.y;
+                x1[i] = b.x;
+                y1[i] = b.y;
+            }
+            batches.x0.push(f64x4::from(x0));
+            batches.y0.push(f64x4::from(y0));
+            batches.x1.push(f64x4::from(x1));
+            batches.y1.push(f64x4::from(y1));
+        }
+        batches
+    }
+}
+
+/// Squared distance from each point to the corresponding edge.
+#[inline]
+fn point_edge_sq(px: f64x4, py: f64x4, ax: f64x4, ay: f64x4, bx: f64x4, by: f64x4) -> f64x4 {
+    let dx = bx - ax;
+    let dy = by - ay;
+    let len_sq = (dx * dx + dy * dy).max(f64x4::splat(1e-12));
+    let t = (((px - ax) * dx + (py - ay) * dy) / len_sq)
+        .max(f64x4::splat(0.0))
+        .min(f64x4::splat(1.0));
+    let cx = ax + t * dx - px;
+    let cy = ay + t * dy - py;
+    cx * cx + cy * cy
+}
+
+/// Calls `f` with the squared distances between every left edge and each
+/// batch of right edges, along with the height of each approach.
+fn for_each_batch(left: &[(Point, Point)], right: &EdgeBatches, mut f: impl FnMut(f64x4, f64x4)) {
+    let zero = f64x4::splat(0.0);
+    for (p0, p1) in left {
+        let (p0x, p0y) = (f64x4::splat(p0.x), f64x4::splat(p0.y));
+        let (p1x, p1y) = (f64x4::splat(p1.x), f64x4::splat(p1.y));
+        for j in 0..right.x0.len() {
+            let (ax, ay, bx, by) = (right.x0[j], right.y0[j], right.x1[j], right.y1[j]);
+            let d_sq = point_edge_sq(p0x, p0y, ax, ay, bx, by)
+                .min(point_edge_sq(p1x, p1y, ax, ay, bx, by))
+                .min(point_edge_sq(ax, ay, p0x, p0y, p1x, p1y))
+                .min(point_edge_sq(bx, by, p0x, p0y, p1x, p1y));
+            // Edges which cross have no distance between them at all.
+            let cross = |ox: f64x4, oy: f64x4, ux: f64x4, uy: f64x4, qx: f64x4, qy: f64x4| {
+                (ux - ox) * (qy - oy) - (uy - oy) * (qx - ox)
+            };
+            let straddles_right = (cross(p0x, p0y, p1x, p1y, ax, ay)
+                * cross(p0x, p0y, p1x, p1y, bx, by))
+            .cmp_lt(zero);
+            let straddles_left =
+                (cross(ax, ay, bx, by, p0x, p0y) * cross(ax, ay, bx, by, p1x, p1y)).cmp_lt(zero);
+            let d_sq = (straddles_right & straddles_left).blend(zero, d_sq);
+            let y = (p0y + p1y + ay + by) * f64x4::splat(0.25);
+            f(d_sq, y);
+        }
+    }
+}
+
+/// Measures the distance between the positioned outlines after flattening
+/// them to straight edges, comparing edges several at a time.
+pub(crate) fn flat_distance(
+    left_paths: &[BezPath],
+    right_paths: &[BezPath],
+    x_offset: f32,
+    y_offset: f32,
+    parameters: &Parameters,
+) -> Option<Measurement> {
+    let tolerance = parameters.tolerance as f64;
+    let offset1 = Affine::translate(Vec2::new(0.0, y_offset as f64));
+    let offset2 = Affine::translate(Vec2::new(x_offset as f64, 0.0));
+    let left: Vec<(Point, Point)> = left_paths
+        .iter()
+        .flat_map(|p| flatten_edges(&(offset1 * p), tolerance))
+        .collect();
+    let right: Vec<(Point, Point)> = right_paths
+        .iter()
+        .flat_map(|p| flatten_edges(&(offset2 * p), tolerance))
+        .collect();
+    if left.is_empty() || right.is_empty() {
+        return None;
+    }
+    let right = EdgeBatches::new(&right);
+
+    if !parameters.target_varies() {
+        // With a single target we only need the closest approach, which
+        // can be found without leaving the vector registers.
+        let mut best: Option<f64x4> = None;
+        for_each_batch(&left, &right, |d_sq, _| {
+            best = Some(best.map_or(d_sq, |b| b.min(d_sq)))
+        });
+        let distance = best?.to_array().iter().copied().reduce(f64::min)?.sqrt();
+        return Some(Measurement {
+            distance,
+            target: parameters.target_distance as f64,
+            weight: 1.0,
+        });
+    }
+
+    let mut critical: Option<Measurement> = None;
+    for_each_batch(&left, &right, |d_sq, y| {
+        for (d_sq, y) in d_sq.to_array().iter().zip(y.to_array().iter()) {
+            if let Some(m) = parameters.measurement(d_sq.sqrt(), *y) {
+                if critical.map_or(true, |c| m.shortfall() > c.shortfall()) {
+                    critical = Some(m);
+                }
+            }
+        }
+    });
+    critical
+}
@@ -0,0 +1,489 @@
+use crate::outline::MAX_COMPONENT_DEPTH;
+use babelfont::{
+    Anchor, Axis, Component, Font, Glyph, GlyphCategory, Layer, Location, Master, Node, NodeType,
+    Path, PathDirection, Shape,
+};
+use kurbo::{Affine, Point};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path as FilePath;
+
+/// Point type flags in Fontra's packed paths.
+const OFF_CURVE_QUAD: u64 = 1;
+const OFF_CURVE_CUBIC: u64 = 2;
+const POINT_TYPE_MASK: u64 = 0x07;
+
+/// Everything needed to instance a variable component: the glyphs' data by
+/// name, the masters' locations and the font's axis defaults.
+struct Project {
+    glyphs: HashMap<String, Value>,
+    masters: Vec<(String, HashMap<String, f32>)>,
+    defaults: HashMap<String, f32>,
+}
+
+/// Loads a Fontra project directory. Each of the font's sources becomes a
+/// master, and each glyph's layers are matched to masters through the
+/// glyph sources' `locationBase` or, failing that, their location.
+/// Variable components (those with a location) are decomposed to their
+/// glyph instanced at that location; see `Project::instance`.
+pub(crate) fn load(path: &FilePath) -> Result<Font, String> {
+    let font_data = read_json(&path.join("font-data.json"))?;
+    let mut font = Font::new();
+    if let Some(upm) = font_data["unitsPerEm"].as_u64() {
+        font.upm = upm as u16;
+    }
+
+    let axes = font_data["axes"]["axes"]
+        .as_array()
+        .or_else(|| font_data["axes"].as_array())
+        .cloned()
+        .unwrap_or_default();
+    let mut defaults: HashMap<String, f32> = HashMap::new();
+    for a in axes.iter() {
+        let name = a["name"].as_str().unwrap_or_default().to_string();
+        let mut axis = Axis::new(
+            name.clone(),
+            a["tag"].as_str().unwrap_or_default().to_string(),
+        );
+        axis.min = a["minValue"].as_f64().map(|v| v as f32);
+        axis.default = a["defaultValue"].as_f64().map(|v| v as f32);
+        axis.max = a["maxValue"].as_f64().map(|v| v as f32);
+        defaults.insert(name, axis.default.unwrap_or(0.0));
+        font.axes.push(axis);
+    }
+
+    let mut master_locations: Vec<(String, HashMap<String, f32>)> = vec![];
+    if let Some(sources) = font_data["sources"].as_object() {
+        for (id, source) in sources {
+            let location = full_location(&source["location"], &defaults);
+            let name = source["name"].as_str().unwrap_or(id);
+            font.masters.push(Master::new(
+                name,
+                id,
+                Location(location.clone().into_iter().collect()),
+            ));
+            master_locations.push((id.clone(), location));
+        }
+    }
+
+    let codepoints = read_glyph_info(&path.join("glyph-info.csv"));
+    let glyph_dir = path.join("glyphs");
+    let entries =
+        fs::read_dir(&glyph_dir).map_err(|e| format!("Couldn't read {:?}: {:}", glyph_dir, e))?;
+    let mut names = vec![];
+    let mut glyphs = HashMap::new();
+    for entry in entries.flatten() {
+        if entry.path().extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let data = read_json(&entry.path())?;
+        let name = data["name"].as_str().unwrap_or_default().to_string();
+        names.push(name.clone());
+        glyphs.insert(name, data);
+    }
+    let project = Project {
+        glyphs,
+        masters: master_locations,
+        defaults,
+    };
+    for name in names {
+        let glyph = load_glyph(&project, &project.glyphs[&name], &codepoints);
+        font.glyphs.0.push(glyph);
+    }
+    Ok(font)
+}
+
+fn read_json(path: &FilePath) -> Result<Value, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read {:?}: {:}", path, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("Couldn't parse {:?}: {:}", path, e))
+}
+
+/// Maps glyph names to code points from `glyph-info.csv`, whose lines are
+/// a glyph name and a comma-separated list of `U+XXXX` code points,
+/// separated by a semicolon.
+fn read_glyph_info(path: &FilePath) -> HashMap<String, Vec<usize>> {
+    let text = fs::read_to_string(path).unwrap_or_default();
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split(';');
+            let name = fields.next()?.trim().to_string();
+            let codepoints = fields
+                .next()
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|c| usize::from_str_radix(c.trim().trim_start_matches("U+"), 16).ok())
+                .collect();
+            Some((name, codepoints))
+        })
+        .collect()
+}
+
+/// A location with every axis filled in, missing ones at their defaults.
+fn full_location(location: &Value, defaults: &HashMap<String, f32>) -> HashMap<String, f32> {
+    let mut full = defaults.clone();
+    if let Some(location) = location.as_object() {
+        for (axis, value) in location {
+            if let Some(value) = value.as_f64() {
+                full.insert(axis.clone(), value as f32);
+            }
+        }
+    }
+    full
+}
+
+fn load_glyph(project: &Project, data: &Value, codepoints: &HashMap<String, Vec<usize>>) -> Glyph {
+    let name = data["name"].as_str().unwrap_or_default().to_string();
+    let mut layers = vec![];
+    let sources = data["sources"].as_array().cloned().unwrap_or_default();
+    if let Some(fontra_layers) = data["layers"].as_object() {
+        for (layer_name, layer_data) in fontra_layers {
+            let source = sources
+                .iter()
+                .find(|s| s["layerName"].as_str() == Some(layer_name));
+            let master_id = source.and_then(|s| {
+                s["locationBase"]
+                    .as_str()
+                    .map(|b| b.to_string())
+                    .or_else(|| {
+                        let location = full_location(&s["location"], &project.defaults);
+                        project
+                            .masters
+                            .iter()
+                            .find(|(_, l)| *l == location)
+                            .map(|(id, _)| id.clone())
+                    })
+            });
+            let location = match source {
+                Some(s) => project.font_location(&project.source_location(data, s)),
+                None => project.defaults.clone(),
+            };
+            let mut layer = load_layer(project, &layer_data["glyph"], &location);
+            match master_id {
+                // A glyph's layer for a master is the one carrying its ID.
+                Some(id) => layer.id = Some(id),
+                None => layer.name = Some(layer_name.clone()),
+            }
+            layers.push(layer);
+        }
+    }
+    Glyph {
+        codepoints: codepoints.get(&name).cloned().unwrap_or_default(),
+        name,
+        production_name: None,
+        category: GlyphCategory::Base,
+        layers,
+        exported: true,
+        direction: None,
+    }
+}
+
+/// Loads a layer's glyph, whose location on the font's axes is given for
+/// instancing any variable components.
+fn load_layer(project: &Project, glyph: &Value, location: &HashMap<String, f32>) -> Layer {
+    let mut layer = Layer::new(glyph["xAdvance"].as_f64().unwrap_or(0.0).round() as i32);
+    for path in load_paths(&glyph["path"]) {
+        layer.push_path(path);
+    }
+    for component in glyph["components"].as_array().into_iter().flatten() {
+        push_component(
+            &mut layer,
+            project,
+            component,
+            Affine::default(),
+            location,
+            0,
+        );
+    }
+    for anchor in glyph["anchors"].as_array().into_iter().flatten() {
+        layer.anchors.push(Anchor {
+            name: anchor["name"].as_str().unwrap_or_default().to_string(),
+            x: anchor["x"].as_f64().unwrap_or(0.0).round() as i32,
+            y: anchor["y"].as_f64().unwrap_or(0.0).round() as i32,
+        });
+    }
+    layer
+}
+
+/// Adds a component to a layer, placed by the given transform. A variable
+/// component is decomposed into the outlines (and components) of its
+/// glyph instanced at the component's location, on top of the font
+/// location it appears at; other components are kept as they are, and
+/// decomposed with the rest when measured.
+fn push_component(
+    layer: &mut Layer,
+    project: &Project,
+    component: &Value,
+    transform: Affine,
+    location: &HashMap<String, f32>,
+    depth: usize,
+) {
+    let reference = component["name"].as_str().unwrap_or_default();
+    let transform = transform * transformation(&component["transformation"]);
+    let component_location = component["location"].as_object().filter(|l| !l.is_empty());
+    let instanced = match component_location {
+        Some(component_location) if depth < MAX_COMPONENT_DEPTH => {
+            let mut location = location.clone();
+            for (axis, value) in component_location {
+                if let Some(value) = value.as_f64() {
+                    location.insert(axis.clone(), value as f32);
+                }
+            }
+            project
+                .instance(reference, &location)
+                .map(|glyph| (glyph, project.font_location(&location)))
+        }
+        _ => None,
+    };
+    let (glyph, location) = match instanced {
+        Some(instanced) => instanced,
+        None => {
+            layer.shapes.push(Shape::ComponentShape(Component {
+                reference: reference.to_string(),
+                transform,
+            }));
+            return;
+        }
+    };
+    for mut path in load_paths(&glyph["path"]) {
+        for node in path.nodes.iter_mut() {
+            let point = transform * Point::new(node.x as f64, node.y as f64);
+            node.x = point.x as f32;
+            node.y = point.y as f32;
+        }
+        layer.push_path(path);
+    }
+    for component in glyph["components"].as_array().into_iter().flatten() {
+        push_component(layer, project, component, transform, &location, depth + 1);
+    }
+}
+
+impl Project {
+    /// A glyph's axis defaults: the font's, along with those of any axes
+    /// the glyph defines for itself.
+    fn glyph_defaults(&self, data: &Value) -> HashMap<String, f32> {
+        let mut defaults = self.defaults.clone();
+        for axis in data["axes"].as_array().into_iter().flatten() {
+            if let (Some(name), Some(default)) =
+                (axis["name"].as_str(), axis["defaultValue"].as_f64())
+            {
+                defaults.insert(name.to_string(), default as f32);
+            }
+        }
+        defaults
+    }
+
+    /// Where a glyph source sits, with every axis (the font's and the
+    /// glyph's own) filled in.
+    fn source_location(&self, data: &Value, source: &Value) -> HashMap<String, f32> {
+        let mut location = self.glyph_defaults(data);
+        if let Some(base) = source["locationBase"].as_str() {
+            if let Some((_, master)) = self.masters.iter().find(|(id, _)| id == base) {
+                location.extend(master.clone());
+            }
+        }
+        location.extend(full_location(&source["location"], &HashMap::new()));
+        location
+    }
+
+    /// Only the parts of a location on the font's axes, which is what
+    /// components inherit from the glyph using them.
+    fn font_location(&self, location: &HashMap<String, f32>) -> HashMap<String, f32> {
+        location
+            .iter()
+            .filter(|(axis, _)| self.defaults.contains_key(*axis))
+            .map(|(axis, value)| (axis.clone(), *value))
+            .collect()
+    }
+
+    /// A glyph instanced at a location, as Fontra glyph data. Each axis the
+    /// location moves off its default contributes the difference between
+    /// the glyph's default source and its sources along that axis alone
+    /// (interpolating between the two either side, and going no further
+    /// than the outermost); sources off more than one axis are not used.
+    /// Returns `None` if the glyph has no default source.
+    fn instance(&self, name: &str, location: &HashMap<String, f32>) -> Option<Value> {
+        let data = self.glyphs.get(name)?;
+        let defaults = self.glyph_defaults(data);
+        let sources: Vec<(HashMap<String, f32>, &Value)> = data["sources"]
+            .as_array()?
+            .iter()
+            .filter_map(|s| {
+                let glyph = &data["layers"][s["layerName"].as_str()?]["glyph"];
+                glyph
+                    .is_object()
+                    .then(|| (self.source_location(data, s), glyph))
+            })
+            .collect();
+        let base = sources.iter().find(|(l, _)| *l == defaults)?.1;
+        let mut deltas: Vec<(&Value, f64)> = vec![];
+        for (axis, default) in defaults.iter() {
+            let value = location.get(axis).copied().unwrap_or(*default);
+            let offset = (value - default) as f64;
+            if offset == 0.0 {
+                continue;
+            }
+            // The sources along this axis on the same side as the value,
+            // from the default outwards.
+            let mut poles: Vec<(f64, &Value)> = sources
+                .iter()
+                .filter(|(l, _)| {
+                    l.iter()
+                        .all(|(a, v)| a == axis || defaults.get(a) == Some(v))
+                })
+                .map(|(l, glyph)| ((l[axis] - default) as f64, *glyph))
+                .filter(|(o, _)| o * offset > 0.0)
+                .collect();
+            poles.sort_by(|a, b| a.0.abs().partial_cmp(&b.0.abs()).unwrap());
+            let outer = match poles.iter().position(|(o, _)| o.abs() >= offset.abs()) {
+                Some(outer) => outer,
+                None if !poles.is_empty() => {
+                    deltas.push((poles[poles.len() - 1].1, 1.0));
+                    continue;
+                }
+                None => continue,
+            };
+            let (inner_offset, inner) = match outer {
+                0 => (0.0, base),
+                _ => poles[outer - 1],
+            };
+            let (outer_offset, outer) = poles[outer];
+            let t = (offset - inner_offset) / (outer_offset - inner_offset);
+            deltas.push((outer, t));
+            deltas.push((inner, 1.0 - t));
+        }
+        let coordinates = |glyph: &Value| glyph["path"]["coordinates"].as_array().map(|c| c.len());
+        deltas.retain(|(glyph, _)| {
+            let compatible = coordinates(glyph) == coordinates(base);
+            if !compatible {
+                log::warn!("Sources of variable component {} are incompatible", name);
+            }
+            compatible
+        });
+        Some(blend(base, &deltas))
+    }
+}
+
+/// Adds each weighted difference from `base` to every number in it,
+/// matching the others' numbers to its own by their place. Numbers which
+/// don't move are left untouched, so that integers stay integers.
+fn blend(base: &Value, deltas: &[(&Value, f64)]) -> Value {
+    match base {
+        Value::Number(n) => {
+            let b = n.as_f64().unwrap_or(0.0);
+            let moved: f64 = deltas
+                .iter()
+                .map(|(d, t)| (d.as_f64().unwrap_or(b) - b) * t)
+                .sum();
+            if moved == 0.0 {
+                base.clone()
+            } else {
+                Value::from(b + moved)
+            }
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let deltas: Vec<(&Value, f64)> =
+                        deltas.iter().map(|(d, t)| (&d[i], *t)).collect();
+                    blend(item, &deltas)
+                })
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, item)| {
+                    let deltas: Vec<(&Value, f64)> =
+                        deltas.iter().map(|(d, t)| (&d[key.as_str()], *t)).collect();
+                    (key.clone(), blend(item, &deltas))
+                })
+                .collect(),
+        ),
+        _ => base.clone(),
+    }
+}
+
+/// Unpacks Fontra's packed path (a flat list of coordinates, a point type
+/// for each point and the index of each contour's last point) into
+/// babelfont paths.
+fn load_paths(path: &Value) -> Vec<Path> {
+    let coordinates: Vec<f32> = path["coordinates"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|c| c.as_f64().map(|c| c as f32))
+        .collect();
+    let point_types: Vec<u64> = path["pointTypes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|t| t.as_u64().unwrap_or(0) & POINT_TYPE_MASK)
+        .collect();
+    let mut paths = vec![];
+    let mut start = 0;
+    for contour in path["contourInfo"].as_array().into_iter().flatten() {
+        let end = contour["endPoint"].as_u64().unwrap_or(0) as usize;
+        let closed = contour["isClosed"].as_bool().unwrap_or(true);
+        let points: Vec<(f32, f32, u64)> = (start..=end)
+            .filter(|i| 2 * i + 1 < coordinates.len())
+            .map(|i| {
+                (
+                    coordinates[2 * i],
+                    coordinates[2 * i + 1],
+                    point_types.get(i).copied().unwrap_or(0),
+                )
+            })
+            .collect();
+        start = end + 1;
+        if points.is_empty() {
+            continue;
+        }
+        // On-curve points are typed by the off-curve points leading to them,
+        // which for the first point of a closed contour are at the end.
+        let nodes = points
+            .iter()
+            .enumerate()
+            .map(|(i, &(x, y, t))| {
+                let previous = if i > 0 {
+                    Some(points[i - 1].2)
+                } else if closed {
+                    points.last().map(|p| p.2)
+                } else {
+                    None
+                };
+                let nodetype = match (t, previous) {
+                    (OFF_CURVE_QUAD, _) | (OFF_CURVE_CUBIC, _) => NodeType::OffCurve,
+                    (_, None) => NodeType::Move,
+                    (_, Some(OFF_CURVE_CUBIC)) => NodeType::Curve,
+                    (_, Some(OFF_CURVE_QUAD)) => NodeType::QCurve,
+                    _ => NodeType::Line,
+                };
+                Node { x, y, nodetype }
+            })
+            .collect();
+        paths.push(Path {
+            nodes,
+            closed,
+            direction: PathDirection::Clockwise,
+        });
+    }
+    paths
+}
+
+/// Builds the affine transform for Fontra's decomposed transformation,
+/// which applies its scale, skew and rotation about the transformation
+/// centre before translating.
+fn transformation(t: &Value) -> Affine {
+    let get = |key: &str, default: f64| t[key].as_f64().unwrap_or(default);
+    let (cx, cy) = (get("tCenterX", 0.0), get("tCenterY", 0.0));
+    let skew_x = get("skewX", 0.0).to_radians();
+    let skew_y = get("skewY", 0.0).to_radians();
+    Affine::translate((get("translateX", 0.0) + cx, get("translateY", 0.0) + cy))
+        * Affine::rotate(get("rotation", 0.0).to_radians())
+        * Affine::new([get("scaleX", 1.0), 0.0, 0.0, get("scaleY", 1.0), 0.0, 0.0])
+        * Affine::new([1.0, skew_y.tan(), -skew_x.tan(), 1.0, 0.0, 0.0])
+        * Affine::translate((-cx, -cy))
+}
@@ -0,0 +1,170 @@
+use crate::distance::Measurement;
+use crate::flat::{flatten_edges, spans};
+use crate::parameters::Parameters;
+use kurbo::{Affine, BezPath, Point, Shape, Vec2};
+
+/// How far apart (in font units) the rows the gap is measured along are.
+const ROW_STEP: f64 = 2.0;
+
+/// The lowest and highest points of a set of edges.
+fn vertical_extent(edges: &[(Point, Point)]) -> Option<(f64, f64)> {
+    edges
+        .iter()
+        .flat_map(|(a, b)| vec![a.y, b.y])
+        .fold(None, |extent, y| match extent {
+            None => Some((y, y)),
+            Some((low, high)) => Some((low.min(y), high.max(y))),
+        })
+}
+
+/// Measures the horizontal gap between the positioned outlines along rows
+/// at which both have ink: from the rightmost ink of the left glyph to the
+/// leftmost ink of the right glyph. Unlike the closest approach, a feature
+/// above or below the other glyph's ink (a dot over the next letter, say)
+/// only counts in the rows it shares with it. Where the outlines overlap
+/// horizontally the gap is negative. Returns the row which falls furthest
+/// short of its target, or `None` if the outlines share no rows.
+pub(crate) fn horizontal_gap(
+    left_paths: &[BezPath],
+    right_paths: &[BezPath],
+    x_offset: f32,
+    y_offset: f32,
+    parameters: &Parameters,
+) -> Option<Measurement> {
+    let tolerance = parameters.tolerance as f64;
+    let offset1 = Affine::translate(Vec2::new(0.0, y_offset as f64));
+    let offset2 = Affine::translate(Vec2::new(x_offset as f64, 0.0));
+    let left: Vec<(Point, Point)> = left_paths
+        .iter()
+        .flat_map(|p| flatten_edges(&(offset1 * p), tolerance))
+        .collect();
+    let right: Vec<(Point, Point)> = right_paths
+        .iter()
+        .flat_map(|p| flatten_edges(&(offset2 * p), tolerance))
+        .collect();
+    let (left_low, left_high) = vertical_extent(&left)?;
+    let (right_low, right_high) = vertical_extent(&right)?;
+    let (low, high) = (left_low.max(right_low), left_high.min(right_high));
+
+    let mut critical: Option<Measurement> = None;
+    let mut y = low.floor() + ROW_STEP / 2.0;
+    while y < high {
+        let left_edge = spans(&left, y)
+            .iter()
+            .map(|s| s.1)
+            .fold(None, |m: Option<f64>, x| Some(m.map_or(x, |m| m.max(x))));
+        let right_edge = spans(&right, y)
+            .iter()
+            .map(|s| s.0)
+            .fold(None, |m: Option<f64>, x| Some(m.map_or(x, |m| m.min(x))));
+        if let (Some(l), Some(r)) = (left_edge, right_edge) {
+            if let Some(m) = parameters.measurement(r - l, y) {
+                if critical.map_or(true, |c| m.shortfall() > c.shortfall()) {
+                    critical = Some(m);
+                }
+            }
+        }
+        y += ROW_STEP;
+    }
+    critical
+}
+
+/// Which side of a glyph's ink a line is on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Side {
+    Left,
+    Right,
+}
+
+/// The gaps between a glyph's ink and a vertical line.
+#[derive(Debug, Clone)]
+pub(crate) struct LineGap {
+    /// The closest approach of the ink to the line, negative if the ink
+    /// crosses it
+    pub distance: f64,
+    /// The row which falls furthest short of its target, and its height
+    pub critical: Option<(f64, Measurement)>,
+    /// The gap at each row with ink, as `(y, gap)` from the bottom up
+    pub profile: Vec<(f64, f64)>,
+}
+
+/// Measures the gaps between a glyph's ink and the vertical line at `x`,
+/// on the given side of the ink, row by row as `horizontal_gap` does: the
+/// line stands in for a neighbouring glyph, for margins at the start and
+/// end of a line of text and the like. Returns `None` if the glyph has no
+/// ink.
+pub(crate) fn line_gap(
+    paths: &[BezPath],
+    x: f64,
+    side: Side,
+    parameters: &Parameters,
+) -> Option<LineGap> {
+    let tolerance = parameters.tolerance as f64;
+    let edges: Vec<(Point, Point)> = paths
+        .iter()
+        .flat_map(|p| flatten_edges(p, tolerance))
+        .collect();
+    let (low, high) = vertical_extent(&edges)?;
+    let bounds = paths
+        .iter()
+        .map(|p| p.bounding_box())
+        .reduce(|a, b| a.union(b))?;
+    let distance = match side {
+        Side::Right => x - bounds.x1,
+        Side::Left => bounds.x0 - x,
+    };
+
+    let mut critical: Option<(f64, Measurement)> = None;
+    let mut profile = vec![];
+    let mut y = low.floor() + ROW_STEP / 2.0;
+    while y < high {
+        let row = spans(&edges, y);
+        let edge = match side {
+            Side::Right => row.iter().map(|s| s.1).reduce(f64::max),
+            Side::Left => row.iter().map(|s| s.0).reduce(f64::min),
+        };
+        if let Some(edge) = edge {
+            let gap = match side {
+                Side::Right => x - edge,
+                Side::Left => edge - x,
+            };
+            if let Some(m) = parameters.measurement(gap, y) {
+                if critical.map_or(true, |(_, c)| m.shortfall() > c.shortfall()) {
+                    critical = Some((y, m));
+                }
+            }
+            profile.push((y, gap));
+        }
+        y += ROW_STEP;
+    }
+    Some(LineGap {
+        distance,
+        critical,
+        profile,
+    })
+}
+
+/// How far a glyph's ink sits, optically, from the vertical line at `x`:
+/// the furthest the glyph could move towards the line before one of its
+/// rows came closer to it than that row's target. With a flat margin as
+/// the line, this is where the glyph's optical edge lies, for optical
+/// bounds and margin kerns. Returns `None` if the glyph has no ink.
+pub(crate) fn margin_room(
+    paths: &[BezPath],
+    x: f64,
+    side: Side,
+    parameters: &Parameters,
+) -> Option<f64> {
+    let gap = line_gap(paths, x, side, parameters)?;
+    gap.profile
+        .iter()
+        .filter_map(|&(y, g)| {
+            let m = parameters.measurement(g, y)?;
+            if m.weight > 0.0 {
+                Some(g - m.target)
+            } else {
+                None
+            }
+        })
+        .reduce(f64::min)
+}
@@ -0,0 +1,280 @@
+use crate::distance::Measurement;
+use crate::flat::flatten_edges;
+use crate::parameters::Parameters;
+use kurbo::{Affine, BezPath, Vec2};
+use std::sync::mpsc::channel;
+use std::sync::OnceLock;
+use wgpu::util::DeviceExt;
+
+/// Invocations per workgroup; the shader must agree.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// For each left edge at each height, finds the closest right edge and
+/// where the two approach. Distances are squared, and single precision is
+/// plenty at font scales.
+const SHADER: &str = r#"
+struct Counts {
+    left: u32,
+    right: u32,
+    heights: u32,
+    pad: u32,
+}
+
+@group(0) @binding(0) var<uniform> counts: Counts;
+@group(0) @binding(1) var<storage, read> left: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read> right: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read> heights: array<f32>;
+@group(0) @binding(4) var<storage, read_write> closest: array<vec2<f32>>;
+
+fn point_edge_sq(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>) -> f32 {
+    let d = b - a;
+    let t = clamp(dot(p - a, d) / max(dot(d, d), 1e-12), 0.0, 1.0);
+    let c = a + t * d - p;
+    return dot(c, c);
+}
+
+fn side(o: vec2<f32>, u: vec2<f32>, q: vec2<f32>) -> f32 {
+    return (u.x - o.x) * (q.y - o.y) - (u.y - o.y) * (q.x - o.x);
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= counts.left * counts.heights) {
+        return;
+    }
+    let edge = left[index % counts.left];
+    let rise = vec2<f32>(0.0, heights[index / counts.left]);
+    let p0 = edge.xy + rise;
+    let p1 = edge.zw + rise;
+    var best = 3.4e38;
+    var best_y = 0.0;
+    for (var j = 0u; j < counts.right; j = j + 1u) {
+        let a = right[j].xy;
+        let b = right[j].zw;
+        var d = min(
+            min(point_edge_sq(p0, a, b), point_edge_sq(p1, a, b)),
+            min(point_edge_sq(a, p0, p1), point_edge_sq(b, p0, p1))
+        );
+        // Edges which cross have no distance between them at all.
+        if (side(p0, p1, a) * side(p0, p1, b) < 0.0 && side(a, b, p0) * side(a, b, p1) < 0.0) {
+            d = 0.0;
+        }
+        if (d < best) {
+            best = d;
+            best_y = (p0.y + p1.y + a.y + b.y) * 0.25;
+        }
+    }
+    closest[index] = vec2<f32>(best, best_y);
+}
+"#;
+
+/// The device and compiled pipeline, shared by every thread.
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+
+impl Gpu {
+    fn new() -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        }))?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("kerndeterminer"),
+                required_features: wgpu::Features::empty(),
+                required_limits: wgpu::Limits::downlevel_defaults(),
+            },
+            None,
+        ))
+        .ok()?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("flattened distances"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("flattened distances"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+        });
+        Some(Gpu {
+            device,
+            queue,
+            pipeline,
+        })
+    }
+
+    /// The device, set up the first time it is asked for, or `None` if
+    /// there is no adapter to use.
+    fn get() -> Option<&'static Gpu> {
+        GPU.get_or_init(|| {
+            let gpu = Gpu::new();
+            if gpu.is_none() {
+                log::warn!("No GPU adapter found; the gpu engine will measure on the CPU");
+            }
+            gpu
+        })
+        .as_ref()
+    }
+
+    /// The squared distance from each left edge at each height to the
+    /// closest right edge, and the height of that approach, for each
+    /// height in turn.
+    fn closest(
+        &self,
+        left: &[[f32; 4]],
+        right: &[[f32; 4]],
+        heights: &[f32],
+    ) -> Option<Vec<[f32; 2]>> {
+        let storage = |label: &str, contents: &[u8]| {
+            self.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(label),
+                    contents,
+                    usage: wgpu::BufferUsages::STORAGE,
+                })
+        };
+        let counts = [
+            left.len() as u32,
+            right.len() as u32,
+            heights.len() as u32,
+            0,
+        ];
+        let counts = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("counts"),
+                contents: bytemuck::cast_slice(&counts),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let left_edges = storage("left edges", bytemuck::cast_slice(left));
+        let right_edges = storage("right edges", bytemuck::cast_slice(right));
+        let rises = storage("heights", bytemuck::cast_slice(heights));
+        let invocations = (left.len() * heights.len()) as u32;
+        let size = (invocations as usize * std::mem::size_of::<[f32; 2]>()) as u64;
+        let output = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("closest"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("closest (read back)"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: counts.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: left_edges.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: right_edges.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: rises.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: output.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups((invocations + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&output, 0, &staging, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (sender, receiver) = channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv().ok()?.ok()?;
+        let closest = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        staging.unmap();
+        Some(closest)
+    }
+}
+
+/// Measures the distance between the positioned outlines, flattened as
+/// for the flattened engine, at every height at once on the GPU. Returns
+/// a measurement for each height, or `None` if there is no GPU to use.
+/// Where the target varies with height, each left edge is judged at its
+/// closest approach to the right outline.
+pub(crate) fn gpu_distances(
+    left_paths: &[BezPath],
+    right_paths: &[BezPath],
+    x_offset: f32,
+    heights: &[f64],
+    parameters: &Parameters,
+) -> Option<Vec<Option<Measurement>>> {
+    let gpu = Gpu::get()?;
+    let tolerance = parameters.tolerance as f64;
+    let offset = Affine::translate(Vec2::new(x_offset as f64, 0.0));
+    let edges = |paths: &[BezPath], transform: Affine| -> Vec<[f32; 4]> {
+        paths
+            .iter()
+            .flat_map(|p| flatten_edges(&(transform * p), tolerance))
+            .map(|(a, b)| [a.x as f32, a.y as f32, b.x as f32, b.y as f32])
+            .collect()
+    };
+    let left = edges(left_paths, Affine::default());
+    let right = edges(right_paths, offset);
+    if left.is_empty() || right.is_empty() || heights.is_empty() {
+        return Some(vec![None; heights.len()]);
+    }
+    let rises: Vec<f32> = heights.iter().map(|&y| y as f32).collect();
+    let closest = gpu.closest(&left, &right, &rises)?;
+
+    let varies = parameters.target_varies();
+    Some(
+        closest
+            .chunks(left.len())
+            .map(|per_edge| {
+                if !varies {
+                    let d_sq = per_edge.iter().map(|c| c[0]).reduce(f32::min)?;
+                    return Some(Measurement {
+                        distance: (d_sq as f64).sqrt(),
+                        target: parameters.target_distance as f64,
+                        weight: 1.0,
+                    });
+                }
+                per_edge
+                    .iter()
+                    .filter_map(|c| parameters.measurement((c[0] as f64).sqrt(), c[1] as f64))
+                    .reduce(|a, b| if b.shortfall() > a.shortfall() { b } else { a })
+            })
+            .collect(),
+    )
+}
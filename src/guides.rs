@@ -0,0 +1,168 @@
+use crate::parameters::{Parameters, Zone};
+use babelfont::{Font, Guide};
+use std::collections::HashMap;
+
+/// Ink above a guideline with this name is ignored.
+const IGNORE_ABOVE: &str = "kern-ignore-above";
+/// Ink below a guideline with this name is ignored.
+const IGNORE_BELOW: &str = "kern-ignore-below";
+/// Guidelines named this followed by a distance (`clearance-150`) bound a
+/// zone with that target.
+const CLEARANCE: &str = "clearance-";
+
+/// The measurement constraints drawn as horizontal guidelines, in font
+/// units.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GuideRules {
+    pub ignore_below: Option<f32>,
+    pub ignore_above: Option<f32>,
+    pub zones: Vec<Zone>,
+}
+
+impl GuideRules {
+    /// Reads the constraints from a set of guidelines; guidelines which
+    /// aren't horizontal, or whose names mean nothing here, are passed
+    /// over. `clearance-N` guidelines are paired off from the bottom up,
+    /// each pair bounding a zone with a target of N; an odd one out starts
+    /// a zone which runs to the top.
+    pub fn new(guides: &[Guide]) -> Self {
+        let mut rules = GuideRules::default();
+        // Keyed by the bits of the target, since floats can't be hashed.
+        let mut clearances: HashMap<u32, Vec<f32>> = HashMap::new();
+        for guide in guides {
+            if guide.pos.angle % 180.0 != 0.0 {
+                continue;
+            }
+            let name = match guide.name.as_deref() {
+                Some(name) => name.trim(),
+                None => continue,
+            };
+            let y = guide.pos.y as f32;
+            if !y.is_finite() {
+                continue;
+            }
+            if name == IGNORE_ABOVE {
+                rules.ignore_above = Some(rules.ignore_above.map_or(y, |a| a.min(y)));
+            } else if name == IGNORE_BELOW {
+                rules.ignore_below = Some(rules.ignore_below.map_or(y, |b| b.max(y)));
+            } else if let Some(distance) = name.strip_prefix(CLEARANCE) {
+                match distance.parse::<f32>() {
+                    Ok(target) if target.is_finite() => {
+                        clearances.entry(target.to_bits()).or_default().push(y)
+                    }
+                    _ => log::warn!(
+                        "Ignoring guideline {:}: {:} is not a distance",
+                        name,
+                        distance
+                    ),
+                }
+            }
+        }
+        let mut clearances: Vec<(f32, Vec<f32>)> = clearances
+            .into_iter()
+            .map(|(bits, heights)| (f32::from_bits(bits), heights))
+            .collect();
+        clearances.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (target_distance, mut heights) in clearances {
+            heights.sort_by(|a, b| a.total_cmp(b));
+            for bounds in heights.chunks(2) {
+                rules.zones.push(Zone {
+                    bottom: bounds[0],
+                    top: bounds.get(1).copied().unwrap_or(f32::MAX),
+                    target_distance,
+                });
+            }
+        }
+        rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ignore_below.is_none() && self.ignore_above.is_none() && self.zones.is_empty()
+    }
+
+    /// Combines two sets of constraints: the tighter cutoff wins, and this
+    /// set's zones are checked before the other's.
+    pub fn merge(&mut self, other: &GuideRules) {
+        if let Some(above) = other.ignore_above {
+            self.ignore_above = Some(self.ignore_above.map_or(above, |a| a.min(above)));
+        }
+        if let Some(below) = other.ignore_below {
+            self.ignore_below = Some(self.ignore_below.map_or(below, |b| b.max(below)));
+        }
+        self.zones.extend(other.zones.iter().copied());
+    }
+
+    /// Applies the constraints on top of the parameters: cutoffs tighten
+    /// any already set, and zones are checked before the caller's. Zone
+    /// targets are widened as the caller's are for italics.
+    pub fn apply(&self, parameters: &mut Parameters) {
+        let mut rules = self.clone();
+        let widen = parameters.widen();
+        for zone in rules.zones.iter_mut() {
+            zone.target_distance *= widen;
+        }
+        rules.merge(&GuideRules {
+            ignore_below: parameters.ignore_below,
+            ignore_above: parameters.ignore_above,
+            zones: std::mem::take(&mut parameters.zones),
+        });
+        parameters.ignore_below = rules.ignore_below;
+        parameters.ignore_above = rules.ignore_above;
+        parameters.zones = rules.zones;
+    }
+}
+
+/// A master's guideline constraints: those drawn across the master, which
+/// hold for every pair, and those drawn in glyphs, which hold for every
+/// pair the glyph is in.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MasterGuides {
+    pub global: GuideRules,
+    pub glyphs: HashMap<String, GuideRules>,
+}
+
+impl MasterGuides {
+    /// The constraints from the guidelines of either glyph of a pair, or
+    /// `None` if neither has any.
+    pub fn for_pair(&self, left_glyph: &str, right_glyph: &str) -> Option<GuideRules> {
+        let (left, right) = (self.glyphs.get(left_glyph), self.glyphs.get(right_glyph));
+        let mut rules = left.or(right)?.clone();
+        if let (Some(_), Some(right)) = (left, right) {
+            rules.merge(right);
+        }
+        Some(rules)
+    }
+}
+
+/// Reads the constraints from the font's guidelines, by master ID. Only
+/// masters with some constraints are included.
+pub(crate) fn master_guides(font: &Font) -> HashMap<String, MasterGuides> {
+    font.masters
+        .iter()
+        .filter_map(|master| {
+            let glyphs: HashMap<String, GuideRules> = font
+                .glyphs
+                .0
+                .iter()
+                .filter_map(|glyph| {
+                    let layer = font.master_layer_for(&glyph.name, master)?;
+                    let rules = GuideRules::new(&layer.guides);
+                    if rules.is_empty() {
+                        None
+                    } else {
+                        Some((glyph.name.clone(), rules))
+                    }
+                })
+                .collect();
+            let guides = MasterGuides {
+                global: GuideRules::new(&master.guides),
+                glyphs,
+            };
+            if guides.global.is_empty() && guides.glyphs.is_empty() {
+                None
+            } else {
+                Some((master.id.clone(), guides))
+            }
+        })
+        .collect()
+}
@@ -1,224 +1,2163 @@
-use babelfont::{Font, Layer, Master};
+use babelfont::{Font, Location, Master};
 use core::cmp::Ordering;
-use kurbo::{Affine, BezPath, CubicBez, ParamCurve, ParamCurveNearest, PathSeg, Vec2};
+use env_logger;
+use pyo3::exceptions::{
+    PyFileNotFoundError, PyIOError, PyKeyError, PyRuntimeError, PyTypeError, PyValueError,
+};
 use pyo3::prelude::*;
-use std::collections::HashMap;
-use {env_logger, log};
+use pyo3::types::PyDict;
+use pyo3::wrap_pyfunction;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+mod anchors;
+mod audit;
+mod babelfont_json;
+mod backend;
+mod bands;
+mod batch;
+mod cache;
+mod classes;
+mod composite;
+mod distance;
+mod explain;
+mod fea;
+mod flat;
+mod fontra;
+mod gap;
+#[cfg(feature = "gpu")]
+mod gpu;
+mod guides;
+mod memo;
+mod outline;
+mod overlap;
+mod parameters;
+mod presets;
+mod preview;
+mod proof;
+mod raster;
+mod rules;
+mod smart;
+mod smoothing;
+mod solver;
+mod spacing;
+mod suggest;
+mod timing;
+mod ttx;
+use audit::{audit_pair, tracking_room, Finding};
+use backend::Backend;
+use batch::{BatchResults, KernResult, Summary};
+use cache::OutlineCache;
+use classes::{apply_kerning, existing_kern, extreme_members};
+use explain::Explanation;
+use fea::optical_bounds_fea;
+use gap::{line_gap, margin_room, Side};
+use guides::{master_guides, MasterGuides};
+use kurbo::{Affine, BezPath, Vec2};
+use memo::{alias_groups, pair_key, PairKey, PairMemo};
+use outline::{normalize_direction, svg_path, Outline, EXIT_ANCHOR};
+use parameters::{Parameters, ReferenceHeights, ScaleReference};
+use solver::{
+    determine_kern, measure_at, profile, rise_needed, solve, solve_traced, Solution, Status, Step,
+};
+use std::time::Instant;
+use suggest::{did_you_mean, missing_glyph};
+use timing::PairTimer;
 
 #[pyclass]
 struct KernDeterminer {
+    /// The font we measure, which may be limited to some of the glyphs.
+    /// Components are decomposed as outlines are converted.
     font: Font,
-    layer_paths: HashMap<Layer, Vec<BezPath>>,
+    /// The font as it was loaded, which is what we save
+    source: Font,
+    /// Where the font was loaded from (if it was loaded from a file), and
+    /// the glyphs asked for, so that it can be reloaded
+    filename: Option<String>,
+    glyph_subset: Option<Vec<String>>,
+    /// Set by `close`, after which the font and outlines are gone
+    closed: bool,
+    /// Other names for glyphs, given by the caller, and the working name
+    /// each stands for
+    renames: HashMap<String, String>,
+    /// Those names along with the font's production names, by the working
+    /// name they resolve to (see `working_names`)
+    working_names: HashMap<String, String>,
+    /// The constraints drawn as guidelines in the font, by master ID (see
+    /// `guides::master_guides`), read once per load
+    guides: HashMap<String, MasterGuides>,
+    /// How many seconds reading the font (most recently) took
+    #[pyo3(get)]
+    load_time: f64,
+    outlines: OutlineCache,
+    /// The threads batch operations run on, which are shut down when the
+    /// determiner is dropped
+    pool: ThreadPool,
+}
+
+// Python threads call into the determiner with the GIL released, so it
+// must be safe to share.
+#[allow(dead_code)]
+fn assert_thread_safe() {
+    fn is_send_sync<T: Send + Sync>() {}
+    is_send_sync::<KernDeterminer>();
 }
 
 #[pymethods]
 impl KernDeterminer {
+    /// If `glyphs` is given, only those glyphs (and the glyphs they use as
+    /// components) are available for measurement. Batch operations use
+    /// `threads` threads, or one per core if not given. With
+    /// `normalize_direction`, contours are rewound consistently after
+    /// decomposition. Pairs may name glyphs by their production names, or
+    /// by any of the other names in `names`, a dictionary of each to the
+    /// working name it stands for.
     #[new]
-    fn new(filename: String) -> Self {
-        env_logger::init();
-        let mut font = babelfont::load(&filename).expect("Couldn't load font");
-        for glyph_index in 0..font.glyphs.0.len() {
-            let mut decomposed_layers = Vec::new();
-            if let Some(glyph) = font.glyphs.get_by_index(glyph_index) {
-                for layer in glyph.layers.iter() {
-                    let decomposed_layer = layer.decomposed_components(&font);
-                    decomposed_layers.push(decomposed_layer);
+    #[args(
+        glyphs = "None",
+        threads = "None",
+        normalize_direction = "false",
+        names = "None"
+    )]
+    fn new(
+        filename: &PyAny,
+        glyphs: Option<Vec<String>>,
+        threads: Option<usize>,
+        normalize_direction: bool,
+        names: Option<HashMap<String, String>>,
+    ) -> PyResult<Self> {
+        let filename = fspath(filename)?;
+        if !std::path::Path::new(&filename).exists() {
+            return Err(PyFileNotFoundError::new_err(format!(
+                "No such font: {:}",
+                filename
+            )));
+        }
+        let started = Instant::now();
+        let source = load_font(&filename)?;
+        let load_time = started.elapsed().as_secs_f64();
+        KernDeterminer::with_source(
+            source,
+            Some(filename),
+            load_time,
+            glyphs,
+            threads,
+            normalize_direction,
+            names.unwrap_or_default(),
+        )
+    }
+
+    /// Creates a determiner for a font which has already been parsed, given
+    /// as babelfont's JSON serialization: the text of a `.babelfont` file,
+    /// or the dictionary it decodes to. The other arguments are as for the
+    /// constructor. Such a determiner can't be reloaded.
+    #[staticmethod]
+    #[args(
+        glyphs = "None",
+        threads = "None",
+        normalize_direction = "false",
+        names = "None"
+    )]
+    fn from_babelfont(
+        py: Python,
+        data: &PyAny,
+        glyphs: Option<Vec<String>>,
+        threads: Option<usize>,
+        normalize_direction: bool,
+        names: Option<HashMap<String, String>>,
+    ) -> PyResult<Self> {
+        let started = Instant::now();
+        let text: String = match data.extract() {
+            Ok(text) => text,
+            Err(_) => py.import("json")?.call1("dumps", (data,))?.extract()?,
+        };
+        let data: serde_json::Value = serde_json::from_str(&text)
+            .map_err(|e| PyValueError::new_err(format!("Couldn't parse the font: {:}", e)))?;
+        let source = babelfont_json::load(&data)
+            .map_err(|e| PyValueError::new_err(format!("Couldn't load the font: {:}", e)))?;
+        let load_time = started.elapsed().as_secs_f64();
+        KernDeterminer::with_source(
+            source,
+            None,
+            load_time,
+            glyphs,
+            threads,
+            normalize_direction,
+            names.unwrap_or_default(),
+        )
+    }
+
+    /// Reads the font again from the file it was loaded from, returning
+    /// the names of the glyphs which now measure differently (see
+    /// `cache::changed_glyphs`). Later calls measure the new designs; only
+    /// pairs involving the returned glyphs need kerning again.
+    fn reload(&mut self, py: Python) -> PyResult<Vec<String>> {
+        self.check_open()?;
+        let filename = self.filename.as_ref().ok_or_else(|| {
+            PyValueError::new_err("The font wasn't loaded from a file, so can't be reloaded")
+        })?;
+        let started = Instant::now();
+        let source = load_font(filename)?;
+        self.load_time = started.elapsed().as_secs_f64();
+        let font = subset(&source, self.glyph_subset.clone());
+        let outlines = self.outlines.fresh();
+        let changed = py.allow_threads(|| {
+            self.pool
+                .install(|| cache::changed_glyphs(&self.font, &self.outlines, &font, &outlines))
+        });
+        self.working_names = working_names(&font, &self.renames);
+        self.guides = master_guides(&font);
+        self.font = font;
+        self.source = source;
+        self.outlines = outlines;
+        Ok(changed.into_iter().collect())
+    }
+
+    /// Drops the font and every cached outline, so that long-lived
+    /// processes can let go of fonts they have finished with without
+    /// waiting for the determiner to be collected. The determiner can't be
+    /// used afterwards.
+    fn close(&mut self) {
+        self.font = Font::new();
+        self.source = Font::new();
+        self.outlines = self.outlines.fresh();
+        self.guides = HashMap::new();
+        self.closed = true;
+    }
+
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    /// Closes the determiner at the end of a `with` block. Exceptions are
+    /// passed on.
+    fn __exit__(&mut self, _exc_type: &PyAny, _exc_value: &PyAny, _traceback: &PyAny) -> bool {
+        self.close();
+        false
+    }
+
+    /// Writes the font back out; the format is chosen from the file extension.
+    fn save(&self, path: &PyAny) -> PyResult<()> {
+        self.check_open()?;
+        let path = fspath(path)?;
+        self.source
+            .save(&path)
+            .map_err(|e| PyIOError::new_err(format!("Couldn't save font to {:}: {:?}", path, e)))
+    }
+
+    /// Writes the results into the font's kerning, in the master named or
+    /// each result's own, so that `save` (and later runs starting from the
+    /// font's kerns) see them. See `classes::apply_kerning` for how groups
+    /// are used. Kerns are taken to be in font units. Returns how many
+    /// kerning pairs were set.
+    #[args(master = "None", as_classes = "false")]
+    fn apply_kerning(
+        &mut self,
+        results: PyRef<BatchResults>,
+        master: Option<&str>,
+        as_classes: bool,
+    ) -> PyResult<usize> {
+        self.check_open()?;
+        if let Some(name) = master {
+            if self.source.master(name).is_none() {
+                let names: Vec<String> = self.source.masters.iter().map(master_name).collect();
+                return Err(PyKeyError::new_err(format!(
+                    "Couldn't find master {:}{}",
+                    name,
+                    did_you_mean(name, names.iter().map(|n| n.as_str()))
+                )));
+            }
+        }
+        apply_kerning(&mut self.font, &results.results, master, as_classes);
+        Ok(apply_kerning(
+            &mut self.source,
+            &results.results,
+            master,
+            as_classes,
+        ))
+    }
+
+    /// The font's masters, in order, each as a dict of its `name`, `id`
+    /// and `location` (a dict of axis name to value).
+    fn masters<'py>(&self, py: Python<'py>) -> PyResult<Vec<&'py PyDict>> {
+        self.check_open()?;
+        self.font
+            .masters
+            .iter()
+            .map(|master| {
+                let dict = PyDict::new(py);
+                dict.set_item("name", master_name(master))?;
+                dict.set_item("id", &master.id)?;
+                dict.set_item("location", location_dict(py, &master.location)?)?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
+    /// The names of the glyphs available for measurement, in font order.
+    fn glyph_names(&self) -> PyResult<Vec<String>> {
+        self.check_open()?;
+        Ok(self.font.glyph_names())
+    }
+
+    /// The name to export each glyph under, by working name: its name in
+    /// the `names` given to the constructor, or else its production name.
+    /// Pass it to `BatchResults.renamed` to name kerns by production name.
+    fn production_names(&self) -> PyResult<HashMap<String, String>> {
+        self.check_open()?;
+        let mut names: HashMap<String, String> = self
+            .font
+            .glyphs
+            .0
+            .iter()
+            .filter_map(|g| Some((g.name.clone(), g.production_name.clone()?)))
+            .collect();
+        names.extend(self.renames.iter().map(|(k, v)| (v.clone(), k.clone())));
+        Ok(names)
+    }
+
+    /// The font's axes, each as a dict of its `name`, `tag`, and `min`,
+    /// `default` and `max` values (`None` where the font leaves them out).
+    fn axes<'py>(&self, py: Python<'py>) -> PyResult<Vec<&'py PyDict>> {
+        self.check_open()?;
+        self.font
+            .axes
+            .iter()
+            .map(|axis| {
+                let dict = PyDict::new(py);
+                dict.set_item("name", axis.name.get_default().map(|n| n.to_string()))?;
+                dict.set_item("tag", &axis.tag)?;
+                dict.set_item("min", axis.min)?;
+                dict.set_item("default", axis.default)?;
+                dict.set_item("max", axis.max)?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
+    /// The font's named instances, each as a dict of its `name` and
+    /// `location`.
+    fn instances<'py>(&self, py: Python<'py>) -> PyResult<Vec<&'py PyDict>> {
+        self.check_open()?;
+        self.font
+            .instances
+            .iter()
+            .map(|instance| {
+                let dict = PyDict::new(py);
+                dict.set_item("name", instance.name.get_default().map(|n| n.to_string()))?;
+                dict.set_item("location", location_dict(py, &instance.location)?)?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
+    /// Converts the outlines of every glyph in the given masters (or all
+    /// masters) ahead of time, returning statistics about the cache.
+    #[args(master_names = "None")]
+    fn precompute(
+        &self,
+        py: Python,
+        master_names: Option<Vec<String>>,
+    ) -> PyResult<HashMap<&'static str, usize>> {
+        self.check_open()?;
+        let masters = self.selected_masters(master_names)?;
+        py.allow_threads(|| {
+            self.pool
+                .install(|| self.outlines.precompute(&self.font, &masters))
+        });
+        Ok(self.outlines.stats())
+    }
+
+    #[args(kwargs = "**")]
+    fn determine_kern(
+        &self,
+        py: Python,
+        left_glyph: String,
+        right_glyph: String,
+        master_name: String,
+        target_distance: f32,
+        height: i32,
+        max_tuck: f32,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<Option<f32>> {
+        self.check_open()?;
+        let master = self.master(&master_name)?;
+        let (left_glyph, right_glyph) = (
+            self.working_name(left_glyph),
+            self.working_name(right_glyph),
+        );
+        let parameters = self
+            .parameters(target_distance, height, max_tuck, kwargs)?
+            .for_master(&self.font, master);
+        let parameters = parameters.for_pair(&self.font, &left_glyph, &right_glyph);
+        let start = self.start(master, &left_glyph, &right_glyph, &parameters);
+        py.allow_threads(|| {
+            let solution = determine_kern(
+                &self.font,
+                &self.outlines,
+                master,
+                &left_glyph,
+                &right_glyph,
+                &parameters,
+                start,
+            )?;
+            Ok(measured_kern(parameters.output(solution)))
+        })
+    }
+
+    #[args(include_non_exporting = "false", kwargs = "**")]
+    fn determine_kerns(
+        &self,
+        py: Python,
+        pairs: Vec<(String, String)>,
+        master_name: String,
+        target_distance: f32,
+        height: i32,
+        max_tuck: f32,
+        include_non_exporting: bool,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<BatchResults> {
+        self.check_open()?;
+        let master = self.master(&master_name)?;
+        let parameters = self
+            .parameters(target_distance, height, max_tuck, kwargs)?
+            .for_master(&self.font, master);
+        let memo = PairMemo::default();
+        let pairs: Vec<(String, String)> = self
+            .working_pairs(pairs)
+            .into_iter()
+            .filter(|(left, right)| {
+                (include_non_exporting || self.exports(left, right))
+                    && parameters.wants_pair(left, right)
+            })
+            .collect();
+        let results: Vec<KernResult> = py.allow_threads(|| {
+            self.pool.install(|| -> PyResult<_> {
+                let keys: Vec<PairKey> = pairs
+                    .par_iter()
+                    .map(|(left, right)| {
+                        let parameters = parameters.for_pair(&self.font, left, right);
+                        Ok(pair_key(
+                            &self.measured(master, left, &parameters)?,
+                            &self.measured(master, right, &parameters)?,
+                            &parameters,
+                            Some(self.start(master, left, right, &parameters)),
+                        ))
+                    })
+                    .collect::<PyResult<_>>()?;
+                let groups = alias_groups(&keys);
+                let solved: Vec<KernResult> = groups
+                    .par_iter()
+                    .map(|group| {
+                        let (left, right) = pairs[group[0]].clone();
+                        let mut timer = PairTimer::start(parameters.timings);
+                        let parameters = parameters.for_pair(&self.font, &left, &right);
+                        let left_outline = self.measured(master, &left, &parameters)?;
+                        let right_outline = self.measured(master, &right, &parameters)?;
+                        timer.converted();
+                        let (solution, trace) = solve_pair(
+                            &memo,
+                            &left_outline,
+                            &right_outline,
+                            &parameters,
+                            self.start(master, &left, &right, &parameters),
+                        );
+                        let rise =
+                            suggested_rise(&left_outline, &right_outline, &parameters, solution);
+                        let solution = parameters.output(solution);
+                        let bare_kern = if parameters.bare_kern && !parameters.marks.is_empty() {
+                            let mut bare = parameters.clone().into_owned();
+                            bare.marks.clear();
+                            let left_outline = self.measured(master, &left, &bare)?;
+                            let right_outline = self.measured(master, &right, &bare)?;
+                            let start = self.start(master, &left, &right, &bare);
+                            let solution = memo.solve(&left_outline, &right_outline, &bare, start);
+                            Some(bare.output(solution).kern)
+                        } else {
+                            None
+                        };
+                        let mut result =
+                            KernResult::new(left, right, master_name.clone(), height, solution);
+                        result.timings = timer.finish();
+                        result.rise = rise;
+                        result.bare_kern = bare_kern;
+                        result.target_scale = parameters.target_scale;
+                        result.record_trace(trace, parameters.trace, parameters.scale);
+                        Ok(result)
+                    })
+                    .collect::<PyResult<_>>()?;
+                Ok(fan_out(&groups, solved, |result, index| {
+                    let (left, right) = pairs[index].clone();
+                    result.aliased(left, right)
+                }))
+            })
+        })?;
+        Ok(BatchResults { results })
+    }
+
+    /// Kerns pairs of classes, where `classes` maps each class name to its
+    /// members; a name in a pair which isn't a class stands for itself.
+    /// Each class pair is kerned by measuring pairs of its members and
+    /// keeping the loosest kern, so that no member measured collides:
+    /// with `members="extremes"` (the default) the members which stand out
+    /// on the class's side of the pair (see `classes::extreme_members`),
+    /// with `"first"` only the first member, and with `"all"` every one.
+    /// Results are named by class, and record the member pair whose kern
+    /// was kept as `decided_by`. The other arguments are as for
+    /// `determine_kerns`.
+    #[args(
+        members = "\"extremes\"",
+        include_non_exporting = "false",
+        kwargs = "**"
+    )]
+    fn determine_class_kerns(
+        &self,
+        py: Python,
+        pairs: Vec<(String, String)>,
+        classes: HashMap<String, Vec<String>>,
+        master_name: String,
+        target_distance: f32,
+        height: i32,
+        max_tuck: f32,
+        members: &str,
+        include_non_exporting: bool,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<BatchResults> {
+        self.check_open()?;
+        if !["extremes", "first", "all"].contains(&members) {
+            return Err(PyValueError::new_err(format!(
+                "Unknown members {:?}: expected \"extremes\", \"first\" or \"all\"",
+                members
+            )));
+        }
+        let master = self.master(&master_name)?;
+        let parameters = self
+            .parameters(target_distance, height, max_tuck, kwargs)?
+            .for_master(&self.font, master);
+        let mut chosen: HashMap<(String, bool), Vec<String>> = HashMap::new();
+        for (left, right) in &pairs {
+            for (class, is_left) in [(left, true), (right, false)].iter() {
+                let key = ((*class).clone(), *is_left);
+                if chosen.contains_key(&key) {
+                    continue;
                 }
-            } else {
-                continue;
+                let all: Vec<String> = classes
+                    .get(*class)
+                    .cloned()
+                    .unwrap_or_else(|| vec![(*class).clone()])
+                    .into_iter()
+                    .map(|g| self.working_name(g))
+                    .collect();
+                let picked = match members {
+                    "first" => all.into_iter().take(1).collect(),
+                    "all" => all,
+                    _ => {
+                        let outlines: Vec<(String, Arc<Outline>)> = all
+                            .into_iter()
+                            .filter_map(|g| {
+                                let outline = self.outlines.get(&self.font, master, &g)?;
+                                Some((g, outline))
+                            })
+                            .collect();
+                        extreme_members(&outlines, *is_left, parameters.dot_size as f64)
+                    }
+                };
+                chosen.insert(key, picked);
             }
+        }
 
-            if let Some(glyph) = font.glyphs.get_by_index_mut(glyph_index) {
-                for (layer, decomposed_paths) in glyph.layers.iter_mut().zip(decomposed_layers) {
-                    for path in decomposed_paths {
-                        layer.push_path(path);
+        let mut member_pairs: Vec<(String, String)> = vec![];
+        for (left, right) in &pairs {
+            for l in &chosen[&(left.clone(), true)] {
+                for r in &chosen[&(right.clone(), false)] {
+                    member_pairs.push((l.clone(), r.clone()));
+                }
+            }
+        }
+        member_pairs.sort();
+        member_pairs.dedup();
+        let measured = self.determine_kerns(
+            py,
+            member_pairs,
+            master_name,
+            target_distance,
+            height,
+            max_tuck,
+            include_non_exporting,
+            kwargs,
+        )?;
+        let by_pair: HashMap<(&str, &str), &KernResult> = measured
+            .results
+            .iter()
+            .map(|r| ((r.left.as_str(), r.right.as_str()), r))
+            .collect();
+
+        let mut results = vec![];
+        for (left, right) in pairs {
+            let mut candidates: Vec<&KernResult> = vec![];
+            for l in &chosen[&(left.clone(), true)] {
+                for r in &chosen[&(right.clone(), false)] {
+                    if let Some(&result) = by_pair.get(&(l.as_str(), r.as_str())) {
+                        candidates.push(result);
                     }
-                    layer.clear_components();
                 }
             }
+            let kept = candidates
+                .iter()
+                .filter(|r| r.status != Status::NoDistance)
+                .max_by(|a, b| a.kern.partial_cmp(&b.kern).unwrap_or(Ordering::Equal))
+                .or_else(|| candidates.first());
+            if let Some(kept) = kept {
+                results.push(KernResult {
+                    decided_by: Some((kept.left.clone(), kept.right.clone())),
+                    left,
+                    right,
+                    ..(*kept).clone()
+                });
+            }
         }
-        KernDeterminer {
-            font,
-            layer_paths: HashMap::new(),
+        Ok(BatchResults { results })
+    }
+
+    /// Checks pairs at the kerns the font already has (which may have been
+    /// set by hand) and reports every pair whose outlines overlap or come
+    /// closer than `threshold`, as a quality check. `height` is the height
+    /// for every pair, a dictionary of heights by `(left, right)` pair (for
+    /// pairs not in it the height is zero), or a function taking the left
+    /// and right glyph names and returning the height. Each finding is a
+    /// dictionary of the pair, its `kern`, `height` and `severity`
+    /// (`"collision"` or `"tight"`), the `distance` between the outlines
+    /// (`None` for collisions) and the `area` they share; collisions come
+    /// first, then the tightest pairs.
+    #[args(height = "None", include_non_exporting = "false", kwargs = "**")]
+    fn audit_collisions<'py>(
+        &self,
+        py: Python<'py>,
+        pairs: Vec<(String, String)>,
+        master_name: String,
+        threshold: f32,
+        height: Option<&PyAny>,
+        include_non_exporting: bool,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<Vec<&'py PyDict>> {
+        self.check_open()?;
+        let master = self.master(&master_name)?;
+        let parameters = self
+            .parameters(0.0, 0, 0.0, kwargs)?
+            .for_master(&self.font, master);
+        let pairs: Vec<(String, String)> = self
+            .working_pairs(pairs)
+            .into_iter()
+            .filter(|(left, right)| {
+                (include_non_exporting || self.exports(left, right))
+                    && parameters.wants_pair(left, right)
+            })
+            .collect();
+        let heights: Vec<i32> = match height {
+            None => vec![0; pairs.len()],
+            Some(height) if height.is_callable() => pairs
+                .iter()
+                .map(|(left, right)| height.call1((left, right))?.extract())
+                .collect::<PyResult<_>>()?,
+            Some(height) => match height.extract::<HashMap<(String, String), i32>>() {
+                Ok(heights) => pairs
+                    .iter()
+                    .map(|pair| heights.get(pair).copied().unwrap_or(0))
+                    .collect(),
+                Err(_) => vec![height.extract()?; pairs.len()],
+            },
+        };
+        let threshold = (threshold * parameters.scale) as f64;
+        let findings: Vec<Option<(String, String, i32, f32, Finding)>> =
+            py.allow_threads(|| {
+                self.pool.install(|| {
+                    pairs
+                        .into_par_iter()
+                        .zip(heights.into_par_iter())
+                        .map(|((left, right), height)| {
+                            let mut parameters =
+                                parameters.for_pair(&self.font, &left, &right).into_owned();
+                            parameters.height = (height as f32 * parameters.scale).round() as i32;
+                            let left_outline = self.measured(master, &left, &parameters)?;
+                            let right_outline = self.measured(master, &right, &parameters)?;
+                            let kern =
+                                existing_kern(&self.font, master, &left, &right).unwrap_or(0.0);
+                            let finding = audit_pair(
+                                &left_outline,
+                                &right_outline,
+                                &parameters,
+                                kern,
+                                threshold,
+                            );
+                            Ok(finding.map(|finding| (left, right, height, kern, finding)))
+                        })
+                        .collect::<PyResult<_>>()
+                })
+            })?;
+        let mut findings: Vec<(String, String, i32, f32, Finding)> =
+            findings.into_iter().flatten().collect();
+        findings.sort_by(|a, b| {
+            b.4.severity
+                .cmp(&a.4.severity)
+                .then(b.4.area.partial_cmp(&a.4.area).unwrap_or(Ordering::Equal))
+                .then(
+                    a.4.distance
+                        .partial_cmp(&b.4.distance)
+                        .unwrap_or(Ordering::Equal),
+                )
+        });
+        let scale = parameters.scale as f64;
+        findings
+            .into_iter()
+            .map(|(left, right, height, kern, finding)| {
+                let dict = PyDict::new(py);
+                dict.set_item("left", left)?;
+                dict.set_item("right", right)?;
+                dict.set_item("kern", kern / parameters.scale)?;
+                dict.set_item("height", height)?;
+                dict.set_item("severity", finding.severity.as_str())?;
+                dict.set_item("distance", finding.distance.map(|d| d / scale))?;
+                dict.set_item(
+                    "area",
+                    finding.area / (scale * scale) / parameters.vertical_scale(),
+                )?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
+    /// Finds the largest uniform negative tracking which can be applied to
+    /// text set from `glyphs` in a master, on top of the kerning the font
+    /// already has, before any pair of them comes closer than `threshold`
+    /// (or touches, by default). Every ordered pair of the glyphs is
+    /// checked, with the left glyph raised by `height`. Returns a
+    /// dictionary of the `tracking` (negative, or positive if some pair is
+    /// already too close) and the `left` and `right` glyphs of the pair
+    /// which limits it, all `None` if there was nothing to measure.
+    #[args(
+        threshold = "0.0",
+        height = "0",
+        include_non_exporting = "false",
+        kwargs = "**"
+    )]
+    fn safe_tracking<'py>(
+        &self,
+        py: Python<'py>,
+        glyphs: Vec<String>,
+        master_name: String,
+        threshold: f32,
+        height: i32,
+        include_non_exporting: bool,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<&'py PyDict> {
+        self.check_open()?;
+        let master = self.master(&master_name)?;
+        let parameters = self
+            .parameters(0.0, height, 0.0, kwargs)?
+            .for_master(&self.font, master);
+        let threshold = threshold * parameters.scale;
+        let glyphs: Vec<String> = glyphs.into_iter().map(|g| self.working_name(g)).collect();
+        let pairs: Vec<(&String, &String)> = glyphs
+            .iter()
+            .flat_map(|left| glyphs.iter().map(move |right| (left, right)))
+            .filter(|(left, right)| {
+                (include_non_exporting || self.exports(left, right))
+                    && parameters.wants_pair(left, right)
+            })
+            .collect();
+        let rooms: Vec<Option<(f32, &String, &String)>> = py.allow_threads(|| {
+            self.pool.install(|| {
+                pairs
+                    .into_par_iter()
+                    .map(|(left, right)| {
+                        let parameters = parameters.for_pair(&self.font, left, right);
+                        let left_outline = self.measured(master, left, &parameters)?;
+                        let right_outline = self.measured(master, right, &parameters)?;
+                        let kern = existing_kern(&self.font, master, left, right).unwrap_or(0.0);
+                        let room = tracking_room(
+                            &left_outline,
+                            &right_outline,
+                            &parameters,
+                            kern,
+                            threshold,
+                        );
+                        Ok(room.map(|room| (room, left, right)))
+                    })
+                    .collect::<PyResult<_>>()
+            })
+        })?;
+        let tightest = rooms
+            .into_iter()
+            .flatten()
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        let dict = PyDict::new(py);
+        dict.set_item("tracking", tightest.map(|t| t.0 / parameters.scale))?;
+        dict.set_item("left", tightest.map(|t| t.1))?;
+        dict.set_item("right", tightest.map(|t| t.2))?;
+        Ok(dict)
+    }
+
+    /// As `determine_kern`, but for a pair of characters, which are looked
+    /// up in the font's character map.
+    #[args(kwargs = "**")]
+    fn determine_kern_for_chars(
+        &self,
+        py: Python,
+        left_char: String,
+        right_char: String,
+        master_name: String,
+        target_distance: f32,
+        height: i32,
+        max_tuck: f32,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<Option<f32>> {
+        self.check_open()?;
+        let cmap = self.cmap();
+        let left_glyph = glyph_for_char(&cmap, &left_char)?;
+        let right_glyph = glyph_for_char(&cmap, &right_char)?;
+        self.determine_kern(
+            py,
+            left_glyph,
+            right_glyph,
+            master_name,
+            target_distance,
+            height,
+            max_tuck,
+            kwargs,
+        )
+    }
+
+    /// As `determine_kerns`, but for pairs of characters. The results are
+    /// named by glyph.
+    #[args(include_non_exporting = "false", kwargs = "**")]
+    fn determine_kerns_for_chars(
+        &self,
+        py: Python,
+        pairs: Vec<(String, String)>,
+        master_name: String,
+        target_distance: f32,
+        height: i32,
+        max_tuck: f32,
+        include_non_exporting: bool,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<BatchResults> {
+        self.check_open()?;
+        let cmap = self.cmap();
+        let pairs = pairs
+            .iter()
+            .map(|(left, right)| Ok((glyph_for_char(&cmap, left)?, glyph_for_char(&cmap, right)?)))
+            .collect::<PyResult<Vec<_>>>()?;
+        self.determine_kerns(
+            py,
+            pairs,
+            master_name,
+            target_distance,
+            height,
+            max_tuck,
+            include_non_exporting,
+            kwargs,
+        )
+    }
+
+    /// Kerns each pair in every given master (or all masters). Each master
+    /// starts from the kern found in the previous one, since values are
+    /// usually close across masters.
+    #[args(include_non_exporting = "false", kwargs = "**")]
+    fn determine_kerns_for_masters(
+        &self,
+        py: Python,
+        pairs: Vec<(String, String)>,
+        master_names: Option<Vec<String>>,
+        target_distance: f32,
+        height: i32,
+        max_tuck: f32,
+        include_non_exporting: bool,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<BatchResults> {
+        self.check_open()?;
+        let masters = self.selected_masters(master_names)?;
+        let parameters = self.parameters(target_distance, height, max_tuck, kwargs)?;
+        let per_master: Vec<Parameters> = masters
+            .iter()
+            .map(|m| parameters.for_master(&self.font, m))
+            .collect();
+        let memo = PairMemo::default();
+        let pairs: Vec<(String, String)> = self
+            .working_pairs(pairs)
+            .into_iter()
+            .filter(|(left, right)| {
+                (include_non_exporting || self.exports(left, right))
+                    && parameters.wants_pair(left, right)
+            })
+            .collect();
+        let results: Vec<Vec<KernResult>> = py.allow_threads(|| {
+            self.pool.install(|| -> PyResult<_> {
+                // Each master starts from the last, so pairs are only
+                // aliases if they are in every master.
+                let keys: Vec<Vec<PairKey>> = pairs
+                    .par_iter()
+                    .map(|(left, right)| {
+                        masters
+                            .iter()
+                            .zip(per_master.iter())
+                            .map(|(master, parameters)| {
+                                let parameters = parameters.for_pair(&self.font, left, right);
+                                Ok(pair_key(
+                                    &self.measured(master, left, &parameters)?,
+                                    &self.measured(master, right, &parameters)?,
+                                    &parameters,
+                                    parameters.initial_kern_for(left, right).or_else(|| {
+                                        self.existing_kern(master, left, right, &parameters)
+                                    }),
+                                ))
+                            })
+                            .collect()
+                    })
+                    .collect::<PyResult<_>>()?;
+                let groups = alias_groups(&keys);
+                let solved: Vec<Vec<KernResult>> = groups
+                    .par_iter()
+                    .map(|group| {
+                        let (left, right) = &pairs[group[0]];
+                        let mut results = vec![];
+                        let mut start = 0.0;
+                        for (master, parameters) in masters.iter().zip(per_master.iter()) {
+                            let mut timer = PairTimer::start(parameters.timings);
+                            let parameters = parameters.for_pair(&self.font, left, right);
+                            let left_outline = self.measured(master, left, &parameters)?;
+                            let right_outline = self.measured(master, right, &parameters)?;
+                            timer.converted();
+                            let (solution, trace) = solve_pair(
+                                &memo,
+                                &left_outline,
+                                &right_outline,
+                                &parameters,
+                                parameters
+                                    .initial_kern_for(left, right)
+                                    .or_else(|| {
+                                        self.existing_kern(master, left, right, &parameters)
+                                    })
+                                    .unwrap_or(start),
+                            );
+                            if solution.status.converged() {
+                                start = solution.kern;
+                            }
+                            let rise = suggested_rise(
+                                &left_outline,
+                                &right_outline,
+                                &parameters,
+                                solution,
+                            );
+                            let mut result = KernResult::new(
+                                left.clone(),
+                                right.clone(),
+                                master_name(master),
+                                height,
+                                parameters.output(solution),
+                            );
+                            result.timings = timer.finish();
+                            result.rise = rise;
+                            result.target_scale = parameters.target_scale;
+                            result.record_trace(trace, parameters.trace, parameters.scale);
+                            results.push(result);
+                        }
+                        Ok(results)
+                    })
+                    .collect::<PyResult<_>>()?;
+                Ok(fan_out(&groups, solved, |results, index| {
+                    let (left, right) = &pairs[index];
+                    results
+                        .iter()
+                        .map(|r| r.aliased(left.clone(), right.clone()))
+                        .collect()
+                }))
+            })
+        })?;
+        let results = results.into_iter().flatten().collect();
+        Ok(BatchResults { results })
+    }
+
+    /// Copies the kerns in `results` to pairs of composite glyphs built on
+    /// the same bases, flagging those whose marks mean they should be
+    /// measured properly. The other parameters are as for the run which
+    /// produced the results.
+    #[args(include_non_exporting = "false", kwargs = "**")]
+    fn propagate_to_composites(
+        &self,
+        py: Python,
+        results: PyRef<BatchResults>,
+        target_distance: f32,
+        include_non_exporting: bool,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<BatchResults> {
+        self.check_open()?;
+        let mut parameters = HashMap::new();
+        for result in results.results.iter() {
+            if !parameters.contains_key(&result.height) {
+                parameters.insert(
+                    result.height,
+                    self.parameters(target_distance, result.height, 0.0, kwargs)?,
+                );
+            }
         }
+        let filter = self.parameters(target_distance, 0, 0.0, kwargs)?;
+        let results = &results.results;
+        let results = py.allow_threads(|| {
+            composite::propagate(
+                &self.font,
+                &self.source,
+                &self.outlines,
+                results,
+                &parameters,
+                |left, right| {
+                    (include_non_exporting || self.exports(left, right))
+                        && filter.wants_pair(left, right)
+                },
+            )
+        });
+        Ok(BatchResults { results })
     }
 
-    fn determine_kern(
+    /// Brings kerns which would put a kink in the interpolated kern back
+    /// into line with the pair's kerns in neighbouring masters, or with
+    /// `flag_only` just marks them (see `smoothing::smooth_across_masters`).
+    /// Kerns within `tolerance` units of their neighbours' range are left
+    /// alone.
+    #[args(tolerance = "0.0", flag_only = "false")]
+    fn smooth_across_masters(
         &self,
+        results: PyRef<BatchResults>,
+        tolerance: f32,
+        flag_only: bool,
+    ) -> PyResult<BatchResults> {
+        self.check_open()?;
+        let locations: HashMap<String, Vec<f32>> = self
+            .font
+            .masters
+            .iter()
+            .map(|master| {
+                let location = self
+                    .font
+                    .axes
+                    .iter()
+                    .map(|axis| {
+                        let name = axis.name.get_default().map(|n| n.to_string());
+                        name.and_then(|n| master.location.0.get(&n).copied())
+                            .or(axis.default)
+                            .unwrap_or(0.0)
+                    })
+                    .collect();
+                (master_name(master), location)
+            })
+            .collect();
+        Ok(BatchResults {
+            results: smoothing::smooth_across_masters(
+                &results.results,
+                &locations,
+                tolerance,
+                flag_only,
+            ),
+        })
+    }
+
+    /// Suggests changes to sidebearings which would take up the part of
+    /// each glyph's kerns that it shares with most of its partners, keyed
+    /// by `(glyph, master)`. Each suggestion has the `lsb_change` and
+    /// `rsb_change`, and the new `lsb` and `rsb` these would give.
+    #[args(min_partners = "5")]
+    fn suggest_sidebearings<'py>(
+        &self,
+        py: Python<'py>,
+        results: PyRef<BatchResults>,
+        min_partners: usize,
+    ) -> PyResult<&'py PyDict> {
+        self.check_open()?;
+        let changes = spacing::sidebearing_changes(&results.results, min_partners);
+        let suggestions = PyDict::new(py);
+        for ((glyph, master), change) in changes {
+            let suggestion = PyDict::new(py);
+            suggestion.set_item("lsb_change", change.lsb)?;
+            suggestion.set_item("rsb_change", change.rsb)?;
+            let outline = self
+                .font
+                .master(&master)
+                .and_then(|m| self.outlines.get(&self.font, m, &glyph));
+            if let Some(bounds) = outline.as_ref().and_then(|o| o.bounds()) {
+                let rsb = outline.map_or(0.0, |o| o.width) - bounds.x1 as f32;
+                suggestion.set_item("lsb", (bounds.x0 as f32 + change.lsb).round())?;
+                suggestion.set_item("rsb", (rsb + change.rsb).round())?;
+            }
+            suggestions.set_item((glyph, master), suggestion)?;
+        }
+        Ok(suggestions)
+    }
+
+    /// Describes how the kern for a pair was arrived at: the steps the
+    /// solver took, the tuck limit, and where the outlines come closest at
+    /// the kern it chose.
+    #[args(kwargs = "**")]
+    fn explain<'py>(
+        &self,
+        py: Python<'py>,
         left_glyph: String,
         right_glyph: String,
         master_name: String,
         target_distance: f32,
         height: i32,
         max_tuck: f32,
-    ) -> PyResult<f32> {
-        let master = self
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<&'py PyDict> {
+        self.check_open()?;
+        let master = self.master(&master_name)?;
+        let (left_glyph, right_glyph) = (
+            self.working_name(left_glyph),
+            self.working_name(right_glyph),
+        );
+        let parameters = self
+            .parameters(target_distance, height, max_tuck, kwargs)?
+            .for_master(&self.font, master);
+        let parameters = parameters.for_pair(&self.font, &left_glyph, &right_glyph);
+        let left = self.measured(master, &left_glyph, &parameters)?;
+        let right = self.measured(master, &right_glyph, &parameters)?;
+        let explanation = py.allow_threads(|| Explanation::new(&left, &right, &parameters));
+        explanation.to_dict(py)
+    }
+
+    /// Works out where a base glyph's anchor should go so that a mark
+    /// attached to it clears the base's ink by `target_distance`. The mark
+    /// attaches by its `_` + `anchor` anchor and slides `direction`
+    /// (`"above"`, `"below"`, `"left"` or `"right"`) from where the base's
+    /// anchor is now, or from where the mark is drawn if the base has no
+    /// such anchor. Returns the new anchor position as `x` and `y`, the
+    /// `offset` it moved by, the `distance` between the inks there and
+    /// whether the target was `converged` on.
+    #[args(anchor = "\"top\"", direction = "\"above\"", kwargs = "**")]
+    fn place_anchor<'py>(
+        &self,
+        py: Python<'py>,
+        base_glyph: String,
+        mark_glyph: String,
+        master_name: String,
+        target_distance: f32,
+        anchor: &str,
+        direction: &str,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<&'py PyDict> {
+        self.check_open()?;
+        let direction = match direction {
+            "above" => Vec2::new(0.0, 1.0),
+            "below" => Vec2::new(0.0, -1.0),
+            "left" => Vec2::new(-1.0, 0.0),
+            "right" => Vec2::new(1.0, 0.0),
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown direction {:}",
+                    other
+                )))
+            }
+        };
+        let (base_glyph, mark_glyph) =
+            (self.working_name(base_glyph), self.working_name(mark_glyph));
+        let master = self.master(&master_name)?;
+        let parameters = self.parameters(target_distance, 0, 0.0, kwargs)?;
+        let base = self.outline(master, &base_glyph)?;
+        let mark = self.outline(master, &mark_glyph)?;
+        let anchors_of = |glyph: &str| self.font.anchors(glyph, &master.id).unwrap_or_default();
+        let mark_anchor = *anchors_of(&mark_glyph)
+            .get(&format!("_{:}", anchor))
+            .ok_or_else(|| {
+                PyKeyError::new_err(format!("{:} has no _{:} anchor", mark_glyph, anchor))
+            })?;
+        let start = anchors_of(&base_glyph)
+            .get(anchor)
+            .copied()
+            .unwrap_or(mark_anchor);
+        let placement = py
+            .allow_threads(|| {
+                anchors::place_mark(&base, &mark, start, mark_anchor, direction, &parameters)
+            })
+            .ok_or_else(|| PyValueError::new_err("Both glyphs need outlines"))?;
+        let scale = parameters.scale as f64;
+        let dict = PyDict::new(py);
+        dict.set_item("x", (placement.anchor.x / scale).round())?;
+        dict.set_item("y", (placement.anchor.y / scale).round())?;
+        dict.set_item("offset", placement.offset / scale)?;
+        dict.set_item("distance", placement.distance / scale)?;
+        dict.set_item("converged", placement.converged)?;
+        Ok(dict)
+    }
+
+    /// The geometry an editor plugin needs to draw a pair at `kern`: both
+    /// outlines as point lists, the closest points and the distance between
+    /// them, and the tuck limit line.
+    #[args(kwargs = "**")]
+    fn preview<'py>(
+        &self,
+        py: Python<'py>,
+        left_glyph: String,
+        right_glyph: String,
+        master_name: String,
+        kern: f32,
+        target_distance: f32,
+        height: i32,
+        max_tuck: f32,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<&'py PyDict> {
+        self.check_open()?;
+        let master = self.master(&master_name)?;
+        let (left_glyph, right_glyph) = (
+            self.working_name(left_glyph),
+            self.working_name(right_glyph),
+        );
+        let parameters = self
+            .parameters(target_distance, height, max_tuck, kwargs)?
+            .for_master(&self.font, master);
+        let parameters = parameters.for_pair(&self.font, &left_glyph, &right_glyph);
+        let left = self.measured(master, &left_glyph, &parameters)?;
+        let right = self.measured(master, &right_glyph, &parameters)?;
+        preview::preview(py, &left, &right, &parameters, kern * parameters.scale)
+    }
+
+    /// Lays out a sequence of glyphs in a master as the determiner
+    /// understands them, for proofing whole words. Kerns come from `kerns`,
+    /// which may be batch results (whose rises are applied too) or a
+    /// dictionary mapping `(left, right)` pairs to kerns, or else from the
+    /// font's own kerning. See `preview::layout_preview` for what is
+    /// returned.
+    #[args(kerns = "None", cursive = "false", kwargs = "**")]
+    fn layout_preview<'py>(
+        &self,
+        py: Python<'py>,
+        glyph_sequence: Vec<String>,
+        master_name: String,
+        kerns: Option<&PyAny>,
+        cursive: bool,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<Vec<&'py PyDict>> {
+        self.check_open()?;
+        let glyph_sequence: Vec<String> = glyph_sequence
+            .into_iter()
+            .map(|name| self.working_name(name))
+            .collect();
+        let master = self.master(&master_name)?;
+        let parameters = self
+            .parameters(0.0, 0, 0.0, kwargs)?
+            .for_master(&self.font, master);
+        let from_font = kerns.is_none();
+        let mut given: HashMap<(String, String), (f32, Option<f32>)> = HashMap::new();
+        if let Some(kerns) = kerns {
+            if let Ok(results) = kerns.extract::<PyRef<BatchResults>>() {
+                for r in results.results.iter().filter(|r| r.master == master_name) {
+                    let pair = (
+                        self.working_name(r.left.clone()),
+                        self.working_name(r.right.clone()),
+                    );
+                    given.entry(pair).or_insert((r.kern, r.rise));
+                }
+            } else {
+                let kerns: HashMap<(String, String), f32> = kerns.extract().map_err(|_| {
+                    PyTypeError::new_err(
+                        "kerns must be batch results or a dictionary of (left, right) pairs",
+                    )
+                })?;
+                given.extend(
+                    self.working_pairs(kerns.keys().cloned().collect())
+                        .into_iter()
+                        .zip(kerns.values().map(|&kern| (kern, None))),
+                );
+            }
+        }
+        let outlines: Vec<Arc<Outline>> = glyph_sequence
+            .iter()
+            .map(|name| self.outline(master, name))
+            .collect::<PyResult<_>>()?;
+        let glyphs: Vec<(String, &Outline)> = glyph_sequence
+            .iter()
+            .cloned()
+            .zip(outlines.iter().map(|o| o.as_ref()))
+            .collect();
+        let (kerns, rises): (Vec<f32>, Vec<f32>) = glyph_sequence
+            .windows(2)
+            .map(|pair| {
+                let key = (pair[0].clone(), pair[1].clone());
+                match given.get(&key) {
+                    Some((kern, rise)) => (
+                        kern * parameters.scale,
+                        rise.unwrap_or(0.0) * parameters.scale,
+                    ),
+                    None if !from_font => (0.0, 0.0),
+                    None => (
+                        existing_kern(&self.font, master, &pair[0], &pair[1]).unwrap_or(0.0),
+                        0.0,
+                    ),
+                }
+            })
+            .unzip();
+        preview::layout_preview(py, &glyphs, &kerns, &rises, cursive, &parameters)
+    }
+
+    /// Samples the distance between a pair's outlines at kerns from
+    /// `kern_range.0` to `kern_range.1` in steps of `step`, returning
+    /// `(kern, distance)` tuples.
+    #[args(height = "0", kwargs = "**")]
+    fn kern_profile(
+        &self,
+        py: Python,
+        left_glyph: String,
+        right_glyph: String,
+        master_name: String,
+        kern_range: (f32, f32),
+        step: f32,
+        height: i32,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<Vec<(f32, Option<f32>)>> {
+        self.check_open()?;
+        if step <= 0.0 {
+            return Err(PyValueError::new_err("step must be positive"));
+        }
+        let (left_glyph, right_glyph) = (
+            self.working_name(left_glyph),
+            self.working_name(right_glyph),
+        );
+        let master = self.master(&master_name)?;
+        let parameters = self
+            .parameters(0.0, height, 0.0, kwargs)?
+            .for_master(&self.font, master);
+        let scale = parameters.scale;
+        let (start, end) = kern_range;
+        let kerns: Vec<f32> = (0..)
+            .map(|i| start + i as f32 * step)
+            .take_while(|k| *k <= end)
+            .map(|k| k * scale)
+            .collect();
+        let left = self.measured(master, &left_glyph, &parameters)?;
+        let right = self.measured(master, &right_glyph, &parameters)?;
+        let profile = py.allow_threads(|| profile(&left, &right, &parameters, &kerns));
+        Ok(profile
+            .into_iter()
+            .map(|(kern, distance)| (kern / scale, distance.map(|d| d / scale)))
+            .collect())
+    }
+
+    /// Measures the gaps between a glyph's ink and a vertical line `x`
+    /// units from its origin, on the `side` (`"left"` or `"right"`) of the
+    /// ink the line is on, for optical margins and the spacing of the first
+    /// and last glyphs of a line. Returns a dictionary of the closest
+    /// `distance` to the line (negative if the ink crosses it), the `target`
+    /// and `y` of the row which falls furthest short of its target, and the
+    /// `profile` of `(y, gap)` for every row with ink. Returns `None` if the
+    /// glyph has no ink.
+    #[args(side = "\"right\"", kwargs = "**")]
+    fn distance_to_line<'py>(
+        &self,
+        py: Python<'py>,
+        glyph_name: String,
+        master_name: String,
+        x: f32,
+        side: &str,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<Option<&'py PyDict>> {
+        self.check_open()?;
+        let side = match side {
+            "left" => Side::Left,
+            "right" => Side::Right,
+            _ => return Err(PyValueError::new_err("side must be 'left' or 'right'")),
+        };
+        let glyph_name = self.working_name(glyph_name);
+        let master = self.master(&master_name)?;
+        let parameters = self
+            .parameters(0.0, 0, 0.0, kwargs)?
+            .for_master(&self.font, master);
+        let outline = self.measured(master, &glyph_name, &parameters)?;
+        let x = (x * parameters.scale) as f64;
+        let gap = match py.allow_threads(|| line_gap(&outline.paths, x, side, &parameters)) {
+            Some(gap) => gap,
+            None => return Ok(None),
+        };
+        let scale = parameters.scale as f64;
+        let y_scale = scale * parameters.vertical_scale();
+        let result = PyDict::new(py);
+        result.set_item("distance", gap.distance / scale)?;
+        if let Some((y, m)) = gap.critical {
+            result.set_item("y", y / y_scale)?;
+            result.set_item("target", m.target / scale)?;
+        }
+        let profile: Vec<(f64, f64)> = gap
+            .profile
+            .iter()
+            .map(|(y, g)| (y / y_scale, g / scale))
+            .collect();
+        result.set_item("profile", profile)?;
+        Ok(Some(result))
+    }
+
+    /// Measures each glyph against a flat margin on either side, as if the
+    /// margin were its neighbour (see `gap::margin_room`), and returns a
+    /// dictionary of its `(lfbd, rtbd)` optical bounds: how far its optical
+    /// left edge sits right of its origin, and its optical right edge left
+    /// of its advance. Glyphs with no ink are left out. With `reference`,
+    /// bounds are given relative to that glyph's, so that it (say, a glyph
+    /// with flat stems) has none and the rest are simple margin kerns.
+    #[args(target_distance = "0.0", reference = "None", kwargs = "**")]
+    fn optical_bounds(
+        &self,
+        py: Python,
+        glyphs: Vec<String>,
+        master_name: String,
+        target_distance: f32,
+        reference: Option<String>,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<HashMap<String, (f32, f32)>> {
+        self.check_open()?;
+        let master = self.master(&master_name)?;
+        let parameters = self
+            .parameters(target_distance, 0, 0.0, kwargs)?
+            .for_master(&self.font, master);
+        let bounds = |glyph_name: &str| -> PyResult<Option<(f64, f64)>> {
+            let outline = self.measured(master, glyph_name, &parameters)?;
+            let room = |x: f64, side| margin_room(&outline.paths, x, side, &parameters);
+            Ok(room(0.0, Side::Left).zip(room(outline.width as f64, Side::Right)))
+        };
+        let glyphs: Vec<String> = glyphs.into_iter().map(|g| self.working_name(g)).collect();
+        let reference_name = reference.map(|r| self.working_name(r));
+        let (measured, reference) = py.allow_threads(|| {
+            self.pool.install(|| -> PyResult<_> {
+                let measured: Vec<Option<(String, (f64, f64))>> = glyphs
+                    .par_iter()
+                    .map(|g| Ok(bounds(g)?.map(|b| (g.clone(), b))))
+                    .collect::<PyResult<_>>()?;
+                let reference = match reference_name.as_deref() {
+                    Some(name) => bounds(name)?,
+                    None => None,
+                };
+                Ok((measured, reference))
+            })
+        })?;
+        if let (Some(name), None) = (&reference_name, reference) {
+            return Err(PyValueError::new_err(format!(
+                "The reference glyph {:} has no ink",
+                name
+            )));
+        }
+        let (left, right) = reference.unwrap_or((0.0, 0.0));
+        let scale = parameters.scale as f64;
+        Ok(measured
+            .into_iter()
+            .flatten()
+            .map(|(g, (l, r))| {
+                (
+                    g,
+                    (((l - left) / scale) as f32, ((r - right) / scale) as f32),
+                )
+            })
+            .collect())
+    }
+
+    /// Writes `lfbd` and `rtbd` features from the optical bounds
+    /// `optical_bounds` returns, in font units.
+    #[staticmethod]
+    fn optical_bounds_fea(bounds: HashMap<String, (f32, f32)>) -> String {
+        optical_bounds_fea(&bounds.into_iter().collect())
+    }
+
+    /// The outline of a glyph's layer given by its ID, which may be any
+    /// layer (a brace layer or an alternate, say) rather than a master's,
+    /// for measuring below the master level, as with
+    /// `determine_kern_for_outlines`. Components are decomposed in the
+    /// named master, or by default the master with the layer's ID, or
+    /// failing that the first master. Kerning layers are not substituted.
+    #[args(master_name = "None")]
+    fn layer_outline(
+        &self,
+        glyph_name: &str,
+        layer_id: &str,
+        master_name: Option<&str>,
+    ) -> PyResult<Outline> {
+        self.check_open()?;
+        let glyph_name = &self.working_name(glyph_name.to_string());
+        if !self.font.has_glyph(glyph_name) {
+            return Err(PyKeyError::new_err(format!(
+                "Couldn't find glyph {:}{}",
+                glyph_name,
+                did_you_mean(
+                    glyph_name,
+                    self.font.glyph_names().iter().map(|g| g.as_str())
+                )
+            )));
+        }
+        let master = self.layer_master(layer_id, master_name)?;
+        let layer = self
             .font
-            .master(&master_name)
-            .unwrap_or_else(|| panic!("Couldn't find master {:}", master_name));
-        Ok(_determine_kern(
+            .layer_with_id(glyph_name, layer_id, &master.id)
+            .ok_or_else(|| {
+                PyKeyError::new_err(format!(
+                    "Glyph {:} has no layer with ID {:}",
+                    glyph_name, layer_id
+                ))
+            })?;
+        let mut outline =
+            Outline::from_glyph_layer(glyph_name, master, layer).map_err(PyValueError::new_err)?;
+        if self.outlines.normalizes_direction() {
+            outline.paths = normalize_direction(outline.paths);
+        }
+        Ok(outline)
+    }
+
+    /// The distance between two layers (given by glyph name and layer ID,
+    /// as for `layer_outline`) set side by side with the given kern, with
+    /// the left raised by `height`, or `None` if there is nothing to
+    /// measure. Keyword arguments are as for `determine_kern`, though the
+    /// optional marks are not attached.
+    #[args(kern = "0.0", height = "0", master_name = "None", kwargs = "**")]
+    fn distance_between_layers(
+        &self,
+        py: Python,
+        left_glyph: &str,
+        left_layer_id: &str,
+        right_glyph: &str,
+        right_layer_id: &str,
+        kern: f32,
+        height: i32,
+        master_name: Option<&str>,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<Option<f64>> {
+        self.check_open()?;
+        let left = self.layer_outline(left_glyph, left_layer_id, master_name)?;
+        let right = self.layer_outline(right_glyph, right_layer_id, master_name)?;
+        let master = self.layer_master(left_layer_id, master_name)?;
+        let parameters = self
+            .parameters(0.0, height, 0.0, kwargs)?
+            .for_master(&self.font, master);
+        let measured = |outline: Outline| {
+            let scale =
+                |cutoff: Option<f32>| cutoff.map(|c| c as f64 * parameters.vertical_scale());
+            outline
+                .transformed(parameters.shear, parameters.vertical_scale())
+                .clipped(
+                    scale(parameters.ignore_below),
+                    scale(parameters.ignore_above),
+                )
+        };
+        let (left, right) = (measured(left), measured(right));
+        let kern = kern * parameters.scale;
+        let measurement = py.allow_threads(|| measure_at(&left, &right, &parameters, kern));
+        Ok(measurement.map(|m| m.distance / parameters.scale as f64))
+    }
+
+    /// The area (in square units) shared by a pair's outlines at the given
+    /// kern, or zero if they do not overlap.
+    #[args(height = "0", kwargs = "**")]
+    fn overlap_area(
+        &self,
+        py: Python,
+        left_glyph: String,
+        right_glyph: String,
+        master_name: String,
+        kern: f32,
+        height: i32,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<f64> {
+        self.check_open()?;
+        let (left_glyph, right_glyph) = (
+            self.working_name(left_glyph),
+            self.working_name(right_glyph),
+        );
+        let master = self.master(&master_name)?;
+        let parameters = self
+            .parameters(0.0, height, 0.0, kwargs)?
+            .for_master(&self.font, master);
+        let left = self.measured(master, &left_glyph, &parameters)?;
+        let right = self.measured(master, &right_glyph, &parameters)?;
+        let x_offset = kern * parameters.scale + left.width;
+        let area = py.allow_threads(|| {
+            overlap::overlap_area(
+                &left.paths,
+                &right.paths,
+                x_offset,
+                parameters.left_offset(&left) as f32,
+            )
+        });
+        Ok(area / (parameters.scale as f64).powi(2) / parameters.vertical_scale())
+    }
+
+    /// Returns the outline measured for a glyph as an SVG path data string.
+    /// The outline is moved right by `kern`, and raised as the left glyph
+    /// of a pair would be at `height`.
+    #[args(kern = "0.0", height = "0")]
+    fn glyph_svg_path(
+        &self,
+        glyph_name: String,
+        master_name: String,
+        kern: f32,
+        height: i32,
+    ) -> PyResult<String> {
+        self.check_open()?;
+        let glyph_name = self.working_name(glyph_name);
+        let outline = self.outline(self.master(&master_name)?, &glyph_name)?;
+        let transform = Affine::translate(Vec2::new(
+            kern as f64,
+            outline.vertical_offset(height, EXIT_ANCHOR),
+        ));
+        let paths: Vec<BezPath> = outline.paths.iter().map(|p| transform * p).collect();
+        Ok(svg_path(&paths))
+    }
+
+    fn proof_html(&self, results: PyRef<BatchResults>) -> PyResult<String> {
+        self.check_open()?;
+        Ok(proof::proof_html(
             &self.font,
-            master,
-            &left_glyph,
-            &right_glyph,
-            target_distance,
-            height,
-            max_tuck,
+            &self.outlines,
+            &results.results,
         ))
     }
 }
 
-fn _determine_kern(
-    font: &Font,
-    master: &Master,
-    left_glyph: &str,
-    right_glyph: &str,
-    target_distance: f32,
-    height: i32,
-    max_tuck: f32,
-) -> f32 {
-    let layer_1 = font
-        .master_layer_for(left_glyph, master)
-        .unwrap_or_else(|| panic!("{}", format!("Couldn't find glyph {:}", left_glyph)));
-    let layer_2 = font
-        .master_layer_for(right_glyph, master)
-        .unwrap_or_else(|| panic!("{}", format!("Couldn't find glyph {:}", right_glyph)));
-
-    // Get exit anchor
-    let lexit = layer_1
-        .anchors
+impl KernDeterminer {
+    fn with_source(
+        source: Font,
+        filename: Option<String>,
+        load_time: f64,
+        glyphs: Option<Vec<String>>,
+        threads: Option<usize>,
+        normalize_direction: bool,
+        renames: HashMap<String, String>,
+    ) -> PyResult<Self> {
+        // Several determiners may be created in one process.
+        let _ = env_logger::try_init();
+        let font = subset(&source, glyphs.clone());
+        let working_names = working_names(&font, &renames);
+        let guides = master_guides(&font);
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads.unwrap_or(0))
+            .build()
+            .map_err(|e| PyRuntimeError::new_err(format!("Couldn't start threads: {:}", e)))?;
+        Ok(KernDeterminer {
+            font,
+            source,
+            filename,
+            glyph_subset: glyphs,
+            closed: false,
+            working_names,
+            renames,
+            guides,
+            load_time,
+            outlines: OutlineCache::new(normalize_direction),
+            pool,
+        })
+    }
+
+    fn check_open(&self) -> PyResult<()> {
+        if self.closed {
+            return Err(PyValueError::new_err("The determiner has been closed"));
+        }
+        Ok(())
+    }
+
+    /// The working name for a glyph named in a call: the name itself if
+    /// the font has such a glyph, or else the glyph it is another name
+    /// for, if any.
+    fn working_name(&self, glyph_name: String) -> String {
+        if self.font.has_glyph(&glyph_name) {
+            return glyph_name;
+        }
+        self.working_names
+            .get(&glyph_name)
+            .cloned()
+            .unwrap_or(glyph_name)
+    }
+
+    fn working_pairs(&self, pairs: Vec<(String, String)>) -> Vec<(String, String)> {
+        pairs
+            .into_iter()
+            .map(|(left, right)| (self.working_name(left), self.working_name(right)))
+            .collect()
+    }
+
+    fn master(&self, master_name: &str) -> PyResult<&Master> {
+        self.font.master_named(master_name).ok_or_else(|| {
+            let names: Vec<String> = self.font.masters().iter().map(crate::master_name).collect();
+            PyKeyError::new_err(format!(
+                "Couldn't find master {:}{}",
+                master_name,
+                did_you_mean(master_name, names.iter().map(|n| n.as_str()))
+            ))
+        })
+    }
+
+    /// The master a layer's components are decomposed in: the one named,
+    /// or the one with the layer's ID, or failing that the first.
+    fn layer_master(&self, layer_id: &str, master_name: Option<&str>) -> PyResult<&Master> {
+        match master_name {
+            Some(name) => self.master(name),
+            None => self
+                .font
+                .masters()
+                .iter()
+                .find(|m| m.id == layer_id)
+                .or_else(|| self.font.masters().first())
+                .ok_or_else(|| PyValueError::new_err("The font has no masters")),
+        }
+    }
+
+    /// A glyph's outline in a master. A glyph the font doesn't have in
+    /// the master is a `KeyError`, and one which can't be converted a
+    /// `ValueError`.
+    fn outline(&self, master: &Master, glyph_name: &str) -> PyResult<Arc<Outline>> {
+        match self.outlines.try_get(&self.font, master, glyph_name) {
+            Some(outline) => outline.map_err(PyValueError::new_err),
+            None => Err(PyKeyError::new_err(missing_glyph(
+                &self.font, master, glyph_name,
+            ))),
+        }
+    }
+
+    /// The outline measured for a glyph, sheared upright if the
+    /// parameters remove an italic angle. Errors are as for `outline`.
+    fn measured(
+        &self,
+        master: &Master,
+        glyph_name: &str,
+        parameters: &Parameters,
+    ) -> PyResult<Arc<Outline>> {
+        match self
+            .outlines
+            .try_get_measured(&self.font, master, glyph_name, parameters)
+        {
+            Some(outline) => outline.map_err(PyValueError::new_err),
+            None => Err(PyKeyError::new_err(missing_glyph(
+                &self.font, master, glyph_name,
+            ))),
+        }
+    }
+
+    /// Builds the parameters for a call, converting any per-mille values
+    /// into this font's units.
+    fn parameters(
+        &self,
+        target_distance: f32,
+        height: i32,
+        max_tuck: f32,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<Parameters> {
+        let mut parameters = Parameters::from_python(target_distance, height, max_tuck, kwargs)?
+            .for_upm(Some(self.font.units_per_em() as f32))?;
+        if !parameters.target_by_opsz.is_empty() || !parameters.max_tuck_by_opsz.is_empty() {
+            let axis = self
+                .font
+                .axis_name("opsz")
+                .ok_or_else(|| PyValueError::new_err("The font has no optical size axis"))?;
+            parameters.opsz_axis = Some(axis);
+        }
+        if parameters.reference_bands {
+            parameters.master_heights = self.reference_heights();
+        }
+        if parameters.guidelines {
+            parameters.master_guides = self.guides.clone();
+        }
+        if let Some(reference) = parameters.scale_by.as_ref() {
+            parameters.master_scales = self.master_scales(reference, parameters.scale_strength)?;
+        }
+        Ok(parameters)
+    }
+
+    /// Each master's x-height and figure bounds (keyed by master ID),
+    /// measured from the top of `x` and the bounds of `zero`, for
+    /// `reference_bands`. In masters without a `zero`, the figures are
+    /// taken to run from the baseline to the cap height, the top of `H`.
+    fn reference_heights(&self) -> HashMap<String, ReferenceHeights> {
+        self.font
+            .masters()
+            .iter()
+            .map(|master| {
+                let bounds = |glyph: &str| {
+                    self.outlines
+                        .get(&self.font, master, glyph)
+                        .and_then(|outline| outline.bounds())
+                };
+                let x_height = bounds("x").map(|b| b.y1 as f32);
+                let cap_height = bounds("H").map(|b| b.y1 as f32);
+                let figures = bounds("zero")
+                    .map(|b| (b.y0 as f32, b.y1 as f32))
+                    .or_else(|| cap_height.map(|cap| (0.0, cap)));
+                (master.id.clone(), ReferenceHeights { x_height, figures })
+            })
+            .collect()
+    }
+
+    /// The factor each master's target distances are scaled by (keyed by
+    /// master ID): the default master's stem width or axis coordinate over
+    /// the master's own, raised to `strength`. The default master is the
+    /// one at every axis's default, or failing that the first.
+    fn master_scales(
+        &self,
+        reference: &ScaleReference,
+        strength: f32,
+    ) -> PyResult<HashMap<String, f32>> {
+        let measure = |master: &Master| -> PyResult<f64> {
+            let value = match reference {
+                ScaleReference::Stem(glyph) => self
+                    .outlines
+                    .get(&self.font, master, glyph)
+                    .and_then(|outline| outline.stem_width()),
+                ScaleReference::Axis(name) => self
+                    .font
+                    .axis_name(name)
+                    .and_then(|axis| master.location.0.get(&axis).copied())
+                    .map(|v| v as f64),
+            };
+            value.filter(|v| *v > 0.0).ok_or_else(|| {
+                PyValueError::new_err(format!(
+                    "Couldn't measure {:?} in master {:}",
+                    reference,
+                    master_name(master)
+                ))
+            })
+        };
+        let default = self
+            .font
+            .default_master()
+            .ok_or_else(|| PyValueError::new_err("The font has no masters"))?;
+        let reference = measure(default)?;
+        self.font
+            .masters()
+            .iter()
+            .map(|m| {
+                let factor = (reference / measure(m)?).powf(strength as f64);
+                Ok((m.id.clone(), factor as f32))
+            })
+            .collect()
+    }
+
+    /// The kern the font already has for a pair, in font units, if the
+    /// parameters ask for the solver to start from it.
+    fn existing_kern(
+        &self,
+        master: &Master,
+        left_glyph: &str,
+        right_glyph: &str,
+        parameters: &Parameters,
+    ) -> Option<f32> {
+        if !parameters.seed {
+            return None;
+        }
+        existing_kern(&self.font, master, left_glyph, right_glyph)
+    }
+
+    /// Where the solver starts for a pair: the kern the caller gave, the
+    /// font's existing kern, or no kern at all.
+    fn start(
+        &self,
+        master: &Master,
+        left_glyph: &str,
+        right_glyph: &str,
+        parameters: &Parameters,
+    ) -> f32 {
+        parameters
+            .initial_kern_for(left_glyph, right_glyph)
+            .or_else(|| self.existing_kern(master, left_glyph, right_glyph, parameters))
+            .unwrap_or(0.0)
+    }
+
+    /// The glyph for each character the font encodes, along with the glyph
+    /// names of any unencoded glyphs named for characters (`uni0041`,
+    /// `u1F600`). Where several glyphs encode a character, an exported
+    /// glyph is preferred, and then the first.
+    fn cmap(&self) -> HashMap<u32, String> {
+        let mut cmap: HashMap<u32, String> = HashMap::new();
+        let exported = |name: &String| self.font.is_exported(name);
+        let glyphs = self.font.glyph_names();
+        // Backwards, so that earlier glyphs replace later ones.
+        for glyph in glyphs.iter().rev() {
+            for codepoint in self.font.codepoints(glyph).unwrap_or_default() {
+                let codepoint = *codepoint as u32;
+                if exported(glyph) || !cmap.get(&codepoint).map_or(false, exported) {
+                    cmap.insert(codepoint, glyph.clone());
+                }
+            }
+        }
+        for glyph in glyphs.iter() {
+            let name = glyph.as_str();
+            let named = name
+                .strip_prefix("uni")
+                .filter(|hex| hex.len() == 4)
+                .or_else(|| {
+                    name.strip_prefix('u')
+                        .filter(|hex| hex.len() == 5 || hex.len() == 6)
+                })
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+            let unencoded = self.font.codepoints(glyph).map_or(true, |c| c.is_empty());
+            if let (true, Some(codepoint)) = (unencoded, named) {
+                cmap.entry(codepoint).or_insert_with(|| glyph.clone());
+            }
+        }
+        cmap
+    }
+
+    /// Whether both glyphs of a pair will ship in the built font.
+    fn exports(&self, left_glyph: &str, right_glyph: &str) -> bool {
+        [left_glyph, right_glyph]
+            .iter()
+            .all(|name| self.font.is_exported(name))
+    }
+
+    /// The named masters, or all of them if no names are given.
+    fn selected_masters(&self, master_names: Option<Vec<String>>) -> PyResult<Vec<&Master>> {
+        match master_names {
+            Some(names) => names.iter().map(|n| self.master(n)).collect(),
+            None => Ok(self.font.masters().iter().collect()),
+        }
+    }
+}
+
+/// Each glyph's production name and each of the caller's other names,
+/// mapped to the working name it stands for. The caller's names win.
+fn working_names(font: &Font, renames: &HashMap<String, String>) -> HashMap<String, String> {
+    let mut names: HashMap<String, String> = font
+        .glyphs
+        .0
         .iter()
-        .find(|a| a.name == "exit")
-        .map(|a| a.y)
-        .unwrap_or(0);
-    let height = if height > 0 { height - lexit } else { height };
-    let mut minimum_possible = -1000.0;
-    if max_tuck != 0.0 {
-        let maximum_width = layer_1.width as f32 * max_tuck;
-        let left_edge = (-layer_2.lsb().expect("Oops")).min(0.0);
-        minimum_possible = left_edge - maximum_width;
-    }
-    let mut iterations = 0;
-    let mut kern = 0.0;
-    let mut min_distance = -9999.0;
-    let left_paths: Vec<BezPath> = layer_1
-        .paths()
-        .map(|x| x.to_kurbo().expect("Couldn't convert paths?!"))
-        .collect();
-    let right_paths: Vec<BezPath> = layer_2
-        .paths()
-        .map(|x| x.to_kurbo().expect("Couldn't convert paths?!"))
+        .filter_map(|g| Some((g.production_name.clone()?, g.name.clone())))
+        .filter(|(production, working)| production != working)
         .collect();
+    names.extend(renames.iter().map(|(k, v)| (k.clone(), v.clone())));
+    names
+}
 
-    while iterations < 10 && (target_distance - min_distance).abs() > 10.0 {
-        if let Some(md) = _path_distance(
-            &left_paths,
-            &right_paths,
-            kern + layer_1.width as f32,
-            height as f32,
-        ) {
-            log::debug!("With kern of {:?}, distance was {:?}", kern, md);
-            min_distance = md;
-            kern += target_distance - min_distance;
-            if kern < minimum_possible {
-                return minimum_possible;
-            }
-            iterations += 1;
-        } else {
-            return minimum_possible;
+/// The results for every pair of a batch, in order, from those solved for
+/// the first pair of each group of aliases (see `alias_groups`). `alias`
+/// makes the result for the pair at an index from its group's.
+fn fan_out<T: Clone>(
+    groups: &[Vec<usize>],
+    solved: Vec<T>,
+    alias: impl Fn(&T, usize) -> T,
+) -> Vec<T> {
+    let count = groups.iter().map(|g| g.len()).sum();
+    let mut results: Vec<Option<T>> = vec![None; count];
+    for (group, result) in groups.iter().zip(solved.into_iter()) {
+        for &index in &group[1..] {
+            results[index] = Some(alias(&result, index));
         }
+        results[group[0]] = Some(result);
     }
-    kern
+    results.into_iter().flatten().collect()
 }
 
-fn _path_distance(
-    left_paths: &[BezPath],
-    right_paths: &[BezPath],
-    x_offset: f32,
-    y_offset: f32,
+/// Solves a pair through the memo, or directly along with the steps it
+/// took if the parameters ask for a trace (which the memo can't give).
+fn solve_pair(
+    memo: &PairMemo,
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    start: f32,
+) -> (Solution, Option<Vec<Step>>) {
+    if parameters.trace.is_some() {
+        let (solution, trace) = solve_traced(left, right, parameters, start);
+        (solution, Some(trace))
+    } else {
+        (memo.solve(left, right, parameters, start), None)
+    }
+}
+
+/// The rise suggested for a pair the solver left at its tuck limit, in the
+/// units the caller asked for, if the parameters ask for one.
+fn suggested_rise(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    solution: solver::Solution,
 ) -> Option<f32> {
-    let offset1 = Affine::translate(Vec2 {
-        x: 0.0,
-        y: y_offset.into(),
-    });
-    let offset2 = Affine::translate(Vec2 {
-        x: x_offset as f64,
-        y: 0.0,
-    });
-    let mut min_distance: Option<f64> = None;
-    for p1 in left_paths {
-        let moved_p1 = offset1 * p1;
-        for p2 in right_paths {
-            let moved_p2 = offset2 * p2;
-            let d = min_distance_bezpath(&moved_p1, &moved_p2);
-            log::debug!("  d={:?}", d);
-            if min_distance.is_none() || d < min_distance.unwrap() {
-                log::debug!("    (new record)");
-                min_distance = Some(d)
-            } else {
-                log::debug!("    (ignored)");
-            }
+    if !parameters.suggest_rise || solution.status != Status::TuckLimited {
+        return None;
+    }
+    rise_needed(left, right, parameters, solution.kern).map(|rise| rise / parameters.scale)
+}
+
+/// Loads a font from a file, or a Fontra project directory.
+fn load_font(filename: &str) -> PyResult<Font> {
+    if filename.trim_end_matches('/').ends_with(".fontra") {
+        fontra::load(std::path::Path::new(filename))
+            .map_err(|e| PyIOError::new_err(format!("Couldn't load {:}: {:}", filename, e)))
+    } else {
+        let mut font = babelfont::load(filename)
+            .map_err(|e| PyIOError::new_err(format!("Couldn't load {:}: {:?}", filename, e)))?;
+        if filename.ends_with(".glyphs") {
+            let source = std::fs::read_to_string(filename)
+                .map_err(|e| PyIOError::new_err(format!("Couldn't load {:}: {:}", filename, e)))?;
+            smart::decompose_smart_components(&mut font, &source)
+                .map_err(|e| PyIOError::new_err(format!("Couldn't load {:}: {:}", filename, e)))?;
         }
+        Ok(font)
     }
-    min_distance.map(|x| x as f32)
 }
 
-#[pymodule]
-fn kerndeterminer(_py: Python, m: &PyModule) -> PyResult<()> {
-    m.add_class::<KernDeterminer>()?;
-    Ok(())
+/// The font to measure: the whole font, or only the given glyphs and
+/// their components.
+fn subset(source: &Font, glyphs: Option<Vec<String>>) -> Font {
+    let mut font = source.clone();
+    if let Some(glyphs) = glyphs {
+        let wanted = with_components(&font, glyphs);
+        font.glyphs.0.retain(|g| wanted.contains(&g.name));
+    }
+    font
 }
 
-fn min_distance_bezpath(one: &BezPath, other: &BezPath) -> f64 {
-    let segs1 = one.segments();
-    let mut best_pair: Option<(f64, kurbo::PathSeg, kurbo::PathSeg)> = None;
-    for s1 in segs1 {
-        let p1 = vec![s1.eval(0.0), s1.eval(0.5), s1.eval(1.0)];
-        for s2 in other.segments() {
-            let p2 = vec![s2.eval(0.0), s2.eval(0.5), s2.eval(1.0)];
-            let dist = p1
-                .iter()
-                .zip(p2.iter())
-                .map(|(a, b)| a.distance(*b))
-                .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Less))
-                .unwrap();
-            if let Some((best, _, _)) = best_pair {
-                if dist > best {
-                    continue;
-                }
+/// The named glyphs along with every glyph they use as a component,
+/// however deeply nested.
+fn with_components(font: &Font, names: Vec<String>) -> HashSet<String> {
+    let mut wanted = HashSet::new();
+    let mut queue = names;
+    while let Some(name) = queue.pop() {
+        if !wanted.insert(name.clone()) {
+            continue;
+        }
+        if let Some(glyph) = font.glyphs.get(&name) {
+            for layer in glyph.layers.iter() {
+                queue.extend(layer.components().map(|c| c.reference.clone()));
             }
-            best_pair = Some((dist, s1, s2));
         }
     }
-    if let Some((_, s1, s2)) = best_pair {
-        log::debug!("Best pair was {:?}, {:?}", s1, s2);
-        match (s1, s2) {
-            (PathSeg::Line(l1), PathSeg::Line(l2)) => line_line_dist(l1, l2),
-            (PathSeg::Line(l1), PathSeg::Cubic(c2)) => line_curve_dist(l1, c2),
-            (PathSeg::Cubic(c1), PathSeg::Line(l2)) => line_curve_dist(l2, c1),
-            (PathSeg::Cubic(c1), PathSeg::Cubic(c2)) => s1.min_dist(s2, 0.5).distance,
-            _ => panic!("Unusual configuration"),
+    wanted
+}
+
+/// A path given from Python as a string or anything `os.PathLike`, such as
+/// a `pathlib.Path`.
+fn fspath(path: &PyAny) -> PyResult<String> {
+    let os = path.py().import("os")?;
+    os.call1("fspath", (path,))?.extract()
+}
+
+/// The glyph for a single character, from a map made by `cmap`.
+fn glyph_for_char(cmap: &HashMap<u32, String>, character: &str) -> PyResult<String> {
+    let mut chars = character.chars();
+    let c = match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "Expected a single character, got {:?}",
+                character
+            )))
         }
-    } else {
-        f64::MAX
+    };
+    cmap.get(&(c as u32)).cloned().ok_or_else(|| {
+        PyKeyError::new_err(format!(
+            "No glyph for {:?} (U+{:04X}): it is not in the character map and no \
+             unencoded glyph is named for it",
+            c, c as u32
+        ))
+    })
+}
+
+fn master_name(master: &Master) -> String {
+    master
+        .name
+        .get_default()
+        .map(|n| n.to_string())
+        .unwrap_or_default()
+}
+
+/// A location as a dict of axis name to value.
+fn location_dict<'py>(py: Python<'py>, location: &Location) -> PyResult<&'py PyDict> {
+    let dict = PyDict::new(py);
+    for (axis, value) in location.0.iter() {
+        dict.set_item(axis, value)?;
     }
+    Ok(dict)
 }
 
-fn line_line_dist(l1: kurbo::Line, l2: kurbo::Line) -> f64 {
-    let a = l1.nearest(l2.p0, 1.0).distance_sq;
-    let b = l1.nearest(l2.p1, 1.0).distance_sq;
-    let c = l2.nearest(l1.p0, 1.0).distance_sq;
-    let d = l2.nearest(l1.p1, 1.0).distance_sq;
-    (a.min(b).min(c).min(d)).sqrt()
+/// Determines the kern between two outlines supplied directly, without
+/// loading a font.
+#[pyfunction(kwargs = "**")]
+fn determine_kern_for_outlines(
+    left: PyRef<Outline>,
+    right: PyRef<Outline>,
+    target_distance: f32,
+    height: i32,
+    max_tuck: f32,
+    kwargs: Option<&PyDict>,
+) -> PyResult<Option<f32>> {
+    let parameters =
+        Parameters::from_python(target_distance, height, max_tuck, kwargs)?.for_upm(None)?;
+    Ok(measured_kern(parameters.output(solve(
+        &left,
+        &right,
+        &parameters,
+        parameters.initial_kern.unwrap_or(0.0),
+    ))))
 }
 
-fn line_curve_dist(l1: kurbo::Line, c1: kurbo::CubicBez) -> f64 {
-    let t = [0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9];
-    t.iter()
-        .map(|x| c1.nearest(l1.eval(*x), 1.0).distance_sq)
-        .reduce(|a, b| a.min(b))
-        .unwrap_or(f64::MAX)
-        .sqrt()
+/// A solution's kern, or `None` if there was nothing to measure.
+fn measured_kern(solution: Solution) -> Option<f32> {
+    match solution.status {
+        Status::NoDistance => None,
+        _ => Some(solution.kern),
+    }
+}
+
+/// Kerns each pair in every master of a family whose masters are split
+/// across several sources (UFOs, say), merging the results. Pairs are only
+/// kerned in the sources which have both glyphs. Master names must be
+/// unique across the family, since results are told apart by them.
+#[pyfunction(include_non_exporting = "false", kwargs = "**")]
+fn determine_kerns_for_family(
+    py: Python,
+    sources: Vec<&PyAny>,
+    pairs: Vec<(String, String)>,
+    target_distance: f32,
+    height: i32,
+    max_tuck: f32,
+    include_non_exporting: bool,
+    kwargs: Option<&PyDict>,
+) -> PyResult<BatchResults> {
+    let mut masters_seen: HashSet<String> = HashSet::new();
+    let mut results = vec![];
+    for source in sources {
+        let kerner = KernDeterminer::new(source, None, None, false, None)?;
+        for master in kerner.font.masters().iter() {
+            let name = master_name(master);
+            if !masters_seen.insert(name.clone()) {
+                return Err(PyValueError::new_err(format!(
+                    "More than one source has a master called {:}",
+                    name
+                )));
+            }
+        }
+        let has = |name: &str| {
+            let name = kerner.working_name(name.to_string());
+            kerner.font.has_glyph(&name)
+        };
+        let present: Vec<(String, String)> = pairs
+            .iter()
+            .filter(|(left, right)| has(left) && has(right))
+            .cloned()
+            .collect();
+        let batch = kerner.determine_kerns_for_masters(
+            py,
+            present,
+            None,
+            target_distance,
+            height,
+            max_tuck,
+            include_non_exporting,
+            kwargs,
+        )?;
+        results.extend(batch.results);
+    }
+    Ok(BatchResults { results })
+}
+
+#[pymodule]
+fn kerndeterminer(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_wrapped(wrap_pyfunction!(determine_kern_for_outlines))?;
+    m.add_wrapped(wrap_pyfunction!(determine_kerns_for_family))?;
+    m.add_class::<KernDeterminer>()?;
+    m.add_class::<Outline>()?;
+    m.add_class::<BatchResults>()?;
+    m.add_class::<KernResult>()?;
+    m.add_class::<Summary>()?;
+    Ok(())
 }
 
 // #[cfg(test)]
@@ -0,0 +1,251 @@
+use crate::outline::Outline;
+use crate::parameters::{Mode, Parameters};
+use crate::solver::{kern_limit, solve, Solution, Status};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::RwLock;
+
+/// What a pair's result in a batch run depends on besides the parameters
+/// the whole run shares: see `pair_key`.
+pub(crate) type PairKey = (u64, u64, u64);
+
+/// Remembers the gap the solver settled on between the bounding boxes of
+/// each pair of shapes in a batch, so that pairs whose outlines are
+/// identical up to horizontal translation (composites of the same base,
+/// alternates with different sidebearings) or mirroring (right-to-left
+/// mirrored forms) are only measured once. Gaps are kept apart by
+/// everything in the parameters which can change them (see
+/// `settings_key`), and the memo can be shared between threads.
+#[derive(Debug, Default)]
+pub(crate) struct PairMemo {
+    gaps: RwLock<HashMap<(u64, u64, i64, u64), f32>>,
+}
+
+/// Vertical offsets are compared to a hundredth of a unit.
+fn offset_key(offset: f64) -> i64 {
+    (offset * 100.0).round() as i64
+}
+
+/// Hashes everything in the parameters which decides the gap between two
+/// outlines, once they have been converted: the targets wherever they
+/// apply, the cutoffs, the overshoot bands, how distance is measured and
+/// the heights it is measured over. Pairs measured with the same settings
+/// can share a gap; the parameters for a pair (see
+/// `Parameters::for_pair`) can differ in any of these from the run's.
+fn settings_key(parameters: &Parameters) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let bits = |values: &[f32]| values.iter().map(|v| v.to_bits()).collect::<Vec<u32>>();
+    parameters.shear.to_bits().hash(&mut hasher);
+    bits(&[
+        parameters.target_distance,
+        parameters.dot_size,
+        parameters.height_range,
+        parameters.height_step,
+        parameters.resolution,
+        parameters.epsilon,
+        parameters.tolerance,
+        parameters.kern_tolerance,
+    ])
+    .hash(&mut hasher);
+    parameters
+        .vertical_target_distance
+        .map(f32::to_bits)
+        .hash(&mut hasher);
+    parameters
+        .dot_target_distance
+        .map(f32::to_bits)
+        .hash(&mut hasher);
+    parameters.ignore_below.map(f32::to_bits).hash(&mut hasher);
+    parameters.ignore_above.map(f32::to_bits).hash(&mut hasher);
+    for zone in parameters.zones.iter() {
+        bits(&[zone.bottom, zone.top, zone.target_distance]).hash(&mut hasher);
+    }
+    for (y, target) in parameters.target_curve.iter() {
+        bits(&[*y, *target]).hash(&mut hasher);
+    }
+    for zone in parameters.zone_weights.iter() {
+        bits(&[zone.bottom, zone.top, zone.weight]).hash(&mut hasher);
+    }
+    for (metric, overshoot) in parameters.overshoots.iter() {
+        bits(&[*metric, *overshoot]).hash(&mut hasher);
+    }
+    (
+        parameters.engine,
+        parameters.metric,
+        parameters.perpendicular,
+    )
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Identifies a pair by the exact outlines measured (see
+/// `Outline::identity_key`), the settings it is measured with (see
+/// `settings_key`), the tuck limit and the kern the solver starts from,
+/// if the caller chose one. Pairs with the same key in the same run and
+/// master get the same result, so a batch only has to solve one of them:
+/// composites which add no ink to their base, and alternates sharing a
+/// base, are common.
+pub(crate) fn pair_key(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    start: Option<f32>,
+) -> PairKey {
+    let mut hasher = DefaultHasher::new();
+    settings_key(parameters).hash(&mut hasher);
+    parameters.max_tuck.to_bits().hash(&mut hasher);
+    start.map(f32::to_bits).hash(&mut hasher);
+    (left.identity_key(), right.identity_key(), hasher.finish())
+}
+
+/// Groups the indices of equal keys, each group in order and the groups
+/// in order of their first index.
+pub(crate) fn alias_groups<K: Hash + Eq>(keys: &[K]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = vec![];
+    let mut seen: HashMap<&K, usize> = HashMap::new();
+    for (index, key) in keys.iter().enumerate() {
+        match seen.get(key) {
+            Some(&group) => groups[group].push(index),
+            None => {
+                seen.insert(key, groups.len());
+                groups.push(vec![index]);
+            }
+        }
+    }
+    groups
+}
+
+impl PairMemo {
+    pub fn solve(
+        &self,
+        left: &Outline,
+        right: &Outline,
+        parameters: &Parameters,
+        start: f32,
+    ) -> Solution {
+        // Only kerns to a target distance depend on nothing but the gap.
+        let (left_bounds, right_bounds) = match (left.bounds(), right.bounds()) {
+            (Some(l), Some(r)) if parameters.mode == Mode::Target => (l, r),
+            _ => return solve(left, right, parameters, start),
+        };
+        // The horizontal distance between the two shapes' bounding boxes.
+        let overhang = left.width + right_bounds.x0 as f32 - left_bounds.x1 as f32;
+        let offset = parameters.left_offset(left);
+        // Pairs can have their own targets and zones, and masters with
+        // different italic angles are sheared differently.
+        let setting = settings_key(parameters);
+        let key = (
+            left.shape_key(false),
+            right.shape_key(false),
+            offset_key(offset),
+            setting,
+        );
+
+        let known = self.gaps.read().unwrap().get(&key).copied();
+        if let Some(gap) = known {
+            let kern = gap - overhang;
+            let (minimum, limited) = kern_limit(left, right, parameters);
+            return if kern < minimum {
+                Solution {
+                    kern: minimum,
+                    status: limited,
+                    iterations: 0,
+                }
+            } else {
+                Solution {
+                    kern,
+                    status: Status::Converged,
+                    iterations: 0,
+                }
+            };
+        }
+
+        let solution = solve(left, right, parameters, start);
+        if solution.status == Status::Converged {
+            let gap = solution.kern + overhang;
+            let mut gaps = self.gaps.write().unwrap();
+            gaps.insert(key, gap);
+            // Mirroring the whole pair swaps the glyphs and lowers the left
+            // one by as much as it was raised. Zones and target curves are
+            // fixed in y, so that only holds with them if the glyphs sat
+            // level, and only the left glyph's dots have a target of their
+            // own, so not at all with one.
+            let level = !parameters.target_varies() || offset_key(offset) == 0;
+            if level && parameters.dot_target_distance.is_none() {
+                gaps.insert(
+                    (
+                        right.shape_key(true),
+                        left.shape_key(true),
+                        offset_key(-offset),
+                        setting,
+                    ),
+                    gap,
+                );
+            }
+        }
+        solution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameters::Zone;
+    use kurbo::{Rect, Shape};
+
+    fn square(x: f64) -> Outline {
+        let path = Rect::new(x, 0.0, x + 100.0, 100.0).to_path(0.1);
+        Outline::new(vec![path], 200.0, HashMap::new())
+    }
+
+    #[test]
+    fn alias_groups_gather_equal_keys_in_order() {
+        assert_eq!(
+            alias_groups(&[1, 2, 1, 3, 2, 1]),
+            vec![vec![0, 2, 5], vec![1, 4], vec![3]]
+        );
+        assert!(alias_groups::<u8>(&[]).is_empty());
+    }
+
+    #[test]
+    fn pair_key_tells_apart_outlines_and_starts() {
+        let parameters = Parameters::new(100.0, 0, 0.5);
+        let key = pair_key(&square(0.0), &square(0.0), &parameters, None);
+        assert_eq!(key, pair_key(&square(0.0), &square(0.0), &parameters, None));
+        assert_ne!(
+            key,
+            pair_key(&square(10.0), &square(0.0), &parameters, None)
+        );
+        assert_ne!(
+            key,
+            pair_key(&square(0.0), &square(0.0), &parameters, Some(-20.0))
+        );
+    }
+
+    #[test]
+    fn pair_key_tells_apart_per_pair_settings() {
+        let parameters = Parameters::new(100.0, 0, 0.5);
+        let key = |parameters: &Parameters| pair_key(&square(0.0), &square(0.0), parameters, None);
+        let mut zoned = parameters.clone();
+        zoned.zones.push(Zone {
+            bottom: 0.0,
+            top: 50.0,
+            target_distance: 60.0,
+        });
+        let mut dotted = parameters.clone();
+        dotted.dot_target_distance = Some(40.0);
+        let mut curved = parameters.clone();
+        curved.target_curve = vec![(0.0, 80.0), (100.0, 120.0)];
+        let mut tucked = parameters.clone();
+        tucked.max_tuck = 0.2;
+        let keys = [
+            key(&parameters),
+            key(&zoned),
+            key(&dotted),
+            key(&curved),
+            key(&tucked),
+        ];
+        assert_eq!(alias_groups(&keys).len(), keys.len());
+    }
+}
@@ -0,0 +1,865 @@
+use crate::backend::GlyphLayer;
+use crate::bands::BandProfile;
+use crate::flat::{flatten_edges, spans};
+use crate::master_name;
+use babelfont::{Font, Layer, Master};
+use core::cmp::Ordering;
+use kurbo::{
+    Affine, BezPath, CubicBez, Line, ParamCurve, ParamCurveExtrema, PathEl, PathSeg, Point,
+    QuadBez, Rect, Shape, Vec2,
+};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Contours containing an anchor with this name are not measured.
+const IGNORE_ANCHOR: &str = "kern.ignore";
+/// How deeply nested components are followed.
+pub(crate) const MAX_COMPONENT_DEPTH: usize = 16;
+/// Layers with these names override the outlines used for measurement.
+const KERN_LAYER_NAMES: [&str; 2] = ["kern", "public.kernOutline"];
+/// Where along each segment to look from when deciding which way it faces.
+const FACING_SAMPLES: [f64; 5] = [0.05, 0.25, 0.5, 0.75, 0.95];
+/// How far outside a segment (in font units) to look from.
+const FACING_PROBE: f64 = 1.0;
+/// How far apart (in font units) the ends of pieces of a clipped contour
+/// may be and still count as joined.
+const CLIP_JOIN: f64 = 1e-6;
+/// The anchor cursive attachment joins at, unless told otherwise.
+pub(crate) const EXIT_ANCHOR: &str = "exit";
+/// The anchor on the other glyph of a cursive attachment.
+pub(crate) const ENTRY_ANCHOR: &str = "entry";
+
+/// A glyph's outlines and the metrics the solver needs, wherever they
+/// came from.
+#[pyclass]
+#[derive(Debug, Clone)]
+pub(crate) struct Outline {
+    pub paths: Vec<BezPath>,
+    #[pyo3(get)]
+    pub width: f32,
+    pub anchors: HashMap<String, Point>,
+    /// The segments of each contour which can be seen from the right, i.e.
+    /// which face the next glyph when this one is on the left
+    pub right_side: Vec<BezPath>,
+    /// The segments of each contour which can be seen from the left
+    pub left_side: Vec<BezPath>,
+    /// Where each contour's right side reaches, band by band
+    pub right_bands: Vec<BandProfile>,
+    /// Where each contour's left side reaches, band by band
+    pub left_bands: Vec<BandProfile>,
+}
+
+impl Outline {
+    pub fn new(paths: Vec<BezPath>, width: f32, anchors: HashMap<String, Point>) -> Self {
+        let right_side = facing_side(&paths, 1.0);
+        let left_side = facing_side(&paths, -1.0);
+        Outline {
+            right_bands: right_side.iter().map(BandProfile::new).collect(),
+            left_bands: left_side.iter().map(BandProfile::new).collect(),
+            right_side,
+            left_side,
+            paths,
+            width,
+            anchors,
+        }
+    }
+
+    /// The outline of a layer a backend has read (see
+    /// `Backend::layer`). Fails, naming the glyph and master, if the layer
+    /// couldn't be read or has coordinates which are not numbers.
+    pub fn from_glyph_layer(
+        glyph_name: &str,
+        master: &Master,
+        layer: Result<GlyphLayer, String>,
+    ) -> Result<Self, String> {
+        layer
+            .and_then(|layer| {
+                let paths = sanitize(layer.paths)?;
+                Ok(Outline::new(paths, layer.width, layer.anchors))
+            })
+            .map_err(|e| {
+                format!(
+                    "Glyph {:} in master {:} {:}",
+                    glyph_name,
+                    master_name(master),
+                    e
+                )
+            })
+    }
+
+    pub fn bounds(&self) -> Option<Rect> {
+        self.paths
+            .iter()
+            .map(|p| p.bounding_box())
+            .reduce(|a, b| a.union(b))
+    }
+
+    pub fn lsb(&self) -> Option<f64> {
+        self.bounds().map(|b| b.x0)
+    }
+
+    /// The width of the first stem met going across the middle of the
+    /// outline's bounds from the left, for comparing weights.
+    pub fn stem_width(&self) -> Option<f64> {
+        let bounds = self.bounds()?;
+        let y = (bounds.y0 + bounds.y1) / 2.0;
+        let line = Line::new((bounds.x0 - 1.0, y), (bounds.x1 + 1.0, y));
+        let mut crossings: Vec<f64> = self
+            .paths
+            .iter()
+            .flat_map(|p| p.segments())
+            .flat_map(|s| s.intersect_line(line))
+            .map(|i| line.eval(i.line_t).x)
+            .collect();
+        crossings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        Some(crossings.get(1)? - crossings[0])
+    }
+
+    /// Splits the outline into its dots and the rest: a dot is a contour
+    /// no more than `size` units across either way which doesn't sit
+    /// within another contour's bounds, as a counter would.
+    pub fn split_dots(&self, size: f64) -> (Outline, Outline) {
+        let bounds: Vec<Rect> = self.paths.iter().map(|p| p.bounding_box()).collect();
+        let is_dot = |i: usize| {
+            let b = bounds[i];
+            b.width() <= size
+                && b.height() <= size
+                && !bounds
+                    .iter()
+                    .enumerate()
+                    .any(|(j, other)| j != i && other.union(b) == *other && other.area() > b.area())
+        };
+        let dots: Vec<bool> = (0..self.paths.len()).map(is_dot).collect();
+        let part = |wanted: bool| {
+            let keep = |paths: &[BezPath]| -> Vec<BezPath> {
+                paths
+                    .iter()
+                    .zip(dots.iter())
+                    .filter(|(_, dot)| **dot == wanted)
+                    .map(|(p, _)| p.clone())
+                    .collect()
+            };
+            let keep_bands = |bands: &[BandProfile]| -> Vec<BandProfile> {
+                bands
+                    .iter()
+                    .zip(dots.iter())
+                    .filter(|(_, dot)| **dot == wanted)
+                    .map(|(b, _)| b.clone())
+                    .collect()
+            };
+            Outline {
+                paths: keep(&self.paths),
+                width: self.width,
+                anchors: self.anchors.clone(),
+                right_side: keep(&self.right_side),
+                left_side: keep(&self.left_side),
+                right_bands: keep_bands(&self.right_bands),
+                left_bands: keep_bands(&self.left_bands),
+            }
+        };
+        (part(true), part(false))
+    }
+
+    /// The outline sheared horizontally by `shear` units per unit of
+    /// height, in the direction which sets right-leaning stems upright,
+    /// and then scaled vertically by `vertical_scale`. Anchors are only
+    /// sheared, since they decide offsets in the original units.
+    pub fn transformed(&self, shear: f64, vertical_scale: f64) -> Self {
+        let sheared = Affine::new([1.0, 0.0, -shear, 1.0, 0.0, 0.0]);
+        let transform = Affine::new([1.0, 0.0, -shear, vertical_scale, 0.0, 0.0]);
+        Outline::new(
+            self.paths.iter().map(|p| transform * p).collect(),
+            self.width,
+            self.anchors
+                .iter()
+                .map(|(name, point)| (name.clone(), sheared * *point))
+                .collect(),
+        )
+    }
+
+    /// Whether two outlines have the same contours, advance width and
+    /// anchors, and so measure the same.
+    pub fn same_as(&self, other: &Outline) -> bool {
+        self.paths == other.paths && self.width == other.width && self.anchors == other.anchors
+    }
+
+    /// A hash of the outline's shape, ignoring where it sits horizontally,
+    /// and optionally after mirroring it left to right.
+    pub fn shape_key(&self, mirrored: bool) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let bounds = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return hasher.finish(),
+        };
+        let transform = if mirrored {
+            Affine::new([-1.0, 0.0, 0.0, 1.0, bounds.x1, 0.0])
+        } else {
+            Affine::translate(Vec2::new(-bounds.x0, 0.0))
+        };
+        // Coordinates are compared to a hundredth of a unit.
+        let point = |p: Point, hasher: &mut DefaultHasher| {
+            let p = transform * p;
+            ((p.x * 100.0).round() as i64).hash(hasher);
+            ((p.y * 100.0).round() as i64).hash(hasher);
+        };
+        for path in &self.paths {
+            for el in path.elements() {
+                match *el {
+                    PathEl::MoveTo(p) => {
+                        0u8.hash(&mut hasher);
+                        point(p, &mut hasher);
+                    }
+                    PathEl::LineTo(p) => {
+                        1u8.hash(&mut hasher);
+                        point(p, &mut hasher);
+                    }
+                    PathEl::QuadTo(p1, p2) => {
+                        2u8.hash(&mut hasher);
+                        point(p1, &mut hasher);
+                        point(p2, &mut hasher);
+                    }
+                    PathEl::CurveTo(p1, p2, p3) => {
+                        3u8.hash(&mut hasher);
+                        point(p1, &mut hasher);
+                        point(p2, &mut hasher);
+                        point(p3, &mut hasher);
+                    }
+                    PathEl::ClosePath => 4u8.hash(&mut hasher),
+                }
+            }
+        }
+        hasher.finish()
+    }
+
+    /// A hash of everything about the outline which the solver measures:
+    /// its shape and where it sits, its advance width and its anchors.
+    /// Outlines with the same key kern the same.
+    pub fn identity_key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.shape_key(false).hash(&mut hasher);
+        let round = |v: f64| (v * 100.0).round() as i64;
+        self.bounds().map(|b| round(b.x0)).hash(&mut hasher);
+        self.width.to_bits().hash(&mut hasher);
+        let mut anchors: Vec<(&String, &Point)> = self.anchors.iter().collect();
+        anchors.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, point) in anchors {
+            name.hash(&mut hasher);
+            round(point.x).hash(&mut hasher);
+            round(point.y).hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// The outline with the ink below `low` and above `high` cut away.
+    /// Contours wholly outside the range are dropped, and those crossing
+    /// a cutoff are closed along it.
+    pub fn clipped(&self, low: Option<f64>, high: Option<f64>) -> Self {
+        let low = low.unwrap_or(f64::NEG_INFINITY);
+        let high = high.unwrap_or(f64::INFINITY);
+        Outline::new(
+            self.paths
+                .iter()
+                .filter_map(|p| clip_path(p, low, high))
+                .collect(),
+            self.width,
+            self.anchors.clone(),
+        )
+    }
+
+    /// The outline with marks attached: each mark whose anchor `_name`
+    /// matches one of this glyph's anchors `name` is moved so the two
+    /// meet, and its contours are added to the outline's. Outlines and
+    /// marks should have been transformed alike (see `transformed`), whose
+    /// vertical scale is `vertical_scale`. Marks which attach nowhere are
+    /// left out.
+    pub fn with_marks(&self, marks: &[Arc<Outline>], vertical_scale: f64) -> Self {
+        let mut paths = self.paths.clone();
+        for mark in marks {
+            let attachment = mark.anchors.iter().find_map(|(name, point)| {
+                let base = self.anchors.get(name.strip_prefix('_')?)?;
+                Some(*base - *point)
+            });
+            if let Some(shift) = attachment {
+                let transform = Affine::translate(Vec2::new(shift.x, shift.y * vertical_scale));
+                paths.extend(mark.paths.iter().map(|p| transform * p));
+            }
+        }
+        Outline::new(paths, self.width, self.anchors.clone())
+    }
+
+    /// The vertical offset applied to this glyph, as the left of a pair,
+    /// for a given height, taking its exit anchor into account.
+    pub fn vertical_offset(&self, height: i32, exit_anchor: &str) -> f64 {
+        // Get exit anchor
+        let lexit = self.anchors.get(exit_anchor).map(|a| a.y).unwrap_or(0.0);
+        if height > 0 {
+            height as f64 - lexit
+        } else {
+            height as f64
+        }
+    }
+}
+
+#[pymethods]
+impl Outline {
+    /// Contours may be SVG path strings or lists of `(x, y)` points, which
+    /// are joined with straight lines into a closed polygon.
+    #[new]
+    #[args(anchors = "None")]
+    fn py_new(
+        contours: Vec<&PyAny>,
+        width: f32,
+        anchors: Option<HashMap<String, (f64, f64)>>,
+    ) -> PyResult<Self> {
+        let mut paths = vec![];
+        for contour in contours {
+            if let Ok(svg) = contour.extract::<&str>() {
+                paths.push(BezPath::from_svg(svg).map_err(|e| {
+                    PyValueError::new_err(format!("Couldn't parse SVG path {:}: {:?}", svg, e))
+                })?);
+            } else {
+                let points: Vec<(f64, f64)> = contour.extract()?;
+                let mut path = BezPath::new();
+                for (i, (x, y)) in points.into_iter().enumerate() {
+                    if i == 0 {
+                        path.move_to((x, y));
+                    } else {
+                        path.line_to((x, y));
+                    }
+                }
+                path.close_path();
+                paths.push(path);
+            }
+        }
+        let paths =
+            sanitize(paths).map_err(|e| PyValueError::new_err(format!("Outline {:}", e)))?;
+        Ok(Outline::new(
+            paths,
+            width,
+            anchors
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, (x, y))| (name, Point::new(x, y)))
+                .collect(),
+        ))
+    }
+}
+
+/// For each contour, the segments which can be seen from the right
+/// (`direction` 1) or left (-1): those with a point from just outside which
+/// a horizontal line running off in that direction meets no ink. This takes
+/// in the undersides of overhangs, which a tucked glyph may come closest
+/// to, but leaves out counters and the far side of the glyph.
+fn facing_side(paths: &[BezPath], direction: f64) -> Vec<BezPath> {
+    let edges: Vec<(Point, Point)> = paths.iter().flat_map(|p| flatten_edges(p, 1.0)).collect();
+    let inside = |q: Point| spans(&edges, q.y).iter().any(|s| s.0 < q.x && q.x < s.1);
+    let visible = |q: Point| {
+        spans(&edges, q.y).iter().all(|s| {
+            if direction > 0.0 {
+                s.1 <= q.x
+            } else {
+                s.0 >= q.x
+            }
+        })
+    };
+    paths
+        .iter()
+        .map(|path| {
+            let mut side = BezPath::new();
+            for seg in path.segments() {
+                let faces = FACING_SAMPLES.iter().any(|&t| {
+                    let p = seg.eval(t);
+                    let tangent = seg.eval((t + 0.01).min(1.0)) - seg.eval((t - 0.01).max(0.0));
+                    if tangent.hypot() == 0.0 {
+                        return false;
+                    }
+                    let normal =
+                        Vec2::new(-tangent.y, tangent.x) * (FACING_PROBE / tangent.hypot());
+                    [p + normal, p - normal]
+                        .iter()
+                        .any(|&q| !inside(q) && visible(q))
+                });
+                if faces {
+                    side.move_to(seg.start());
+                    match seg {
+                        PathSeg::Line(l) => side.line_to(l.p1),
+                        PathSeg::Quad(q) => side.quad_to(q.p1, q.p2),
+                        PathSeg::Cubic(c) => side.curve_to(c.p1, c.p2, c.p3),
+                    }
+                }
+            }
+            side
+        })
+        .collect()
+}
+
+/// The layer whose outlines are measured in place of a glyph's master layer.
+/// Designers can supply a simplified silhouette in a layer called `kern` or
+/// `public.kernOutline`, optionally followed by a space and the master name
+/// for sources where each master needs its own; the master-specific layer
+/// takes priority.
+pub(crate) fn measurement_layer<'a>(
+    font: &'a Font,
+    glyph_name: &str,
+    master: &Master,
+    master_layer: &'a Layer,
+) -> &'a Layer {
+    let glyph = match font.glyphs.get(glyph_name) {
+        Some(glyph) => glyph,
+        None => return master_layer,
+    };
+    let master_name = master_name(master);
+    KERN_LAYER_NAMES
+        .iter()
+        .map(|n| format!("{} {}", n, master_name))
+        .chain(KERN_LAYER_NAMES.iter().map(|n| n.to_string()))
+        .find_map(|name| {
+            glyph
+                .layers
+                .iter()
+                .find(|l| l.name.as_deref() == Some(name.as_str()))
+        })
+        .unwrap_or(master_layer)
+}
+
+/// A babelfont layer as the backend reads it: the outlines of `measured`,
+/// with components decomposed in `master`, and the advance width and
+/// anchors of `layer`. Fails if a path can't be converted.
+pub(crate) fn babelfont_layer(
+    font: &Font,
+    master: &Master,
+    measured: &Layer,
+    layer: &Layer,
+) -> Result<GlyphLayer, String> {
+    Ok(GlyphLayer {
+        paths: layer_paths(font, master, measured)?,
+        width: layer.width as f32,
+        anchors: babelfont_anchors(layer),
+    })
+}
+
+/// A babelfont layer's anchors, by name.
+pub(crate) fn babelfont_anchors(layer: &Layer) -> HashMap<String, Point> {
+    layer
+        .anchors
+        .iter()
+        .map(|a| (a.name.clone(), Point::new(a.x as f64, a.y as f64)))
+        .collect()
+}
+
+/// Converts a layer's outlines, with its components decomposed, to kurbo
+/// paths, dropping any contours the designer has marked to be ignored by
+/// placing an anchor called `kern.ignore` (or `kern.ignore.1`,
+/// `kern.ignore.2`...) inside them. Fails if a path can't be converted.
+fn layer_paths(font: &Font, master: &Master, layer: &Layer) -> Result<Vec<BezPath>, String> {
+    let ignored: Vec<Point> = layer
+        .anchors
+        .iter()
+        .filter(|a| a.name == IGNORE_ANCHOR || a.name.starts_with(&format!("{}.", IGNORE_ANCHOR)))
+        .map(|a| Point::new(a.x as f64, a.y as f64))
+        .collect();
+    Ok(decomposed_paths(font, master, layer, Affine::default(), 0)?
+        .into_iter()
+        .filter(|p| !ignored.iter().any(|pt| p.contains(*pt)))
+        .collect())
+}
+
+/// A layer's own contours along with those of its components, and of their
+/// components in turn, placed by the product of the transforms on the way
+/// down. Contours from mirrored components are reversed, so that they wind
+/// the same way as they do in the component glyph. Fails if a path (in
+/// the layer or any of its components) can't be converted, as happens
+/// with runs of off-curve points no curve can be drawn through.
+fn decomposed_paths(
+    font: &Font,
+    master: &Master,
+    layer: &Layer,
+    transform: Affine,
+    depth: usize,
+) -> Result<Vec<BezPath>, String> {
+    let mirrored = transform.determinant() < 0.0;
+    let mut paths = layer
+        .paths()
+        .map(|x| {
+            let path = transform
+                * x.to_kurbo()
+                    .map_err(|e| format!("has a path which can't be converted ({:?})", e))?;
+            Ok(if mirrored { reversed(&path) } else { path })
+        })
+        .collect::<Result<Vec<BezPath>, String>>()?;
+    // Components which (eventually) refer to themselves go no deeper.
+    if depth >= MAX_COMPONENT_DEPTH {
+        return Ok(paths);
+    }
+    for component in layer.components() {
+        if let Some(component_layer) = font.master_layer_for(&component.reference, master) {
+            paths.extend(decomposed_paths(
+                font,
+                master,
+                component_layer,
+                transform * component.transform,
+                depth + 1,
+            )?);
+        }
+    }
+    Ok(paths)
+}
+
+/// The part of a contour between `low` and `high`, or `None` if none of
+/// it is. Segments are split where they cross a cutoff and the pieces
+/// outside are left out; where the contour leaves the range and comes back
+/// (always across the same cutoff), a line along the cutoff joins it up.
+fn clip_path(path: &BezPath, low: f64, high: f64) -> Option<BezPath> {
+    let bounds = path.bounding_box();
+    if bounds.y0 >= low && bounds.y1 <= high {
+        return Some(path.clone());
+    }
+    if bounds.y1 < low || bounds.y0 > high {
+        return None;
+    }
+    let mut pieces: Vec<PathSeg> = vec![];
+    for segment in path.segments() {
+        let extent = segment.bounding_box();
+        let mut cuts: Vec<f64> = vec![0.0, 1.0];
+        for y in [low, high].iter().filter(|y| y.is_finite()) {
+            let line = Line::new((extent.x0 - 1.0, *y), (extent.x1 + 1.0, *y));
+            cuts.extend(segment.intersect_line(line).iter().map(|i| i.segment_t));
+        }
+        cuts.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        for range in cuts.windows(2).filter(|t| t[1] > t[0]) {
+            let piece = segment.subsegment(range[0]..range[1]);
+            let y = piece.eval(0.5).y;
+            if y >= low && y <= high {
+                pieces.push(piece);
+            }
+        }
+    }
+    let first = pieces.first()?;
+    let mut clipped = BezPath::new();
+    clipped.move_to(first.start());
+    let mut end = first.start();
+    for piece in pieces.iter() {
+        if piece.start().distance(end) > CLIP_JOIN {
+            clipped.line_to(piece.start());
+        }
+        end = piece.end();
+        match piece {
+            PathSeg::Line(l) => clipped.line_to(l.p1),
+            PathSeg::Quad(q) => clipped.quad_to(q.p1, q.p2),
+            PathSeg::Cubic(c) => clipped.curve_to(c.p1, c.p2, c.p3),
+        }
+    }
+    clipped.close_path();
+    Some(clipped)
+}
+
+/// Reverses the direction of each contour in a path.
+fn reversed(path: &BezPath) -> BezPath {
+    let mut out = BezPath::new();
+    let mut segments: Vec<PathSeg> = vec![];
+    let flush = |segments: &mut Vec<PathSeg>, closed: bool, out: &mut BezPath| {
+        if let Some(last) = segments.last() {
+            out.move_to(last.end());
+            for segment in segments.iter().rev() {
+                match *segment {
+                    PathSeg::Line(l) => out.line_to(l.p0),
+                    PathSeg::Quad(q) => out.quad_to(q.p1, q.p0),
+                    PathSeg::Cubic(c) => out.curve_to(c.p2, c.p1, c.p0),
+                }
+            }
+            if closed {
+                out.close_path();
+            }
+        }
+        segments.clear();
+    };
+    let mut start = Point::ZERO;
+    let mut current = Point::ZERO;
+    for el in path.elements() {
+        match *el {
+            PathEl::MoveTo(p) => {
+                flush(&mut segments, false, &mut out);
+                start = p;
+                current = p;
+            }
+            PathEl::LineTo(p) => {
+                segments.push(PathSeg::Line(Line::new(current, p)));
+                current = p;
+            }
+            PathEl::QuadTo(p1, p2) => {
+                segments.push(PathSeg::Quad(QuadBez::new(current, p1, p2)));
+                current = p2;
+            }
+            PathEl::CurveTo(p1, p2, p3) => {
+                segments.push(PathSeg::Cubic(CubicBez::new(current, p1, p2, p3)));
+                current = p3;
+            }
+            PathEl::ClosePath => {
+                flush(&mut segments, true, &mut out);
+                current = start;
+            }
+        }
+    }
+    flush(&mut segments, false, &mut out);
+    out
+}
+
+/// Rewinds contours so that outer contours run anticlockwise and the
+/// counters inside them clockwise, however the source drew them. A contour
+/// is a counter if it lies inside an odd number of other contours.
+pub(crate) fn normalize_direction(paths: Vec<BezPath>) -> Vec<BezPath> {
+    let starts: Vec<Option<Point>> = paths
+        .iter()
+        .map(|p| p.segments().next().map(|s| s.start()))
+        .collect();
+    paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let depth = paths
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && starts[i].map_or(false, |pt| other.contains(pt)))
+                .count();
+            let anticlockwise = signed_area(path) > 0.0;
+            if anticlockwise == (depth % 2 == 0) {
+                path.clone()
+            } else {
+                reversed(path)
+            }
+        })
+        .collect()
+}
+
+/// The area enclosed by a path, positive if it runs anticlockwise.
+fn signed_area(path: &BezPath) -> f64 {
+    flatten_edges(path, 1.0)
+        .iter()
+        .map(|(a, b)| a.x * b.y - b.x * a.y)
+        .sum::<f64>()
+        / 2.0
+}
+
+/// Drops the zero-length segments, repeated points and empty contours
+/// which sloppy sources contain, as they only confuse the measurements.
+/// Coordinates which are not numbers are an error.
+fn sanitize(paths: Vec<BezPath>) -> Result<Vec<BezPath>, String> {
+    let mut clean = vec![];
+    for path in paths {
+        let mut out = BezPath::new();
+        let mut start: Option<Point> = None;
+        let mut current: Option<Point> = None;
+        let mut pending = true;
+        for el in path.elements() {
+            let points: Vec<Point> = match *el {
+                PathEl::MoveTo(p) | PathEl::LineTo(p) => vec![p],
+                PathEl::QuadTo(p1, p2) => vec![p1, p2],
+                PathEl::CurveTo(p1, p2, p3) => vec![p1, p2, p3],
+                PathEl::ClosePath => vec![],
+            };
+            if points.iter().any(|p| !p.x.is_finite() || !p.y.is_finite()) {
+                return Err("has a coordinate which is not a number".to_string());
+            }
+            match *el {
+                PathEl::MoveTo(p) => {
+                    start = Some(p);
+                    current = Some(p);
+                    pending = true;
+                }
+                PathEl::ClosePath => {
+                    if !pending {
+                        out.close_path();
+                        pending = true;
+                    }
+                    current = start;
+                }
+                _ => {
+                    // A segment which goes nowhere adds nothing.
+                    if points.iter().all(|p| Some(*p) == current) {
+                        continue;
+                    }
+                    if pending {
+                        match current {
+                            Some(p) => out.move_to(p),
+                            None => return Err("has a segment before its first point".to_string()),
+                        }
+                        start = current;
+                        pending = false;
+                    }
+                    out.push(*el);
+                    current = points.last().copied();
+                }
+            }
+        }
+        if out.elements().len() > 1 {
+            clean.push(out);
+        }
+    }
+    Ok(clean)
+}
+
+/// Joins paths into a single SVG path data string.
+pub(crate) fn svg_path(paths: &[BezPath]) -> String {
+    paths
+        .iter()
+        .map(|p| p.to_svg())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Backend;
+    use serde_json::{json, Value};
+
+    /// A one-master font with a 100 unit square, drawn anticlockwise, and
+    /// the given glyphs, each a list of shapes.
+    fn font(glyphs: Vec<(&str, Value)>) -> Font {
+        let square = json!({
+            "nodes": [[0, 0, "l"], [100, 0, "l"], [100, 100, "l"], [0, 100, "l"]],
+            "closed": true
+        });
+        let glyphs: Vec<Value> = std::iter::once(("square", json!([square])))
+            .chain(glyphs)
+            .map(|(name, shapes)| {
+                json!({"name": name, "layers": [{"id": "m", "width": 100, "shapes": shapes}]})
+            })
+            .collect();
+        crate::babelfont_json::load(&json!({
+            "masters": [{"id": "m", "name": "Regular"}],
+            "glyphs": glyphs
+        }))
+        .unwrap()
+    }
+
+    fn component(reference: &str, transform: [f64; 6]) -> Value {
+        json!({"ref": reference, "transform": transform})
+    }
+
+    fn paths(font: &Font, glyph: &str) -> Vec<BezPath> {
+        let master = &font.masters[0];
+        let layer = font.master_layer_for(glyph, master).unwrap();
+        decomposed_paths(font, master, layer, Affine::IDENTITY, 0).unwrap()
+    }
+
+    #[test]
+    fn nested_components_compose_their_transforms() {
+        let font = font(vec![
+            (
+                "inner",
+                json!([component("square", [1., 0., 0., 1., 10., 0.])]),
+            ),
+            (
+                "outer",
+                json!([component("inner", [1., 0., 0., 1., 100., 20.])]),
+            ),
+        ]);
+        let paths = paths(&font, "outer");
+        assert_eq!(paths.len(), 1);
+        assert_eq!(
+            paths[0].bounding_box(),
+            Rect::new(110.0, 20.0, 210.0, 120.0)
+        );
+    }
+
+    #[test]
+    fn mirrored_components_keep_their_winding() {
+        let font = font(vec![(
+            "mirrored",
+            json!([component("square", [-1., 0., 0., 1., 200., 0.])]),
+        )]);
+        let paths = paths(&font, "mirrored");
+        assert_eq!(paths[0].bounding_box(), Rect::new(100.0, 0.0, 200.0, 100.0));
+        assert!(signed_area(&paths[0]) > 0.0);
+    }
+
+    #[test]
+    fn mirrored_components_nested_in_mirrored_components_are_not_reversed() {
+        let font = font(vec![
+            (
+                "once",
+                json!([component("square", [-1., 0., 0., 1., 0., 0.])]),
+            ),
+            (
+                "twice",
+                json!([component("once", [-1., 0., 0., 1., 0., 0.])]),
+            ),
+        ]);
+        let paths = paths(&font, "twice");
+        assert_eq!(paths[0].bounding_box(), Rect::new(0.0, 0.0, 100.0, 100.0));
+        assert!(signed_area(&paths[0]) > 0.0);
+    }
+
+    #[test]
+    fn rotated_components_are_placed_where_they_are_drawn() {
+        let font = font(vec![(
+            "rotated",
+            json!([component("square", [0., 1., -1., 0., 0., 0.])]),
+        )]);
+        let paths = paths(&font, "rotated");
+        assert_eq!(paths[0].bounding_box(), Rect::new(-100.0, 0.0, 0.0, 100.0));
+        assert!(signed_area(&paths[0]) > 0.0);
+    }
+
+    #[test]
+    fn components_referring_to_themselves_stop() {
+        let font = font(vec![(
+            "loop",
+            json!([
+                component("square", [1., 0., 0., 1., 0., 0.]),
+                component("loop", [1., 0., 0., 1., 0., 0.])
+            ]),
+        )]);
+        assert_eq!(paths(&font, "loop").len(), MAX_COMPONENT_DEPTH + 1);
+    }
+
+    #[test]
+    fn paths_which_cant_be_converted_are_an_error() {
+        // Three off-curve points in a row can't make a cubic curve.
+        let broken = json!({
+            "nodes": [[0, 0, "l"], [10, 50, "o"], [50, 90, "o"], [90, 50, "o"], [100, 0, "c"]],
+            "closed": true
+        });
+        let font = font(vec![
+            ("broken", json!([broken])),
+            (
+                "holder",
+                json!([component("broken", [1., 0., 0., 1., 0., 0.])]),
+            ),
+        ]);
+        let master = &font.masters[0];
+        for glyph in ["broken", "holder"].iter() {
+            let error = font.outline(glyph, &master.id).unwrap().unwrap_err();
+            assert!(error.starts_with(&format!("Glyph {} in master Regular", glyph)));
+        }
+    }
+
+    #[test]
+    fn normalize_direction_winds_outers_anticlockwise_and_counters_clockwise() {
+        let outer = reversed(&Rect::new(0.0, 0.0, 300.0, 300.0).to_path(0.1));
+        let counter = Rect::new(100.0, 100.0, 200.0, 200.0).to_path(0.1);
+        let island = reversed(&Rect::new(140.0, 140.0, 160.0, 160.0).to_path(0.1));
+        let areas: Vec<f64> = normalize_direction(vec![outer, counter, island])
+            .iter()
+            .map(signed_area)
+            .collect();
+        assert!(areas[0] > 0.0);
+        assert!(areas[1] < 0.0);
+        assert!(areas[2] > 0.0);
+    }
+
+    #[test]
+    fn normalize_direction_leaves_well_wound_contours_alone() {
+        let paths = vec![
+            Rect::new(0.0, 0.0, 300.0, 300.0).to_path(0.1),
+            reversed(&Rect::new(100.0, 100.0, 200.0, 200.0).to_path(0.1)),
+            Rect::new(400.0, 0.0, 500.0, 100.0).to_path(0.1),
+        ];
+        assert_eq!(normalize_direction(paths.clone()), paths);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::flat::{flatten_edges, spans};
+use kurbo::{Affine, BezPath, ParamCurve, Point, Shape, Vec2};
+
+/// Tolerance (in font units) when flattening outlines to look for overlaps.
+const TOLERANCE: f64 = 0.5;
+/// Distance (in font units) between scanlines when measuring overlaps.
+const SCANLINE: f64 = 0.5;
+
+/// Whether the positioned outlines touch or overlap: either their edges
+/// cross, or a contour of one lies inside the other.
+pub(crate) fn overlaps(
+    left_paths: &[BezPath],
+    right_paths: &[BezPath],
+    x_offset: f32,
+    y_offset: f32,
+) -> bool {
+    let (left, right) = positioned(left_paths, right_paths, x_offset, y_offset);
+    let inside = |paths: &[BezPath], others: &[BezPath]| {
+        paths
+            .iter()
+            .any(|p| start_point(p).map_or(false, |pt| others.iter().any(|o| o.contains(pt))))
+    };
+    if inside(&left, &right) || inside(&right, &left) {
+        return true;
+    }
+    let (left_edges, right_edges) = (edges(&left), edges(&right));
+    left_edges
+        .iter()
+        .any(|l| right_edges.iter().any(|r| edges_cross(*l, *r)))
+}
+
+/// The area shared by the positioned outlines, found by intersecting
+/// their spans along scanlines `SCANLINE` units apart.
+pub(crate) fn overlap_area(
+    left_paths: &[BezPath],
+    right_paths: &[BezPath],
+    x_offset: f32,
+    y_offset: f32,
+) -> f64 {
+    let (left, right) = positioned(left_paths, right_paths, x_offset, y_offset);
+    let (left_edges, right_edges) = (edges(&left), edges(&right));
+    let extent = |edges: &[(Point, Point)]| {
+        edges
+            .iter()
+            .flat_map(|(a, b)| vec![a.y, b.y])
+            .fold(None, |extent, y| match extent {
+                None => Some((y, y)),
+                Some((lo, hi)) => Some((f64::min(lo, y), f64::max(hi, y))),
+            })
+    };
+    let ((left_bottom, left_top), (right_bottom, right_top)) =
+        match (extent(&left_edges), extent(&right_edges)) {
+            (Some(l), Some(r)) => (l, r),
+            _ => return 0.0,
+        };
+    let (bottom, top) = (left_bottom.max(right_bottom), left_top.min(right_top));
+    if top <= bottom {
+        return 0.0;
+    }
+
+    let lines = ((top - bottom) / SCANLINE).ceil() as usize;
+    let mut area = 0.0;
+    for line in 0..lines {
+        let y = bottom + (line as f64 + 0.5) * SCANLINE;
+        let (a, b) = (spans(&left_edges, y), spans(&right_edges, y));
+        // Both sets of spans are sorted and disjoint.
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            let overlap = a[i].1.min(b[j].1) - a[i].0.max(b[j].0);
+            if overlap > 0.0 {
+                area += overlap * SCANLINE;
+            }
+            if a[i].1 < b[j].1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+    area
+}
+
+fn positioned(
+    left_paths: &[BezPath],
+    right_paths: &[BezPath],
+    x_offset: f32,
+    y_offset: f32,
+) -> (Vec<BezPath>, Vec<BezPath>) {
+    let offset1 = Affine::translate(Vec2::new(0.0, y_offset as f64));
+    let offset2 = Affine::translate(Vec2::new(x_offset as f64, 0.0));
+    (
+        left_paths.iter().map(|p| offset1 * p).collect(),
+        right_paths.iter().map(|p| offset2 * p).collect(),
+    )
+}
+
+fn edges(paths: &[BezPath]) -> Vec<(Point, Point)> {
+    paths
+        .iter()
+        .flat_map(|p| flatten_edges(p, TOLERANCE))
+        .collect()
+}
+
+fn start_point(path: &BezPath) -> Option<Point> {
+    path.segments().next().map(|s| s.start())
+}
+
+fn edges_cross((a, b): (Point, Point), (c, d): (Point, Point)) -> bool {
+    let cross = |o: Point, u: Point, q: Point| (u - o).cross(q - o);
+    // Collinear edges pass the cross product tests, so check that the
+    // edges at least share some extent.
+    let apart = a.x.max(b.x) < c.x.min(d.x)
+        || c.x.max(d.x) < a.x.min(b.x)
+        || a.y.max(b.y) < c.y.min(d.y)
+        || c.y.max(d.y) < a.y.min(b.y);
+    !apart && cross(a, b, c) * cross(a, b, d) <= 0.0 && cross(c, d, a) * cross(c, d, b) <= 0.0
+}
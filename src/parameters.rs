@@ -0,0 +1,1114 @@
+use crate::backend::Backend;
+use crate::classes::{reference_band, target_classes, Band};
+use crate::distance::Measurement;
+use crate::guides::MasterGuides;
+use crate::outline::{Outline, EXIT_ANCHOR};
+use crate::presets::{Preset, PRESETS};
+use crate::rules::{glob_match, load_rules, Rule};
+use crate::solver::{Solution, Status};
+use crate::{fspath, master_name};
+use babelfont::Master;
+use core::cmp::Ordering;
+use pyo3::exceptions::{PyIOError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// The default largest size (in the units given) of a dot.
+const DOT_SIZE: f32 = 150.0;
+
+/// A vertical band with its own target distance. Zones are expressed in the
+/// coordinates of the right glyph, i.e. after the left glyph has been raised
+/// to its height.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Zone {
+    pub bottom: f32,
+    pub top: f32,
+    pub target_distance: f32,
+}
+
+/// A vertical band in which shortfalls count for more or less than usual,
+/// in the same coordinates as a `Zone`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ZoneWeight {
+    pub bottom: f32,
+    pub top: f32,
+    pub weight: f32,
+}
+
+/// How the distance between two outlines is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Engine {
+    /// Exact distances between the outline segments
+    Analytic,
+    /// Distance transform of rendered bitmaps
+    Raster,
+    /// Distances between the outlines flattened to straight edges
+    Flattened,
+    /// The flattened engine's distances, computed on the GPU
+    #[cfg(feature = "gpu")]
+    Gpu,
+}
+
+/// What counts as the distance between two outlines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Metric {
+    /// The closest approach in any direction
+    Euclidean,
+    /// The horizontal gap between the outlines along rows they share
+    HorizontalGap,
+    /// The closest approach, with how far it falls short of the target
+    /// weighted by the zone it is in
+    ZoneWeighted,
+}
+
+/// What the solver is trying to achieve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Mode {
+    /// Bring the outlines to the target distance
+    Target,
+    /// Find the smallest kern which keeps the outlines apart by at least
+    /// the target distance
+    Clearance,
+    /// Find the most negative kern which keeps the outlines apart by at
+    /// least the target distance, within the tuck limit
+    Tuck,
+}
+
+/// Which glyph of a pair the height raises.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Raised {
+    /// The left glyph, allowing for its exit anchor, as in cursive
+    /// right-to-left scripts
+    Left,
+    /// The right glyph, as where the following word rises
+    Right,
+}
+
+/// What becomes of the solver's trace for each pair in a batch run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TraceOutput {
+    /// It is kept on the pair's result
+    Return,
+    /// It is logged as a line of JSON
+    Log,
+}
+
+/// What a master's target distances are scaled by, relative to the
+/// default master's, so heavier masters can sit tighter.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ScaleReference {
+    /// The width of a control glyph's stem
+    Stem(String),
+    /// The master's coordinate on an axis (given by name or tag)
+    Axis(String),
+}
+
+/// A master's reference heights, for measuring pairs with punctuation,
+/// quotes and figures in their own bands.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ReferenceHeights {
+    pub x_height: Option<f32>,
+    /// The bottom and top of the figures
+    pub figures: Option<(f32, f32)>,
+}
+
+impl ReferenceHeights {
+    /// The `(ignore_below, ignore_above)` cutoffs for a band, if the
+    /// heights it needs are known.
+    fn cutoffs(&self, band: Band) -> Option<(Option<f32>, Option<f32>)> {
+        match band {
+            Band::High => self.x_height.map(|x| (Some(x), None)),
+            Band::Low => self.x_height.map(|x| (None, Some(x))),
+            Band::Figure => self.figures.map(|(bottom, top)| (Some(bottom), Some(top))),
+        }
+    }
+}
+
+/// The settings which control how a pair is kerned.
+#[derive(Debug, Clone)]
+pub(crate) struct Parameters {
+    pub target_distance: f32,
+    /// How far apart features stacked one above the other must be, if
+    /// different from the target distance
+    pub vertical_target_distance: Option<f32>,
+    pub height: i32,
+    pub raised: Raised,
+    pub max_tuck: f32,
+    /// The largest kern (either way) to return; larger kerns are clamped
+    /// to it and flagged
+    pub max_abs_kern: Option<f32>,
+    /// The most negative kern the solver may go to: an em below no kern
+    /// by default, once the units per em are known
+    pub kern_floor: Option<f32>,
+    /// Zones are checked in order and the first one containing the
+    /// closest approach decides the target distance.
+    pub zones: Vec<Zone>,
+    /// `(height, target distance)` points, sorted by height, between which
+    /// the target for heights outside the zones is interpolated
+    pub target_curve: Vec<(f32, f32)>,
+    /// How much shortfalls count in each band, for the zone-weighted
+    /// metric; elsewhere they count fully
+    pub zone_weights: Vec<ZoneWeight>,
+    /// If non-zero, the pair is measured at every offset within this many
+    /// units above or below the height, and the worst case decides the kern.
+    pub height_range: f32,
+    pub height_step: f32,
+    pub engine: Engine,
+    pub metric: Metric,
+    pub mode: Mode,
+    /// Font units per pixel for the raster engine
+    pub resolution: f32,
+    /// How far (in font units) the analytic engine's distances may be
+    /// above the true distance
+    pub epsilon: f32,
+    /// Flattening tolerance for the flattened engine
+    pub tolerance: f32,
+    /// How close (in font units) to the target distance the solver must
+    /// bring a pair before it accepts the kern
+    pub kern_tolerance: f32,
+    /// Whether distances and heights are given, and kerns returned, in
+    /// thousandths of the em rather than font units
+    pub per_mille: bool,
+    /// Units per em to use for per-mille values, overriding the font's
+    pub upm: Option<f32>,
+    /// Font units per unit of the values given and returned
+    pub scale: f32,
+    /// Vertical shifts applied to each glyph of the pair on top of the
+    /// height, for superiors, inferiors and the like
+    pub left_shift: f32,
+    pub right_shift: f32,
+    /// Italic angle (in degrees) to shear outlines upright by, for all
+    /// masters or for masters by name
+    pub italic_angle: Option<f32>,
+    pub italic_angles: HashMap<String, f32>,
+    /// Whether target distances are measured perpendicular to slanted
+    /// stems rather than horizontally
+    pub perpendicular: bool,
+    /// The horizontal shear (per unit of height) to remove from the outlines
+    /// in the master being measured
+    pub shear: f64,
+    /// Target distances for pairs of glyph classes, which override the
+    /// target distance (but not zones) for pairs whose glyphs belong to them
+    pub target_matrix: HashMap<(String, String), f32>,
+    /// Class memberships to use for the target matrix before kerning groups
+    /// and categories
+    pub target_classes: HashMap<String, Vec<String>>,
+    /// Exceptions for pairs matching glyph name patterns, the first
+    /// matching rule winning
+    pub rules: Vec<Rule>,
+    /// Glyph name patterns for batch runs: if any are given, only pairs
+    /// whose glyphs both match one are kerned, and pairs with a glyph
+    /// matching an excluded pattern are skipped
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Whether to start the solver from the kern the font already has for
+    /// a pair, if any
+    pub seed: bool,
+    /// Where to start the solver, for every pair or for pairs by glyph
+    /// name, ahead of the font's existing kern
+    pub initial_kern: Option<f32>,
+    pub initial_kerns: HashMap<(String, String), f32>,
+    /// The anchor on the left glyph which cursive attachment joins at
+    pub exit_anchor: String,
+    /// A preset to fill in what was not given, which is applied once the
+    /// units per em are known
+    pub preset: Option<&'static Preset>,
+    /// The keyword arguments given, which the preset must not override
+    pub given: Vec<String>,
+    /// Whether batch runs record where the time goes for each pair
+    pub timings: bool,
+    /// Whether batch runs record each step the solver takes for each
+    /// pair, and where to
+    pub trace: Option<TraceOutput>,
+    /// How far the left glyph's dots must stay from the right glyph, if
+    /// they are held to their own target
+    pub dot_target_distance: Option<f32>,
+    /// The largest a contour may be, either way, to count as a dot
+    pub dot_size: f32,
+    /// Whether batch runs suggest how far to raise the right glyph of
+    /// pairs which kerning can't separate
+    pub suggest_rise: bool,
+    /// Optional marks (harakat, say) attached at their anchors to each
+    /// glyph before measuring, so kerns stay clear of them
+    pub marks: Vec<String>,
+    /// Whether batch runs with `marks` also kern each pair without them
+    pub bare_kern: bool,
+    /// Ink below or above these heights (in each glyph's own coordinates)
+    /// is cut away before measuring
+    pub ignore_below: Option<f32>,
+    pub ignore_above: Option<f32>,
+    /// What target distances are scaled by in each master: the default
+    /// master's measure over the master's own, raised to the strength
+    pub scale_by: Option<ScaleReference>,
+    pub scale_strength: f32,
+    /// The factors those come to, by master ID, once measured
+    pub master_scales: HashMap<String, f32>,
+    /// The factor applied to the master being measured, if any
+    pub target_scale: Option<f32>,
+    /// `(optical size, value)` points, sorted by optical size, between
+    /// which each master's target distance and tuck limit are interpolated
+    pub target_by_opsz: Vec<(f32, f32)>,
+    pub max_tuck_by_opsz: Vec<(f32, f32)>,
+    /// The name of the font's optical size axis, filled in by the
+    /// determiner
+    pub opsz_axis: Option<String>,
+    /// Whether pairs with punctuation, quotes or figures are measured only
+    /// in their band (see `classes::reference_band`)
+    pub reference_bands: bool,
+    /// Each master's reference heights by master ID, once measured, and
+    /// those of the master being measured
+    pub master_heights: HashMap<String, ReferenceHeights>,
+    pub reference_heights: Option<ReferenceHeights>,
+    /// Whether specially named guidelines in the font (see
+    /// `guides::GuideRules`) constrain the measurement
+    pub guidelines: bool,
+    /// Those constraints by master ID, once read, and those of the master
+    /// being measured
+    pub master_guides: HashMap<String, MasterGuides>,
+    pub guides: Option<MasterGuides>,
+    /// How far round shapes may overshoot the baseline, x-height and cap
+    /// height without their approaches counting
+    pub overshoot_tolerance: f32,
+    /// `(metric height, signed tolerance)` for each metric overshot in the
+    /// master being measured
+    pub overshoots: Vec<(f32, f32)>,
+}
+
+impl Parameters {
+    pub fn new(target_distance: f32, height: i32, max_tuck: f32) -> Self {
+        Parameters {
+            target_distance,
+            vertical_target_distance: None,
+            height,
+            raised: Raised::Left,
+            max_tuck,
+            max_abs_kern: None,
+            kern_floor: None,
+            zones: vec![],
+            target_curve: vec![],
+            zone_weights: vec![],
+            height_range: 0.0,
+            height_step: 10.0,
+            engine: Engine::Analytic,
+            metric: Metric::Euclidean,
+            mode: Mode::Target,
+            resolution: 4.0,
+            epsilon: 0.1,
+            tolerance: 1.0,
+            kern_tolerance: 10.0,
+            per_mille: false,
+            upm: None,
+            scale: 1.0,
+            left_shift: 0.0,
+            right_shift: 0.0,
+            italic_angle: None,
+            italic_angles: HashMap::new(),
+            perpendicular: false,
+            shear: 0.0,
+            target_matrix: HashMap::new(),
+            target_classes: HashMap::new(),
+            rules: vec![],
+            include: vec![],
+            exclude: vec![],
+            seed: true,
+            initial_kern: None,
+            initial_kerns: HashMap::new(),
+            exit_anchor: EXIT_ANCHOR.to_string(),
+            preset: None,
+            given: vec![],
+            timings: false,
+            trace: None,
+            dot_target_distance: None,
+            dot_size: DOT_SIZE,
+            suggest_rise: false,
+            marks: vec![],
+            bare_kern: false,
+            ignore_below: None,
+            ignore_above: None,
+            scale_by: None,
+            scale_strength: 1.0,
+            master_scales: HashMap::new(),
+            target_scale: None,
+            target_by_opsz: vec![],
+            max_tuck_by_opsz: vec![],
+            opsz_axis: None,
+            reference_bands: false,
+            master_heights: HashMap::new(),
+            reference_heights: None,
+            guidelines: true,
+            master_guides: HashMap::new(),
+            guides: None,
+            overshoot_tolerance: 0.0,
+            overshoots: vec![],
+        }
+    }
+
+    /// Builds parameters from the positional arguments and any keyword
+    /// arguments given to a Python method.
+    pub fn from_python(
+        target_distance: f32,
+        height: i32,
+        max_tuck: f32,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<Self> {
+        let mut parameters = Parameters::new(target_distance, height, max_tuck);
+        if let Some(kwargs) = kwargs {
+            for (key, value) in kwargs.iter() {
+                let key: &str = key.extract()?;
+                parameters.given.push(key.to_string());
+                match key {
+                    "zones" => {
+                        let zones: Vec<(f32, f32, f32)> = value.extract()?;
+                        parameters.zones = zones
+                            .into_iter()
+                            .map(|(bottom, top, target_distance)| Zone {
+                                bottom,
+                                top,
+                                target_distance,
+                            })
+                            .collect();
+                    }
+                    "target_by_height" => parameters.target_curve = target_curve(value)?,
+                    "zone_weights" => {
+                        let weights: Vec<(f32, f32, f32)> = value.extract()?;
+                        if weights.iter().any(|(_, _, weight)| *weight < 0.0) {
+                            return Err(PyValueError::new_err("zone weights must not be negative"));
+                        }
+                        parameters.zone_weights = weights
+                            .into_iter()
+                            .map(|(bottom, top, weight)| ZoneWeight {
+                                bottom,
+                                top,
+                                weight,
+                            })
+                            .collect();
+                    }
+                    "dot_target_distance" => {
+                        let distance: f32 = value.extract()?;
+                        if distance < 0.0 {
+                            return Err(PyValueError::new_err(
+                                "dot_target_distance must not be negative",
+                            ));
+                        }
+                        parameters.dot_target_distance = Some(distance);
+                    }
+                    "dot_size" => {
+                        parameters.dot_size = value.extract()?;
+                        if parameters.dot_size <= 0.0 {
+                            return Err(PyValueError::new_err("dot_size must be positive"));
+                        }
+                    }
+                    "vertical_target_distance" => {
+                        let distance: f32 = value.extract()?;
+                        if distance <= 0.0 {
+                            return Err(PyValueError::new_err(
+                                "vertical_target_distance must be positive",
+                            ));
+                        }
+                        parameters.vertical_target_distance = Some(distance);
+                    }
+                    "max_abs_kern" => {
+                        let limit: f32 = value.extract()?;
+                        if limit <= 0.0 {
+                            return Err(PyValueError::new_err("max_abs_kern must be positive"));
+                        }
+                        parameters.max_abs_kern = Some(limit);
+                    }
+                    "kern_floor" => {
+                        let floor: f32 = value.extract()?;
+                        if floor >= 0.0 {
+                            return Err(PyValueError::new_err("kern_floor must be negative"));
+                        }
+                        parameters.kern_floor = Some(floor);
+                    }
+                    "height_range" => parameters.height_range = value.extract::<f32>()?.abs(),
+                    "height_step" => {
+                        parameters.height_step = value.extract()?;
+                        if parameters.height_step <= 0.0 {
+                            return Err(PyValueError::new_err("height_step must be positive"));
+                        }
+                    }
+                    "engine" => {
+                        parameters.engine = match value.extract::<&str>()? {
+                            "analytic" => Engine::Analytic,
+                            "raster" => Engine::Raster,
+                            "flattened" => Engine::Flattened,
+                            #[cfg(feature = "gpu")]
+                            "gpu" => Engine::Gpu,
+                            #[cfg(not(feature = "gpu"))]
+                            "gpu" => {
+                                return Err(PyValueError::new_err(
+                                    "kerndeterminer was built without the gpu feature",
+                                ))
+                            }
+                            other => {
+                                return Err(PyValueError::new_err(format!(
+                                    "Unknown engine {:}",
+                                    other
+                                )))
+                            }
+                        }
+                    }
+                    "metric" => {
+                        parameters.metric = match value.extract::<&str>()? {
+                            "euclidean" => Metric::Euclidean,
+                            "horizontal_gap" => Metric::HorizontalGap,
+                            "zone_weighted" => Metric::ZoneWeighted,
+                            other => {
+                                return Err(PyValueError::new_err(format!(
+                                    "Unknown metric {:}",
+                                    other
+                                )))
+                            }
+                        }
+                    }
+                    "mode" => {
+                        parameters.mode = match value.extract::<&str>()? {
+                            "target" => Mode::Target,
+                            "clearance" => Mode::Clearance,
+                            "tuck" => Mode::Tuck,
+                            other => {
+                                return Err(PyValueError::new_err(format!(
+                                    "Unknown mode {:}",
+                                    other
+                                )))
+                            }
+                        }
+                    }
+                    "resolution" => {
+                        parameters.resolution = value.extract()?;
+                        if parameters.resolution <= 0.0 {
+                            return Err(PyValueError::new_err("resolution must be positive"));
+                        }
+                    }
+                    "epsilon" => {
+                        parameters.epsilon = value.extract()?;
+                        if parameters.epsilon <= 0.0 {
+                            return Err(PyValueError::new_err("epsilon must be positive"));
+                        }
+                    }
+                    "tolerance" => {
+                        parameters.tolerance = value.extract()?;
+                        if parameters.tolerance <= 0.0 {
+                            return Err(PyValueError::new_err("tolerance must be positive"));
+                        }
+                    }
+                    "kern_tolerance" => {
+                        parameters.kern_tolerance = value.extract()?;
+                        if parameters.kern_tolerance <= 0.0 {
+                            return Err(PyValueError::new_err("kern_tolerance must be positive"));
+                        }
+                    }
+                    "left_shift" => parameters.left_shift = value.extract()?,
+                    "right_shift" => parameters.right_shift = value.extract()?,
+                    "italic_angle" => {
+                        if let Ok(angles) = value.extract::<HashMap<String, f32>>() {
+                            parameters.italic_angles = angles;
+                        } else {
+                            parameters.italic_angle = Some(value.extract()?);
+                        }
+                    }
+                    "perpendicular" => parameters.perpendicular = value.extract()?,
+                    "target_matrix" => parameters.target_matrix = value.extract()?,
+                    "target_classes" => parameters.target_classes = value.extract()?,
+                    "seed" => parameters.seed = value.extract()?,
+                    "raise_glyph" => {
+                        parameters.raised = match value.extract::<&str>()? {
+                            "left" => Raised::Left,
+                            "right" => Raised::Right,
+                            other => {
+                                return Err(PyValueError::new_err(format!(
+                                    "raise_glyph must be 'left' or 'right', not {:}",
+                                    other
+                                )))
+                            }
+                        }
+                    }
+                    "initial_kern" => match value.extract::<f32>() {
+                        Ok(kern) => parameters.initial_kern = Some(kern),
+                        Err(_) => parameters.initial_kerns = value.extract().map_err(|_| {
+                            PyTypeError::new_err(
+                                "initial_kern must be a number or a dictionary of (left, right) pairs",
+                            )
+                        })?,
+                    },
+                    "exit_anchor" => parameters.exit_anchor = value.extract()?,
+                    "preset" => {
+                        let name: &str = value.extract()?;
+                        parameters.preset = Some(Preset::named(name).ok_or_else(|| {
+                            let names: Vec<&str> = PRESETS.iter().map(|p| p.name).collect();
+                            PyValueError::new_err(format!(
+                                "Unknown preset {:}; presets are {:}",
+                                name,
+                                names.join(", ")
+                            ))
+                        })?);
+                    }
+                    "rules" => {
+                        let path = fspath(value)?;
+                        parameters.rules = load_rules(&path).map_err(PyIOError::new_err)?;
+                    }
+                    "include" => parameters.include = patterns(value)?,
+                    "exclude" => parameters.exclude = patterns(value)?,
+                    "suggest_rise" => parameters.suggest_rise = value.extract()?,
+                    "marks" => parameters.marks = value.extract()?,
+                    "bare_kern" => parameters.bare_kern = value.extract()?,
+                    "ignore_below" => parameters.ignore_below = value.extract()?,
+                    "ignore_above" => parameters.ignore_above = value.extract()?,
+                    "scale_by_stem" => {
+                        parameters.scale_by = Some(ScaleReference::Stem(value.extract()?))
+                    }
+                    "scale_by_axis" => {
+                        parameters.scale_by = Some(ScaleReference::Axis(value.extract()?))
+                    }
+                    "scale_strength" => {
+                        parameters.scale_strength = value.extract()?;
+                        if parameters.scale_strength < 0.0 {
+                            return Err(PyValueError::new_err(
+                                "scale_strength must not be negative",
+                            ));
+                        }
+                    }
+                    "target_by_opsz" => {
+                        parameters.target_by_opsz = opsz_curve(value, "target_by_opsz")?
+                    }
+                    "max_tuck_by_opsz" => {
+                        parameters.max_tuck_by_opsz = opsz_curve(value, "max_tuck_by_opsz")?
+                    }
+                    "reference_bands" => parameters.reference_bands = value.extract()?,
+                    "guidelines" => parameters.guidelines = value.extract()?,
+                    "overshoot_tolerance" => {
+                        parameters.overshoot_tolerance = value.extract()?;
+                        if parameters.overshoot_tolerance < 0.0 {
+                            return Err(PyValueError::new_err(
+                                "overshoot_tolerance must not be negative",
+                            ));
+                        }
+                    }
+                    "timings" => parameters.timings = value.extract()?,
+                    "trace" => {
+                        parameters.trace = match value.extract::<&str>() {
+                            Ok("log") => Some(TraceOutput::Log),
+                            Ok(other) => {
+                                return Err(PyValueError::new_err(format!(
+                                    "trace must be True, False or \"log\", not {:}",
+                                    other
+                                )))
+                            }
+                            Err(_) => match value.extract::<bool>()? {
+                                true => Some(TraceOutput::Return),
+                                false => None,
+                            },
+                        }
+                    }
+                    "per_mille" => parameters.per_mille = value.extract()?,
+                    "upm" => {
+                        let upm: f32 = value.extract()?;
+                        if upm <= 0.0 {
+                            return Err(PyValueError::new_err("upm must be positive"));
+                        }
+                        parameters.upm = Some(upm);
+                    }
+                    _ => return Err(PyTypeError::new_err(format!("Unknown parameter {:}", key))),
+                }
+            }
+        }
+        Ok(parameters)
+    }
+
+    /// Converts per-mille distances and heights into font units, for a
+    /// font with the given units per em. Does nothing unless `per_mille`
+    /// was asked for, or a preset (whose values are per-mille) was given.
+    /// The tuck limit is a proportion of the glyph's width, so is left
+    /// alone.
+    pub fn for_upm(mut self, font_upm: Option<f32>) -> PyResult<Self> {
+        let default_floor = self.upm.or(font_upm).map(|upm| -upm);
+        if let Some(preset) = self.preset.take() {
+            let upm = self.upm.or(font_upm).ok_or_else(|| {
+                PyValueError::new_err("presets need the units per em, given as upm")
+            })?;
+            let scale = if self.per_mille { 1.0 } else { upm / 1000.0 };
+            let given = std::mem::take(&mut self.given);
+            preset.apply(&mut self, &given, scale);
+        }
+        if !self.per_mille {
+            self.kern_floor = self.kern_floor.or(default_floor);
+            return Ok(self);
+        }
+        let upm = self.upm.or(font_upm).ok_or_else(|| {
+            PyValueError::new_err("per_mille needs the units per em, given as upm")
+        })?;
+        let scale = upm / 1000.0;
+        self.scale = scale;
+        self.target_distance *= scale;
+        if let Some(limit) = self.max_abs_kern.as_mut() {
+            *limit *= scale;
+        }
+        if let Some(kern) = self.initial_kern.as_mut() {
+            *kern *= scale;
+        }
+        for kern in self.initial_kerns.values_mut() {
+            *kern *= scale;
+        }
+        self.kern_floor = self.kern_floor.map(|floor| floor * scale).or(default_floor);
+        if let Some(distance) = self.dot_target_distance.as_mut() {
+            *distance *= scale;
+        }
+        self.dot_size *= scale;
+        self.overshoot_tolerance *= scale;
+        self.kern_tolerance *= scale;
+        if let Some(cutoff) = self.ignore_below.as_mut() {
+            *cutoff *= scale;
+        }
+        if let Some(cutoff) = self.ignore_above.as_mut() {
+            *cutoff *= scale;
+        }
+        if let Some(distance) = self.vertical_target_distance.as_mut() {
+            *distance *= scale;
+        }
+        self.height = (self.height as f32 * scale).round() as i32;
+        self.height_range *= scale;
+        self.height_step *= scale;
+        self.left_shift *= scale;
+        self.right_shift *= scale;
+        for zone in self.zones.iter_mut() {
+            zone.bottom *= scale;
+            zone.top *= scale;
+            zone.target_distance *= scale;
+        }
+        for (y, target) in self.target_curve.iter_mut() {
+            *y *= scale;
+            *target *= scale;
+        }
+        for zone in self.zone_weights.iter_mut() {
+            zone.bottom *= scale;
+            zone.top *= scale;
+        }
+        for target in self.target_matrix.values_mut() {
+            *target *= scale;
+        }
+        for rule in self.rules.iter_mut() {
+            if let Some(target) = rule.target_distance.as_mut() {
+                *target *= scale;
+            }
+        }
+        for (_, target) in self.target_by_opsz.iter_mut() {
+            *target *= scale;
+        }
+        Ok(self)
+    }
+
+    /// The parameters for measuring in a particular master, which differ if
+    /// they depend on its optical size, its target distances are scaled or
+    /// it has an italic angle to remove, guidelines drawn across it or
+    /// metrics to allow overshoots at. A target distance for the optical
+    /// size scales the zones and other targets along with it.
+    pub fn for_master<B: Backend + ?Sized>(&self, font: &B, master: &Master) -> Parameters {
+        let mut parameters = self.clone();
+        let opsz = self
+            .opsz_axis
+            .as_ref()
+            .and_then(|axis| master.location.0.get(axis).copied());
+        if let Some(opsz) = opsz {
+            if let Some(target) = interpolate(&self.target_by_opsz, opsz as f64) {
+                if self.target_distance > 0.0 {
+                    parameters.scale_targets(target as f32 / self.target_distance);
+                }
+                parameters.target_distance = target as f32;
+            }
+            if let Some(max_tuck) = interpolate(&self.max_tuck_by_opsz, opsz as f64) {
+                parameters.max_tuck = max_tuck as f32;
+            }
+        }
+        parameters.reference_heights = self.master_heights.get(&master.id).copied();
+        if let Some(factor) = self.master_scales.get(&master.id).copied() {
+            parameters.scale_targets(factor);
+            parameters.target_scale = Some(factor);
+        }
+        let angle = self
+            .italic_angles
+            .get(&master_name(master))
+            .copied()
+            .or(self.italic_angle)
+            .unwrap_or(0.0) as f64;
+        if angle != 0.0 {
+            parameters.shear = angle.to_radians().tan();
+            let widen = parameters.widen();
+            parameters.target_distance *= widen;
+            for zone in parameters.zones.iter_mut() {
+                zone.target_distance *= widen;
+            }
+            for (_, target) in parameters.target_curve.iter_mut() {
+                *target *= widen;
+            }
+        }
+        if self.overshoot_tolerance > 0.0 {
+            parameters.overshoots = overshoots(font, master, self.overshoot_tolerance);
+        }
+        if let Some(guides) = self.master_guides.get(&master.id) {
+            guides.global.apply(&mut parameters);
+            parameters.guides = Some(guides.clone());
+        }
+        parameters
+    }
+
+    /// Multiplies every target distance by a factor.
+    fn scale_targets(&mut self, factor: f32) {
+        self.target_distance *= factor;
+        if let Some(distance) = self.vertical_target_distance.as_mut() {
+            *distance *= factor;
+        }
+        if let Some(distance) = self.dot_target_distance.as_mut() {
+            *distance *= factor;
+        }
+        for zone in self.zones.iter_mut() {
+            zone.target_distance *= factor;
+        }
+        for (_, target) in self.target_curve.iter_mut() {
+            *target *= factor;
+        }
+        for target in self.target_matrix.values_mut() {
+            *target *= factor;
+        }
+        for rule in self.rules.iter_mut() {
+            if let Some(target) = rule.target_distance.as_mut() {
+                *target *= factor;
+            }
+        }
+    }
+
+    /// How much wider than a target distance the horizontal gap must be
+    /// once stems are sheared upright, if targets are perpendicular to them.
+    pub fn widen(&self) -> f32 {
+        if self.perpendicular {
+            (1.0 + self.shear * self.shear).sqrt() as f32
+        } else {
+            1.0
+        }
+    }
+
+    /// The parameters for a particular pair, which differ if a rule
+    /// matches its glyph names or the target matrix has a target for the
+    /// classes its glyphs belong to, or if it is measured in a reference
+    /// band. Classes are tried most specific first (see
+    /// `classes::target_classes`), the left glyph's before the right
+    /// glyph's. A rule's target takes precedence over the matrix. Of the
+    /// glyphs' bands, quotes win over low punctuation, which wins over
+    /// figures; the band replaces any cutoffs given. Guidelines drawn in
+    /// either glyph tighten the cutoffs and add zones.
+    pub fn for_pair<B: Backend + ?Sized>(
+        &self,
+        font: &B,
+        left_glyph: &str,
+        right_glyph: &str,
+    ) -> Cow<Parameters> {
+        let glyph_rules = self
+            .guides
+            .as_ref()
+            .and_then(|guides| guides.for_pair(left_glyph, right_glyph));
+        if self.target_matrix.is_empty()
+            && self.rules.is_empty()
+            && self.reference_heights.is_none()
+            && glyph_rules.is_none()
+        {
+            return Cow::Borrowed(self);
+        }
+        let cutoffs = self.reference_heights.and_then(|heights| {
+            let band = reference_band(font, left_glyph).max(reference_band(font, right_glyph))?;
+            heights.cutoffs(band)
+        });
+        let rule = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(left_glyph, right_glyph));
+        let target = rule.and_then(|rule| rule.target_distance).or_else(|| {
+            if self.target_matrix.is_empty() {
+                return None;
+            }
+            let lefts = target_classes(font, left_glyph, true, &self.target_classes);
+            let rights = target_classes(font, right_glyph, false, &self.target_classes);
+            lefts.iter().find_map(|left| {
+                rights
+                    .iter()
+                    .find_map(|right| self.target_matrix.get(&(left.clone(), right.clone())))
+                    .copied()
+            })
+        });
+        let max_tuck = rule.and_then(|rule| rule.max_tuck);
+        if target.is_none() && max_tuck.is_none() && cutoffs.is_none() && glyph_rules.is_none() {
+            return Cow::Borrowed(self);
+        }
+        let mut parameters = self.clone();
+        if let Some(target) = target {
+            parameters.target_distance = target * self.widen();
+        }
+        if let Some(max_tuck) = max_tuck {
+            parameters.max_tuck = max_tuck;
+        }
+        if let Some((below, above)) = cutoffs {
+            parameters.ignore_below = below;
+            parameters.ignore_above = above;
+        }
+        if let Some(rules) = glyph_rules {
+            rules.apply(&mut parameters);
+        }
+        Cow::Owned(parameters)
+    }
+
+    /// Whether a batch run should kern a pair, going by the `include` and
+    /// `exclude` patterns.
+    pub fn wants_pair(&self, left_glyph: &str, right_glyph: &str) -> bool {
+        let matches_any =
+            |patterns: &[String], name: &str| patterns.iter().any(|p| glob_match(p, name));
+        let included = |name: &str| self.include.is_empty() || matches_any(&self.include, name);
+        included(left_glyph)
+            && included(right_glyph)
+            && !matches_any(&self.exclude, left_glyph)
+            && !matches_any(&self.exclude, right_glyph)
+    }
+
+    /// The kern the caller gave to start the solver from for a pair, if
+    /// any.
+    pub fn initial_kern_for(&self, left_glyph: &str, right_glyph: &str) -> Option<f32> {
+        self.initial_kerns
+            .get(&(left_glyph.to_string(), right_glyph.to_string()))
+            .copied()
+            .or(self.initial_kern)
+    }
+
+    /// How far the left glyph is raised relative to the right glyph: the
+    /// height (allowing for the left glyph's exit anchor), or with
+    /// `raise_glyph="right"` the right glyph's height the other way, plus
+    /// any shifts. Measurements are made in the right glyph's coordinates,
+    /// so the zones move with the right glyph.
+    pub fn left_offset(&self, left: &Outline) -> f64 {
+        let height = match self.raised {
+            Raised::Left => left.vertical_offset(self.height, &self.exit_anchor),
+            Raised::Right => -self.height as f64,
+        };
+        (height + (self.left_shift - self.right_shift) as f64) * self.vertical_scale()
+    }
+
+    /// How much outlines are scaled vertically before measuring, so that
+    /// a single distance meets both the horizontal and vertical targets:
+    /// features stacked at the vertical target come out at the target
+    /// distance. Offsets, height ranges and zones are scaled to match.
+    pub fn vertical_scale(&self) -> f64 {
+        match self.vertical_target_distance {
+            Some(vertical) if self.target_distance > 0.0 => {
+                (self.target_distance / vertical) as f64
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// Converts a solution's kern into the units the caller asked for,
+    /// first clamping it to `max_abs_kern`.
+    pub fn output(&self, solution: Solution) -> Solution {
+        let solution = match self.max_abs_kern {
+            Some(limit) if solution.kern.abs() > limit => Solution {
+                kern: limit.copysign(solution.kern),
+                status: Status::KernLimited,
+                ..solution
+            },
+            _ => solution,
+        };
+        Solution {
+            kern: solution.kern / self.scale,
+            ..solution
+        }
+    }
+
+    /// The vertical offsets at which to measure a pair whose left glyph
+    /// sits at the given offset.
+    pub fn heights(&self, base: f64) -> Vec<f64> {
+        if self.height_range <= 0.0 {
+            return vec![base];
+        }
+        let scale = self.vertical_scale();
+        let range = self.height_range as f64 * scale;
+        let step = self.height_step as f64 * scale;
+        let steps = (2.0 * range / step).ceil() as usize;
+        (0..=steps)
+            .map(|i| (base - range + i as f64 * step).min(base + range))
+            .collect()
+    }
+
+    /// The target distance for a closest approach at the given height:
+    /// the first zone's containing it, or failing that the target curve's
+    /// or the target distance.
+    pub fn target_at(&self, y: f64) -> f64 {
+        let y = y / self.vertical_scale();
+        self.zones
+            .iter()
+            .find(|z| y >= z.bottom as f64 && y <= z.top as f64)
+            .map(|z| z.target_distance as f64)
+            .or_else(|| interpolate(&self.target_curve, y))
+            .unwrap_or(self.target_distance as f64)
+    }
+
+    /// Whether a height lies beyond one of the master's metrics, but by no
+    /// more than the overshoot tolerance.
+    fn in_overshoot(&self, y: f64) -> bool {
+        self.overshoots.iter().any(|&(metric, overshoot)| {
+            let beyond = (y - metric as f64) / overshoot as f64;
+            beyond > 0.0 && beyond <= 1.0
+        })
+    }
+
+    /// How much a shortfall at the given height counts: under the
+    /// zone-weighted metric, the weight of the first band containing it,
+    /// and otherwise fully.
+    pub fn weight_at(&self, y: f64) -> f64 {
+        if self.metric != Metric::ZoneWeighted {
+            return 1.0;
+        }
+        let y = y / self.vertical_scale();
+        self.zone_weights
+            .iter()
+            .find(|z| y >= z.bottom as f64 && y <= z.top as f64)
+            .map_or(1.0, |z| z.weight as f64)
+    }
+
+    /// The smallest and largest weights a shortfall can have.
+    pub fn weight_range(&self) -> (f64, f64) {
+        if self.metric != Metric::ZoneWeighted {
+            return (1.0, 1.0);
+        }
+        self.zone_weights
+            .iter()
+            .map(|z| z.weight as f64)
+            .fold((1.0, 1.0), |(low, high), w| (low.min(w), high.max(w)))
+    }
+
+    /// A measurement of `distance` at the given height, against the
+    /// target there, or `None` if the height is within the overshoot
+    /// tolerance of a metric, where approaches don't count at all.
+    pub fn measurement(&self, distance: f64, y: f64) -> Option<Measurement> {
+        if self.in_overshoot(y / self.vertical_scale()) {
+            return None;
+        }
+        Some(Measurement {
+            distance,
+            target: self.target_at(y),
+            weight: self.weight_at(y),
+        })
+    }
+
+    /// Whether the target distance, or how much falling short of it
+    /// counts, depends on the height of the closest approach.
+    pub fn target_varies(&self) -> bool {
+        !self.zones.is_empty()
+            || !self.target_curve.is_empty()
+            || !self.overshoots.is_empty()
+            || (self.metric == Metric::ZoneWeighted && !self.zone_weights.is_empty())
+    }
+
+    /// The largest target distance at any height.
+    pub fn max_target(&self) -> f32 {
+        self.zones
+            .iter()
+            .map(|z| z.target_distance)
+            .chain(self.target_curve.iter().map(|(_, target)| *target))
+            .fold(self.target_distance, f32::max)
+    }
+}
+
+/// The metrics round shapes overshoot upwards; they overshoot the
+/// baseline downwards.
+const OVERSHOT_METRICS: [&str; 2] = ["xHeight", "capHeight"];
+
+/// Where round shapes may overshoot in a master, as `(metric height,
+/// signed tolerance)`: below the baseline, and above any of the
+/// `OVERSHOT_METRICS` the master has.
+fn overshoots<B: Backend + ?Sized>(font: &B, master: &Master, tolerance: f32) -> Vec<(f32, f32)> {
+    let mut overshoots = vec![(0.0, -tolerance)];
+    for metric in OVERSHOT_METRICS.iter() {
+        if let Some(height) = font.metric(&master.id, metric) {
+            overshoots.push((height, tolerance));
+        }
+    }
+    overshoots
+}
+
+/// Glyph name patterns given as a single string or a list of them.
+fn patterns(value: &PyAny) -> PyResult<Vec<String>> {
+    if let Ok(pattern) = value.extract::<String>() {
+        return Ok(vec![pattern]);
+    }
+    value.extract()
+}
+
+/// How far apart (in the units given) a callable target is sampled, and
+/// the heights it is sampled between.
+const TARGET_SAMPLE_STEP: f32 = 10.0;
+const TARGET_SAMPLE_RANGE: (f32, f32) = (-1000.0, 2000.0);
+
+/// A target by height, given as a list of `(height, target)` points or a
+/// callable taking a height, which is sampled once up front so that
+/// measuring needn't call back into Python.
+fn target_curve(value: &PyAny) -> PyResult<Vec<(f32, f32)>> {
+    let mut points: Vec<(f32, f32)> = if value.is_callable() {
+        let (low, high) = TARGET_SAMPLE_RANGE;
+        let steps = ((high - low) / TARGET_SAMPLE_STEP) as usize;
+        (0..=steps)
+            .map(|i| {
+                let y = low + i as f32 * TARGET_SAMPLE_STEP;
+                Ok((y, value.call1((y,))?.extract()?))
+            })
+            .collect::<PyResult<_>>()?
+    } else {
+        value.extract()?
+    };
+    if points.iter().any(|(_, target)| *target < 0.0) {
+        return Err(PyValueError::new_err(
+            "target_by_height must not give negative targets",
+        ));
+    }
+    points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+    Ok(points)
+}
+
+/// `(optical size, value)` points given as a list of pairs or a
+/// dictionary, sorted by optical size.
+fn opsz_curve(value: &PyAny, name: &str) -> PyResult<Vec<(f32, f32)>> {
+    let mut points: Vec<(f32, f32)> = match value.downcast::<PyDict>() {
+        Ok(dict) => dict
+            .iter()
+            .map(|(o, v)| Ok((o.extract()?, v.extract()?)))
+            .collect::<PyResult<_>>()?,
+        Err(_) => value.extract().map_err(|_| {
+            PyTypeError::new_err(format!(
+                "{:} must be a list of (optical size, value) pairs or a dictionary",
+                name
+            ))
+        })?,
+    };
+    if points.iter().any(|(_, v)| *v < 0.0) {
+        return Err(PyValueError::new_err(format!(
+            "{:} must not give negative values",
+            name
+        )));
+    }
+    points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+    Ok(points)
+}
+
+/// Interpolates linearly between points sorted by x, holding the end
+/// values beyond them. Returns `None` if there are no points.
+fn interpolate(points: &[(f32, f32)], x: f64) -> Option<f64> {
+    let (first, last) = (points.first()?, points.last()?);
+    if x <= first.0 as f64 {
+        return Some(first.1 as f64);
+    }
+    if x >= last.0 as f64 {
+        return Some(last.1 as f64);
+    }
+    let after = points.iter().position(|p| p.0 as f64 > x)?;
+    let (x0, y0) = points[after - 1];
+    let (x1, y1) = points[after];
+    let t = (x - x0 as f64) / (x1 - x0) as f64;
+    Some(y0 as f64 + t * (y1 - y0) as f64)
+}
@@ -0,0 +1,115 @@
+use crate::parameters::{Mode, Parameters, Zone};
+
+/// Starting values for the parameters suited to a script. Distances are in
+/// thousandths of the em.
+#[derive(Debug)]
+pub(crate) struct Preset {
+    pub name: &'static str,
+    /// Targets for pairs of glyph categories, as for `target_matrix`
+    targets: &'static [(&'static str, &'static str, f32)],
+    /// `(bottom, top, target distance)`
+    zones: &'static [(f32, f32, f32)],
+    height_range: f32,
+    height_step: f32,
+    mode: Mode,
+    /// The anchor on the left glyph which cursive attachment joins at
+    exit_anchor: &'static str,
+}
+
+pub(crate) const PRESETS: &[Preset] = &[
+    Preset {
+        name: "latin",
+        targets: &[
+            ("letters", "letters", 80.0),
+            ("letters", "punctuation", 110.0),
+            ("punctuation", "letters", 110.0),
+            ("letters", "figures", 90.0),
+            ("figures", "letters", 90.0),
+            ("figures", "figures", 90.0),
+        ],
+        zones: &[],
+        height_range: 0.0,
+        height_step: 10.0,
+        mode: Mode::Target,
+        exit_anchor: "exit",
+    },
+    Preset {
+        name: "arabic-naskh",
+        targets: &[
+            ("letters", "letters", 70.0),
+            ("letters", "punctuation", 100.0),
+            ("punctuation", "letters", 100.0),
+        ],
+        // Dots and marks above may come a little closer.
+        zones: &[(450.0, 1000.0, 55.0)],
+        height_range: 0.0,
+        height_step: 10.0,
+        mode: Mode::Target,
+        exit_anchor: "exit",
+    },
+    Preset {
+        name: "nastaliq",
+        targets: &[("letters", "letters", 60.0)],
+        // Tails sweeping under the next glyph may tuck in close.
+        zones: &[(-1000.0, -150.0, 35.0)],
+        height_range: 40.0,
+        height_step: 20.0,
+        mode: Mode::Target,
+        exit_anchor: "exit",
+    },
+    Preset {
+        name: "devanagari",
+        targets: &[
+            ("letters", "letters", 70.0),
+            ("letters", "punctuation", 100.0),
+            ("punctuation", "letters", 100.0),
+        ],
+        zones: &[],
+        height_range: 0.0,
+        height_step: 10.0,
+        mode: Mode::Target,
+        exit_anchor: "exit",
+    },
+];
+
+impl Preset {
+    pub fn named(name: &str) -> Option<&'static Preset> {
+        PRESETS.iter().find(|p| p.name == name)
+    }
+
+    /// Fills in the parameters which were not given explicitly (in
+    /// `given`), scaling distances by `scale` to the units in use. Targets
+    /// given explicitly for a pair of classes replace the preset's.
+    pub fn apply(&self, parameters: &mut Parameters, given: &[String], scale: f32) {
+        let given = |key: &str| given.iter().any(|g| g == key);
+        for (left, right, target) in self.targets {
+            parameters
+                .target_matrix
+                .entry((left.to_string(), right.to_string()))
+                .or_insert(target * scale);
+        }
+        if !given("zones") {
+            parameters.zones = self
+                .zones
+                .iter()
+                .map(|(bottom, top, target_distance)| Zone {
+                    bottom: bottom * scale,
+                    top: top * scale,
+                    target_distance: target_distance * scale,
+                })
+                .collect();
+        }
+        if !given("height_range") {
+            parameters.height_range = self.height_range * scale;
+        }
+        if !given("height_step") {
+            parameters.height_step = self.height_step * scale;
+        }
+        if !given("mode") {
+            parameters.mode = self.mode;
+        }
+        if !given("exit_anchor") {
+            parameters.exit_anchor = self.exit_anchor.to_string();
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::distance::segment_distance;
+use crate::explain::closest_at;
+use crate::flat::flatten_edges;
+use crate::outline::{Outline, ENTRY_ANCHOR};
+use crate::parameters::Parameters;
+use crate::solver::minimum_kern;
+use kurbo::{Affine, BezPath, Rect, Shape, Vec2};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+/// Each contour flattened to a list of points.
+fn point_lists(paths: &[BezPath], transform: Affine, tolerance: f64) -> Vec<Vec<(f64, f64)>> {
+    paths
+        .iter()
+        .map(|p| {
+            let edges = flatten_edges(&(transform * p), tolerance);
+            edges.iter().map(|(a, _)| (a.x, a.y)).collect()
+        })
+        .collect()
+}
+
+/// Everything an editor needs to draw a pair at a kern (in font units):
+/// both outlines positioned and flattened to point lists, the closest
+/// points between them with the distance measured and its target, and
+/// the vertical line the right glyph's leftmost point can't pass because
+/// of the tuck limit. Coordinates are in font units; distances and the
+/// kern are in the units the caller used.
+pub(crate) fn preview<'py>(
+    py: Python<'py>,
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    kern: f32,
+) -> PyResult<&'py PyDict> {
+    let dict = PyDict::new(py);
+    let scale = parameters.scale as f64;
+    let offset = parameters.left_offset(left);
+    let left_transform = Affine::translate(Vec2::new(0.0, offset));
+    let right_transform = Affine::translate(Vec2::new((kern + left.width) as f64, 0.0));
+    let tolerance = parameters.tolerance as f64;
+    dict.set_item("kern", kern / parameters.scale)?;
+    dict.set_item("left", point_lists(&left.paths, left_transform, tolerance))?;
+    dict.set_item(
+        "right",
+        point_lists(&right.paths, right_transform, tolerance),
+    )?;
+
+    if let Some((_, approach)) = closest_at(left, right, parameters, kern) {
+        let (_, p, q) = segment_distance(
+            approach.left_segment,
+            approach.right_segment,
+            parameters.epsilon as f64,
+        );
+        dict.set_item("closest", ((p.x, p.y), (q.x, q.y)))?;
+        dict.set_item("distance", approach.measurement.distance / scale)?;
+        dict.set_item("target", approach.measurement.target / scale)?;
+    }
+
+    let bounds: Option<Rect> = left
+        .paths
+        .iter()
+        .map(|p| (left_transform * p).bounding_box())
+        .chain(
+            right
+                .paths
+                .iter()
+                .map(|p| (right_transform * p).bounding_box()),
+        )
+        .reduce(|a, b| a.union(b));
+    if let (Some(bounds), Some(lsb)) = (bounds, right.lsb()) {
+        let x = (minimum_kern(left, right, parameters) + left.width) as f64 + lsb;
+        dict.set_item("tuck_limit", ((x, bounds.y0), (x, bounds.y1)))?;
+    }
+    Ok(dict)
+}
+
+/// Lays a string of glyphs out as the determiner sees them, for proofing
+/// whole words: each glyph follows the last glyph's advance plus the kern
+/// between them (`kerns[i]` comes after glyph `i`), and the right glyph of
+/// each pair is raised by its rise (`rises[i]`). With `cursive`, each glyph
+/// is also raised so that its `exit_anchor` meets the entry anchor of the
+/// glyph to its right, as cursive attachment in right-to-left text would
+/// place it; the rightmost glyph sits on the baseline. Returns a
+/// dictionary for each glyph of its name, its `x` and `y` placement, the
+/// `kern` before it, and its contours positioned and flattened to point
+/// lists. Everything is in font units.
+pub(crate) fn layout_preview<'py>(
+    py: Python<'py>,
+    glyphs: &[(String, &Outline)],
+    kerns: &[f32],
+    rises: &[f32],
+    cursive: bool,
+    parameters: &Parameters,
+) -> PyResult<Vec<&'py PyDict>> {
+    let mut x = vec![0.0; glyphs.len()];
+    let mut y = vec![0.0; glyphs.len()];
+    for i in 1..glyphs.len() {
+        x[i] = x[i - 1] + (glyphs[i - 1].1.width + kerns[i - 1]) as f64;
+        y[i] = y[i - 1] + rises[i - 1] as f64;
+    }
+    if cursive {
+        // Attachments chain from the right, so work leftwards from the
+        // rightmost glyph.
+        if let Some(last) = y.last_mut() {
+            *last = 0.0;
+        }
+        for i in (0..glyphs.len().saturating_sub(1)).rev() {
+            let exit = glyphs[i].1.anchors.get(&parameters.exit_anchor);
+            let entry = glyphs[i + 1].1.anchors.get(ENTRY_ANCHOR);
+            let join = match (exit, entry) {
+                (Some(exit), Some(entry)) => entry.y - exit.y,
+                _ => 0.0,
+            };
+            y[i] = y[i + 1] - rises[i] as f64 + join;
+        }
+    }
+
+    let tolerance = parameters.tolerance as f64;
+    glyphs
+        .iter()
+        .enumerate()
+        .map(|(i, (name, outline))| {
+            let dict = PyDict::new(py);
+            let transform = Affine::translate(Vec2::new(x[i], y[i]));
+            dict.set_item("glyph", name)?;
+            dict.set_item("x", x[i])?;
+            dict.set_item("y", y[i])?;
+            dict.set_item("kern", if i > 0 { kerns[i - 1] } else { 0.0 })?;
+            dict.set_item(
+                "contours",
+                point_lists(&outline.paths, transform, tolerance),
+            )?;
+            Ok(dict)
+        })
+        .collect()
+}
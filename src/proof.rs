@@ -0,0 +1,140 @@
+use crate::batch::KernResult;
+use crate::cache::OutlineCache;
+use crate::outline::{svg_path, EXIT_ANCHOR};
+use crate::solver::Status;
+use babelfont::Font;
+use core::cmp::Ordering;
+use kurbo::{Affine, BezPath, Rect, Shape, Vec2};
+
+/// Converged pairs kerned by at least this much are grouped as large.
+const LARGE_KERN: f32 = 200.0;
+/// Converged pairs kerned by at least this much are grouped as medium.
+const MEDIUM_KERN: f32 = 50.0;
+/// Padding (in font units) around each rendered pair.
+const MARGIN: f64 = 50.0;
+
+const HEADER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Kerning proof</title>
+<style>
+body { font-family: sans-serif; }
+.pairs { display: flex; flex-wrap: wrap; }
+figure { margin: 0.5em; text-align: center; }
+svg { height: 120px; }
+.left { fill: #222; }
+.right { fill: #225; }
+.tuck_limited figcaption, .not_converged figcaption, .no_distance figcaption,
+.needs_remeasure figcaption { color: #c00; }
+</style>
+</head>
+<body>
+"#;
+
+/// Renders an HTML page with every pair drawn at its computed kern, grouped
+/// so that the pairs most likely to need attention come first.
+pub(crate) fn proof_html(font: &Font, outlines: &OutlineCache, results: &[KernResult]) -> String {
+    let mut groups: Vec<(&str, Vec<&KernResult>)> = vec![
+        ("Needs review", vec![]),
+        ("Large kerns", vec![]),
+        ("Medium kerns", vec![]),
+        ("Small kerns", vec![]),
+    ];
+    for result in results {
+        let group = if !matches!(
+            result.status,
+            Status::Converged | Status::DotLimited | Status::Propagated | Status::Overridden
+        ) {
+            0
+        } else if result.kern.abs() >= LARGE_KERN {
+            1
+        } else if result.kern.abs() >= MEDIUM_KERN {
+            2
+        } else {
+            3
+        };
+        groups[group].1.push(result);
+    }
+
+    let mut html = String::from(HEADER);
+    for (title, mut members) in groups {
+        if members.is_empty() {
+            continue;
+        }
+        members.sort_by(|a, b| a.kern.partial_cmp(&b.kern).unwrap_or(Ordering::Equal));
+        html.push_str(&format!(
+            "<h2>{} ({})</h2>\n<div class=\"pairs\">\n",
+            title,
+            members.len()
+        ));
+        for result in members {
+            html.push_str(&render_pair(font, outlines, result));
+        }
+        html.push_str("</div>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn render_pair(font: &Font, outlines: &OutlineCache, result: &KernResult) -> String {
+    let caption = format!(
+        "<figcaption>{} {}: {:.0} ({})</figcaption>",
+        escape(&result.left),
+        escape(&result.right),
+        result.kern,
+        result.status.as_str()
+    );
+    let outlines = font.master(&result.master).and_then(|master| {
+        Some((
+            outlines.get(font, master, &result.left)?,
+            outlines.get(font, master, &result.right)?,
+        ))
+    });
+    let (left, right) = match outlines {
+        Some(layers) => layers,
+        None => {
+            return format!(
+                "<figure class=\"{}\">{}</figure>\n",
+                result.status.as_str(),
+                caption
+            )
+        }
+    };
+
+    let offset1 = Affine::translate(Vec2::new(
+        0.0,
+        left.vertical_offset(result.height, EXIT_ANCHOR),
+    ));
+    let offset2 = Affine::translate(Vec2::new((left.width + result.kern) as f64, 0.0));
+    let left: Vec<BezPath> = left.paths.iter().map(|p| offset1 * p).collect();
+    let right: Vec<BezPath> = right.paths.iter().map(|p| offset2 * p).collect();
+
+    let bounds = left
+        .iter()
+        .chain(right.iter())
+        .map(|p| p.bounding_box())
+        .reduce(|a, b| a.union(b))
+        .unwrap_or_else(|| Rect::new(0.0, 0.0, 1.0, 1.0))
+        .inflate(MARGIN, MARGIN);
+    // SVG's y axis points down, so the outlines are flipped and the
+    // viewBox is taken from the flipped bounds.
+    format!(
+        "<figure class=\"{}\"><svg viewBox=\"{:.0} {:.0} {:.0} {:.0}\"><g transform=\"scale(1,-1)\"><path class=\"left\" d=\"{}\"/><path class=\"right\" d=\"{}\"/></g></svg>{}</figure>\n",
+        result.status.as_str(),
+        bounds.x0,
+        -bounds.y1,
+        bounds.width(),
+        bounds.height(),
+        svg_path(&left),
+        svg_path(&right),
+        caption
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,109 @@
+use serde_json::Value;
+use std::fs;
+
+/// An exception to the usual parameters for the pairs whose glyph names
+/// match a pair of glob-style patterns.
+#[derive(Debug, Clone)]
+pub(crate) struct Rule {
+    pub left: String,
+    pub right: String,
+    pub target_distance: Option<f32>,
+    pub max_tuck: Option<f32>,
+}
+
+impl Rule {
+    pub fn matches(&self, left_glyph: &str, right_glyph: &str) -> bool {
+        glob_match(&self.left, left_glyph) && glob_match(&self.right, right_glyph)
+    }
+}
+
+/// Reads a rules file: a JSON list of objects with `left` and `right`
+/// patterns (each defaulting to `*`) and a `target_distance` and/or
+/// `max_tuck` to use for the pairs they match.
+pub(crate) fn load_rules(path: &str) -> Result<Vec<Rule>, String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("Couldn't read {:}: {:}", path, e))?;
+    let data: Value =
+        serde_json::from_str(&contents).map_err(|e| format!("Couldn't parse {:}: {:}", path, e))?;
+    let entries = data
+        .as_array()
+        .ok_or_else(|| format!("{:} should contain a list of rules", path))?;
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let pattern = |key: &str| -> Result<String, String> {
+                match &entry[key] {
+                    Value::Null => Ok("*".to_string()),
+                    Value::String(s) => Ok(s.clone()),
+                    _ => Err(format!(
+                        "Rule {:} in {:}: {:} should be a string",
+                        i, path, key
+                    )),
+                }
+            };
+            let number = |key: &str| -> Result<Option<f32>, String> {
+                match &entry[key] {
+                    Value::Null => Ok(None),
+                    Value::Number(n) => Ok(n.as_f64().map(|n| n as f32)),
+                    _ => Err(format!(
+                        "Rule {:} in {:}: {:} should be a number",
+                        i, path, key
+                    )),
+                }
+            };
+            Ok(Rule {
+                left: pattern("left")?,
+                right: pattern("right")?,
+                target_distance: number("target_distance")?,
+                max_tuck: number("max_tuck")?,
+            })
+        })
+        .collect()
+}
+
+/// Matches a glyph name against a pattern in which `*` stands for any run
+/// of characters and `?` for any one character.
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last star was, and how much of the name it has taken.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_matches_whole_names() {
+        assert!(glob_match("A", "A"));
+        assert!(!glob_match("A", "Aacute"));
+        assert!(!glob_match("", "A"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*.sc", "a.sc"));
+        assert!(!glob_match("*.sc", "a.scx"));
+        assert!(glob_match("?.alt", "a.alt"));
+        assert!(!glob_match("?.alt", "ab.alt"));
+        assert!(glob_match("a*b*c", "aXbYbc"));
+        assert!(!glob_match("a*b*c", "aXbY"));
+        assert!(glob_match("**", "beh.init"));
+    }
+}
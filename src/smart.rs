@@ -0,0 +1,414 @@
+use babelfont::{Font, Node, NodeType, Path, PathDirection, Shape};
+use kurbo::{Affine, Point};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// In a smart glyph layer's `partSelection`, the value marking the layer
+/// as the top pole of an axis.
+const TOP_POLE: &str = "2";
+
+/// One of a smart glyph's interpolation axes (its `partsSettings`).
+struct PartAxis {
+    name: String,
+    bottom: f64,
+    top: f64,
+}
+
+/// A contour's nodes as `(x, y, type)`, in Glyphs 3 terms.
+type Contour = (Vec<(f64, f64, String)>, bool);
+
+/// Replaces the smart components in a Glyphs 3 source with the shapes
+/// their property values interpolate to, so they measure as they are
+/// drawn rather than as their master layers. `source` is the `.glyphs`
+/// file's text; babelfont loads smart components as plain components
+/// without their values, so these are read from the source itself.
+///
+/// A smart glyph's layers for a master are its master layer and those
+/// associated with it, each sitting at the top or bottom pole of every
+/// axis. A component's shape is the layer at the bottom of every axis,
+/// moved towards the layer at the top of each axis (and the bottom of
+/// the others) by how far along that axis the component's value is. Axes
+/// whose layers are missing or incompatible are left at the bottom.
+/// Components inside smart glyphs are not interpolated.
+pub(crate) fn decompose_smart_components(font: &mut Font, source: &str) -> Result<(), String> {
+    let source = parse(source)?;
+    let glyphs: HashMap<&str, &Value> = source["glyphs"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|g| Some((g["glyphname"].as_str()?, g)))
+        .collect();
+    let axes: HashMap<&str, Vec<PartAxis>> = glyphs
+        .iter()
+        .filter_map(|(name, glyph)| {
+            let axes: Vec<PartAxis> = glyph["partsSettings"]
+                .as_array()?
+                .iter()
+                .filter_map(|a| {
+                    Some(PartAxis {
+                        name: a["name"].as_str()?.to_string(),
+                        bottom: number(&a["bottomValue"])?,
+                        top: number(&a["topValue"])?,
+                    })
+                })
+                .collect();
+            if axes.is_empty() {
+                None
+            } else {
+                Some((*name, axes))
+            }
+        })
+        .collect();
+    if axes.is_empty() {
+        return Ok(());
+    }
+
+    for glyph in font.glyphs.0.iter_mut() {
+        let source_glyph = match glyphs.get(glyph.name.as_str()) {
+            Some(g) => g,
+            None => continue,
+        };
+        for layer in glyph.layers.iter_mut() {
+            let source_layer = match layer
+                .id
+                .as_deref()
+                .and_then(|id| find_layer(source_glyph, id))
+            {
+                Some(l) => l,
+                None => continue,
+            };
+            let master_id = source_layer["associatedMasterId"]
+                .as_str()
+                .or_else(|| source_layer["layerId"].as_str())
+                .unwrap_or_default();
+            let pieces: Vec<Option<&Map<String, Value>>> = source_layer["shapes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|s| s["ref"].is_string())
+                .map(|s| s["piece"].as_object())
+                .collect();
+
+            // Components are matched to the source's by their order.
+            let mut component_index = 0;
+            let mut shapes = vec![];
+            let mut paths = vec![];
+            for shape in layer.shapes.drain(..) {
+                let component = match &shape {
+                    Shape::ComponentShape(c) => c,
+                    _ => {
+                        shapes.push(shape);
+                        continue;
+                    }
+                };
+                let piece = pieces.get(component_index).copied().flatten();
+                component_index += 1;
+                let interpolated = match (piece, axes.get(component.reference.as_str())) {
+                    (Some(piece), Some(axes)) => glyphs
+                        .get(component.reference.as_str())
+                        .and_then(|part| interpolate(part, axes, piece, master_id)),
+                    _ => None,
+                };
+                match interpolated {
+                    Some(contours) => {
+                        paths.extend(contours.iter().map(|c| to_path(c, component.transform)))
+                    }
+                    None => shapes.push(shape),
+                }
+            }
+            layer.shapes = shapes;
+            for path in paths {
+                layer.push_path(path);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn find_layer<'a>(glyph: &'a Value, id: &str) -> Option<&'a Value> {
+    glyph["layers"]
+        .as_array()?
+        .iter()
+        .find(|l| l["layerId"].as_str() == Some(id))
+}
+
+/// The contours a smart glyph interpolates to in a master for a
+/// component's property values, or `None` if it has no layer at the
+/// bottom of every axis.
+fn interpolate(
+    part: &Value,
+    axes: &[PartAxis],
+    piece: &Map<String, Value>,
+    master_id: &str,
+) -> Option<Vec<Contour>> {
+    let layers: Vec<&Value> = part["layers"]
+        .as_array()?
+        .iter()
+        .filter(|l| {
+            l["layerId"].as_str() == Some(master_id)
+                || l["associatedMasterId"].as_str() == Some(master_id)
+        })
+        .collect();
+    let at_top = |layer: &Value, axis: &PartAxis| {
+        layer["partSelection"][&axis.name].as_str() == Some(TOP_POLE)
+    };
+    let pole = |top: Option<&PartAxis>| {
+        layers.iter().copied().find(|l| {
+            axes.iter()
+                .all(|a| at_top(l, a) == top.map_or(false, |t| t.name == a.name))
+        })
+    };
+    let base = contours(pole(None)?);
+    let mut shape = base.clone();
+    for axis in axes {
+        let value = piece
+            .get(&axis.name)
+            .and_then(number)
+            .unwrap_or(axis.bottom);
+        let span = axis.top - axis.bottom;
+        let t = if span == 0.0 {
+            0.0
+        } else {
+            ((value - axis.bottom) / span).clamp(0.0, 1.0)
+        };
+        let top = match pole(Some(axis)) {
+            Some(layer) if t > 0.0 => contours(layer),
+            _ => continue,
+        };
+        if !compatible(&base, &top) {
+            log::warn!(
+                "Layers for axis {} of smart glyph {} are incompatible",
+                axis.name,
+                part["glyphname"].as_str().unwrap_or_default()
+            );
+            continue;
+        }
+        for ((contour, low), high) in shape.iter_mut().zip(base.iter()).zip(top.iter()) {
+            for ((node, low), high) in contour.0.iter_mut().zip(low.0.iter()).zip(high.0.iter()) {
+                node.0 += (high.0 - low.0) * t;
+                node.1 += (high.1 - low.1) * t;
+            }
+        }
+    }
+    Some(shape)
+}
+
+/// A layer's contours. Glyphs 3 writes nodes as `(x, y, type)` tuples.
+fn contours(layer: &Value) -> Vec<Contour> {
+    layer["shapes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|s| !s["ref"].is_string())
+        .map(|s| {
+            let nodes = s["nodes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|n| {
+                    Some((
+                        number(&n[0])?,
+                        number(&n[1])?,
+                        n[2].as_str().unwrap_or("l").to_string(),
+                    ))
+                })
+                .collect();
+            (nodes, s["closed"].as_str() != Some("0"))
+        })
+        .collect()
+}
+
+fn compatible(a: &[Contour], b: &[Contour]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.0.len() == b.0.len())
+}
+
+fn to_path((nodes, closed): &Contour, transform: Affine) -> Path {
+    Path {
+        nodes: nodes
+            .iter()
+            .map(|(x, y, nodetype)| {
+                let point = transform * Point::new(*x, *y);
+                Node {
+                    x: point.x as f32,
+                    y: point.y as f32,
+                    nodetype: match nodetype.trim_end_matches('s') {
+                        "o" => NodeType::OffCurve,
+                        "c" => NodeType::Curve,
+                        "q" => NodeType::QCurve,
+                        _ => NodeType::Line,
+                    },
+                }
+            })
+            .collect(),
+        closed: *closed,
+        direction: PathDirection::Clockwise,
+    }
+}
+
+/// Numbers are left as strings by the parser, unquoted or not.
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::String(s) => s.parse().ok(),
+        Value::Number(n) => n.as_f64(),
+        _ => None,
+    }
+}
+
+/// Parses an OpenStep property list, as Glyphs writes, into JSON values:
+/// dictionaries become objects, lists arrays, and everything else
+/// strings.
+fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        position: 0,
+    };
+    parser.value()
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<u8> {
+        loop {
+            match self.text.get(self.position..) {
+                Some([c, ..]) if c.is_ascii_whitespace() => self.position += 1,
+                Some([b'/', b'/', ..]) => {
+                    while !matches!(self.text.get(self.position), None | Some(b'\n')) {
+                        self.position += 1;
+                    }
+                }
+                Some([b'/', b'*', ..]) => {
+                    while !matches!(
+                        self.text.get(self.position..),
+                        None | Some([b'*', b'/', ..])
+                    ) {
+                        self.position += 1;
+                    }
+                    self.position += 2;
+                }
+                _ => return self.text.get(self.position).copied(),
+            }
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(format!(
+                "Expected '{}' at byte {} of the source",
+                c as char, self.position
+            ))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(b'{') => {
+                self.position += 1;
+                let mut dict = Map::new();
+                while self.peek() != Some(b'}') {
+                    let key = match self.value()? {
+                        Value::String(s) => s,
+                        _ => return Err(format!("Bad key at byte {}", self.position)),
+                    };
+                    self.expect(b'=')?;
+                    let value = self.value()?;
+                    self.expect(b';')?;
+                    dict.insert(key, value);
+                }
+                self.position += 1;
+                Ok(Value::Object(dict))
+            }
+            Some(b'(') => {
+                self.position += 1;
+                let mut list = vec![];
+                while self.peek() != Some(b')') {
+                    list.push(self.value()?);
+                    if self.peek() == Some(b',') {
+                        self.position += 1;
+                    }
+                }
+                self.position += 1;
+                Ok(Value::Array(list))
+            }
+            Some(b'"') => {
+                self.position += 1;
+                let mut bytes = vec![];
+                loop {
+                    match self.text.get(self.position) {
+                        None => return Err("Unterminated string in the source".to_string()),
+                        Some(b'"') => break,
+                        Some(b'\\') => {
+                            self.position += 1;
+                            bytes.push(match self.text.get(self.position) {
+                                Some(b'n') => b'\n',
+                                Some(b't') => b'\t',
+                                Some(c) => *c,
+                                None => continue,
+                            });
+                        }
+                        Some(c) => bytes.push(*c),
+                    }
+                    self.position += 1;
+                }
+                self.position += 1;
+                Ok(Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+            }
+            Some(_) => {
+                let start = self.position;
+                while self.text.get(self.position).map_or(false, |c| {
+                    !b"{}()=;,\"".contains(c) && !c.is_ascii_whitespace()
+                }) {
+                    self.position += 1;
+                }
+                if self.position == start {
+                    return Err(format!("Unexpected character at byte {}", start));
+                }
+                Ok(Value::String(
+                    String::from_utf8_lossy(&self.text[start..self.position]).into_owned(),
+                ))
+            }
+            None => Err("Unexpected end of the source".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_reads_dictionaries_lists_and_strings() {
+        let text = r#"{
+            name = "A.smart";
+            // Numbers stay strings.
+            width = 600;
+            parts = (left, "two words", { pole = 2; },);
+            /* A comment, with ( and { in it */
+            note = "a \"quoted\" line\n";
+        }"#;
+        assert_eq!(
+            parse(text).unwrap(),
+            json!({
+                "name": "A.smart",
+                "width": "600",
+                "parts": ["left", "two words", {"pole": "2"}],
+                "note": "a \"quoted\" line\n"
+            })
+        );
+    }
+
+    #[test]
+    fn parse_rejects_broken_sources() {
+        assert!(parse("{ a = 1 }").is_err());
+        assert!(parse("{ a = \"unterminated; }").is_err());
+        assert!(parse("{ a = ; }").is_err());
+        assert!(parse("(a, b").is_err());
+    }
+}
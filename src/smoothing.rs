@@ -0,0 +1,170 @@
+use crate::batch::KernResult;
+use crate::solver::Status;
+use core::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Finds kerns which break the pattern of their pair's kerns in the
+/// neighbouring masters. Masters are neighbours along an axis if they sit
+/// at the same place on every other axis; a master between two neighbours
+/// whose kern lies outside the range of theirs by more than `tolerance`
+/// would put a kink in the interpolated kern. Such kerns are brought back
+/// to the edge of the range (and marked as smoothed), or with `flag_only`
+/// left as they are (and marked as kinked). Only kerns which were actually
+/// measured are used or changed. `locations` gives each master's position
+/// on every axis, by master name.
+pub(crate) fn smooth_across_masters(
+    results: &[KernResult],
+    locations: &HashMap<String, Vec<f32>>,
+    tolerance: f32,
+    flag_only: bool,
+) -> Vec<KernResult> {
+    let mut smoothed = results.to_vec();
+    let mut pairs: HashMap<(&str, &str, i32), Vec<usize>> = HashMap::new();
+    for (i, result) in results.iter().enumerate() {
+        if (result.status.converged() || result.status == Status::TuckLimited)
+            && locations.contains_key(&result.master)
+        {
+            pairs
+                .entry((&result.left, &result.right, result.height))
+                .or_default()
+                .push(i);
+        }
+    }
+    let axis_count = locations.values().map(|l| l.len()).max().unwrap_or(0);
+    for members in pairs.values() {
+        for &i in members {
+            let here = &locations[&results[i].master];
+            let range = (0..axis_count).find_map(|axis| {
+                let on_line = |j: &&usize| {
+                    let there = &locations[&results[**j].master];
+                    (0..axis_count).all(|a| a == axis || there.get(a) == here.get(a))
+                };
+                let position = |j: usize| locations[&results[j].master].get(axis).copied();
+                let below = members
+                    .iter()
+                    .filter(on_line)
+                    .filter(|j| position(**j) < position(i))
+                    .max_by(|a, b| {
+                        position(**a)
+                            .partial_cmp(&position(**b))
+                            .unwrap_or(Ordering::Equal)
+                    })?;
+                let above = members
+                    .iter()
+                    .filter(on_line)
+                    .filter(|j| position(**j) > position(i))
+                    .min_by(|a, b| {
+                        position(**a)
+                            .partial_cmp(&position(**b))
+                            .unwrap_or(Ordering::Equal)
+                    })?;
+                let (a, b) = (results[*below].kern, results[*above].kern);
+                let (low, high) = (a.min(b), a.max(b));
+                let kern = results[i].kern;
+                if kern < low - tolerance || kern > high + tolerance {
+                    Some((low, high))
+                } else {
+                    None
+                }
+            });
+            if let Some((low, high)) = range {
+                if flag_only {
+                    smoothed[i].status = Status::Kinked;
+                } else {
+                    smoothed[i].kern = results[i].kern.clamp(low, high);
+                    smoothed[i].status = Status::Smoothed;
+                }
+            }
+        }
+    }
+    smoothed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::Solution;
+
+    fn result(master: &str, kern: f32, status: Status) -> KernResult {
+        KernResult::new(
+            "A".to_string(),
+            "V".to_string(),
+            master.to_string(),
+            0,
+            Solution {
+                kern,
+                status,
+                iterations: 1,
+            },
+        )
+    }
+
+    fn locations(masters: &[(&str, Vec<f32>)]) -> HashMap<String, Vec<f32>> {
+        masters
+            .iter()
+            .map(|(name, location)| (name.to_string(), location.clone()))
+            .collect()
+    }
+
+    fn weights() -> HashMap<String, Vec<f32>> {
+        locations(&[
+            ("Light", vec![100.0]),
+            ("Regular", vec![400.0]),
+            ("Bold", vec![700.0]),
+        ])
+    }
+
+    fn kinked() -> Vec<KernResult> {
+        vec![
+            result("Light", -10.0, Status::Converged),
+            result("Regular", -80.0, Status::Converged),
+            result("Bold", -30.0, Status::Converged),
+        ]
+    }
+
+    #[test]
+    fn kinks_are_brought_back_into_range() {
+        let smoothed = smooth_across_masters(&kinked(), &weights(), 5.0, false);
+        assert_eq!(smoothed[1].kern, -30.0);
+        assert_eq!(smoothed[1].status, Status::Smoothed);
+        for i in [0, 2].iter() {
+            assert_eq!(smoothed[*i].kern, kinked()[*i].kern);
+            assert_eq!(smoothed[*i].status, Status::Converged);
+        }
+    }
+
+    #[test]
+    fn kinks_are_only_flagged_if_asked() {
+        let smoothed = smooth_across_masters(&kinked(), &weights(), 5.0, true);
+        assert_eq!(smoothed[1].kern, -80.0);
+        assert_eq!(smoothed[1].status, Status::Kinked);
+    }
+
+    #[test]
+    fn kerns_within_tolerance_are_left_alone() {
+        let smoothed = smooth_across_masters(&kinked(), &weights(), 50.0, false);
+        assert_eq!(smoothed[1].kern, -80.0);
+        assert_eq!(smoothed[1].status, Status::Converged);
+    }
+
+    #[test]
+    fn only_measured_kerns_count() {
+        let mut results = kinked();
+        results[2].status = Status::NotConverged;
+        let smoothed = smooth_across_masters(&results, &weights(), 5.0, false);
+        assert_eq!(smoothed[1].kern, -80.0);
+        assert_eq!(smoothed[1].status, Status::Converged);
+    }
+
+    #[test]
+    fn masters_off_the_line_are_not_neighbours() {
+        let locations = locations(&[
+            ("Light", vec![100.0, 0.0]),
+            ("Regular", vec![400.0, 100.0]),
+            ("Bold", vec![700.0, 0.0]),
+        ]);
+        let smoothed = smooth_across_masters(&kinked(), &locations, 5.0, false);
+        assert_eq!(smoothed[1].kern, -80.0);
+        assert_eq!(smoothed[1].status, Status::Converged);
+    }
+}
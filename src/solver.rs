@@ -0,0 +1,595 @@
+use crate::backend::Backend;
+use crate::cache::OutlineCache;
+use crate::distance::{path_distance, Measurement};
+use crate::flat::flat_distance;
+use crate::gap::horizontal_gap;
+#[cfg(feature = "gpu")]
+use crate::gpu::gpu_distances;
+use crate::outline::Outline;
+use crate::overlap::overlaps;
+use crate::parameters::{Engine, Metric, Mode, Parameters};
+use crate::raster::raster_distance;
+use crate::suggest::missing_glyph;
+use crate::timing::measuring;
+use babelfont::Master;
+use core::cmp::Ordering;
+use pyo3::exceptions::{PyKeyError, PyValueError};
+use pyo3::PyResult;
+
+/// How far the tuck mode slides the right glyph between measurements.
+const TUCK_STEP: f32 = 10.0;
+/// The most negative kern (in font units) when the units per em, and so
+/// the default floor, aren't known.
+const DEFAULT_FLOOR: f32 = -1000.0;
+/// How many measurements the target mode makes before giving up on a pair.
+const MAX_ITERATIONS: usize = 10;
+/// How close (in font units) a suggested rise comes to the smallest which
+/// would clear a pair.
+const RISE_PRECISION: f64 = 1.0;
+
+/// How the solver arrived at its kern value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Status {
+    /// The measured distance came within tolerance of the target.
+    Converged,
+    /// The kern was clamped to the maximum tuck.
+    TuckLimited,
+    /// The kern was clamped to the largest kern allowed.
+    KernLimited,
+    /// The kern was clamped to the floor.
+    FloorLimited,
+    /// The outlines came to the target distance, but the left glyph's dots
+    /// rather than its body decided the kern.
+    DotLimited,
+    /// The iteration limit was reached before the distance converged.
+    NotConverged,
+    /// There were no outlines to measure between.
+    NoDistance,
+    /// The kern was carried over from the pair of base glyphs.
+    Propagated,
+    /// The kern was carried over from the base glyphs, but the composite's
+    /// marks come too close for it to be trusted.
+    NeedsRemeasure,
+    /// The kern broke the pattern of the pair's kerns in neighbouring
+    /// masters, and was brought back into line with them.
+    Smoothed,
+    /// The kern breaks the pattern of the pair's kerns in neighbouring
+    /// masters.
+    Kinked,
+    /// The kern was given by the designer rather than computed.
+    Overridden,
+}
+
+impl Status {
+    /// Whether the solver reached the target distance.
+    pub fn converged(&self) -> bool {
+        matches!(self, Status::Converged | Status::DotLimited)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Converged => "converged",
+            Status::TuckLimited => "tuck_limited",
+            Status::KernLimited => "kern_limited",
+            Status::FloorLimited => "floor_limited",
+            Status::DotLimited => "dot_limited",
+            Status::NotConverged => "not_converged",
+            Status::NoDistance => "no_distance",
+            Status::Propagated => "propagated",
+            Status::NeedsRemeasure => "needs_remeasure",
+            Status::Smoothed => "smoothed",
+            Status::Kinked => "kinked",
+            Status::Overridden => "overridden",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Solution {
+    pub kern: f32,
+    pub status: Status,
+    pub iterations: usize,
+}
+
+/// Solves a pair of glyphs in a master of any `Backend`, measuring them
+/// as the parameters say. Glyphs the font doesn't have in the master are a
+/// `KeyError`, and outlines which can't be measured a `ValueError`.
+pub(crate) fn determine_kern<B: Backend + ?Sized>(
+    font: &B,
+    outlines: &OutlineCache,
+    master: &Master,
+    left_glyph: &str,
+    right_glyph: &str,
+    parameters: &Parameters,
+    start: f32,
+) -> PyResult<Solution> {
+    let measured =
+        |glyph_name: &str| match outlines.try_get_measured(font, master, glyph_name, parameters) {
+            Some(outline) => outline.map_err(PyValueError::new_err),
+            None => Err(PyKeyError::new_err(missing_glyph(font, master, glyph_name))),
+        };
+    let (left, right) = (measured(left_glyph)?, measured(right_glyph)?);
+    Ok(solve(&left, &right, parameters, start))
+}
+
+/// The most negative kern the solver may return for a pair.
+pub(crate) fn minimum_kern(left: &Outline, right: &Outline, parameters: &Parameters) -> f32 {
+    kern_limit(left, right, parameters).0
+}
+
+/// The most negative kern the solver may return for a pair, and the
+/// status of a kern clamped to it: the tuck limit, if there is one above
+/// the floor, or else the floor.
+pub(crate) fn kern_limit(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+) -> (f32, Status) {
+    let floor = parameters.kern_floor.unwrap_or(DEFAULT_FLOOR);
+    if parameters.max_tuck != 0.0 {
+        let maximum_width = left.width * parameters.max_tuck;
+        let left_edge = (-right.lsb().unwrap_or(0.0) as f32).min(0.0);
+        let tuck = left_edge - maximum_width;
+        if tuck >= floor {
+            return (tuck, Status::TuckLimited);
+        }
+    }
+    (floor, Status::FloorLimited)
+}
+
+/// What the solver made of a measurement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Decision {
+    /// The distance was off target, so the kern moved by the shortfall.
+    Adjust,
+    /// The distance was within tolerance of the target.
+    Accept,
+    /// Moving by the shortfall would have passed the kern limit, so the
+    /// kern was clamped to it.
+    Clamp,
+    /// The outlines were clear of each other at this kern.
+    Clear,
+    /// The outlines were too close, or overlapped, at this kern.
+    TooClose,
+}
+
+impl Decision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Decision::Adjust => "adjust",
+            Decision::Accept => "accept",
+            Decision::Clamp => "clamp",
+            Decision::Clear => "clear",
+            Decision::TooClose => "too_close",
+        }
+    }
+}
+
+/// A kern the solver tried, what it measured there and what it did next.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Step {
+    pub kern: f32,
+    pub measurement: Measurement,
+    pub decision: Decision,
+}
+
+/// Iterates towards the kern which places the outlines at their target
+/// distance, beginning from the kern given as `start`.
+pub(crate) fn solve(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    start: f32,
+) -> Solution {
+    solve_traced(left, right, parameters, start).0
+}
+
+/// As `solve`, but also returns each step the solver took.
+pub(crate) fn solve_traced(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    start: f32,
+) -> (Solution, Vec<Step>) {
+    let (mut solution, trace) = match parameters.mode {
+        Mode::Target => solve_target(left, right, parameters, start),
+        Mode::Clearance => solve_clearance(left, right, parameters),
+        Mode::Tuck => solve_tuck(left, right, parameters),
+    };
+    if solution.status == Status::Converged && dot_limited(left, right, parameters, solution.kern) {
+        solution.status = Status::DotLimited;
+    }
+    (solution, trace)
+}
+
+/// Iterates towards the kern which places the outlines at their target
+/// distance.
+fn solve_target(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    start: f32,
+) -> (Solution, Vec<Step>) {
+    let mut trace = vec![];
+    let height = parameters.left_offset(left);
+    let (minimum_possible, limited) = kern_limit(left, right, parameters);
+    let tolerance = parameters.kern_tolerance as f64;
+    let mut iterations = 0;
+    let mut kern = start;
+    let mut last: Option<Measurement> = None;
+
+    while iterations < MAX_ITERATIONS && last.map_or(true, |m| m.shortfall().abs() > tolerance) {
+        if let Some(m) = measure(left, right, kern + left.width, height, parameters) {
+            log::debug!("With kern of {:?}, distance was {:?}", kern, m.distance);
+            last = Some(m);
+            let next = kern + m.shortfall() as f32;
+            let decision = if next < minimum_possible {
+                Decision::Clamp
+            } else if m.shortfall().abs() <= tolerance {
+                Decision::Accept
+            } else {
+                Decision::Adjust
+            };
+            trace.push(Step {
+                kern,
+                measurement: m,
+                decision,
+            });
+            kern = next;
+            iterations += 1;
+            if kern < minimum_possible {
+                return (
+                    Solution {
+                        kern: minimum_possible,
+                        status: limited,
+                        iterations,
+                    },
+                    trace,
+                );
+            }
+        } else {
+            return (
+                Solution {
+                    kern: 0.0,
+                    status: Status::NoDistance,
+                    iterations,
+                },
+                trace,
+            );
+        }
+    }
+    let status = match last {
+        Some(m) if m.shortfall().abs() <= tolerance => Status::Converged,
+        _ => Status::NotConverged,
+    };
+    (
+        Solution {
+            kern,
+            status,
+            iterations,
+        },
+        trace,
+    )
+}
+
+/// Whether the outlines are clear of each other by at least the target
+/// distance at the given kern, or `None` if there is nothing to measure.
+fn is_clear(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    kern: f32,
+    trace: &mut Vec<Step>,
+) -> Option<bool> {
+    let height = parameters.left_offset(left);
+    let m = measure(left, right, kern + left.width, height, parameters)?;
+    let overlapping = parameters
+        .heights(height)
+        .into_iter()
+        .any(|y| overlaps(&left.paths, &right.paths, kern + left.width, y as f32));
+    let clear = !overlapping && m.shortfall() <= 0.0;
+    trace.push(Step {
+        kern,
+        measurement: m,
+        decision: if clear {
+            Decision::Clear
+        } else {
+            Decision::TooClose
+        },
+    });
+    Some(clear)
+}
+
+/// Narrows down the boundary between a kern at which the outlines are too
+/// close (`low`) and one at which they are clear (`high`) to within a unit,
+/// returning the clear side.
+fn bisect(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    mut low: f32,
+    mut high: f32,
+    trace: &mut Vec<Step>,
+) -> f32 {
+    while high - low > 1.0 {
+        let middle = (low + high) / 2.0;
+        if is_clear(left, right, parameters, middle, trace).unwrap_or(true) {
+            high = middle;
+        } else {
+            low = middle;
+        }
+    }
+    high
+}
+
+fn no_distance() -> (Solution, Vec<Step>) {
+    (
+        Solution {
+            kern: 0.0,
+            status: Status::NoDistance,
+            iterations: 0,
+        },
+        vec![],
+    )
+}
+
+/// Finds the smallest kern which leaves the outlines clear of each other
+/// by at least the target distance: none if they already are, otherwise
+/// the point found by bisecting between no kern and a kern which
+/// separates their bounding boxes by the target distance.
+fn solve_clearance(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+) -> (Solution, Vec<Step>) {
+    let mut trace = vec![];
+    let (left_bounds, right_bounds) = match (left.bounds(), right.bounds()) {
+        (Some(l), Some(r)) => (l, r),
+        _ => return no_distance(),
+    };
+    let kern = match is_clear(left, right, parameters, 0.0, &mut trace) {
+        None => return no_distance(),
+        Some(true) => 0.0,
+        Some(false) => {
+            let margin = parameters.max_target();
+            let high = (left_bounds.x1 - right_bounds.x0) as f32 - left.width + margin;
+            bisect(left, right, parameters, 0.0, high, &mut trace)
+        }
+    };
+    let iterations = trace.len();
+    (
+        Solution {
+            kern,
+            status: Status::Converged,
+            iterations,
+        },
+        trace,
+    )
+}
+
+/// Finds the most negative kern the right glyph can slide to, starting
+/// from where it is clear of the left glyph, without coming closer than
+/// the target distance or passing the tuck limit.
+fn solve_tuck(left: &Outline, right: &Outline, parameters: &Parameters) -> (Solution, Vec<Step>) {
+    let (clearance, mut trace) = solve_clearance(left, right, parameters);
+    if clearance.status == Status::NoDistance {
+        return (clearance, trace);
+    }
+    let (minimum, limited) = kern_limit(left, right, parameters);
+    let mut kern = clearance.kern;
+    let mut status = Status::Converged;
+    loop {
+        let next = (kern - TUCK_STEP).max(minimum);
+        if next >= kern {
+            status = limited;
+            break;
+        }
+        if is_clear(left, right, parameters, next, &mut trace).unwrap_or(false) {
+            kern = next;
+        } else {
+            kern = bisect(left, right, parameters, next, kern, &mut trace);
+            break;
+        }
+    }
+    let iterations = trace.len();
+    (
+        Solution {
+            kern,
+            status,
+            iterations,
+        },
+        trace,
+    )
+}
+
+/// The smallest rise of the right glyph (in font units, downwards if
+/// negative) which brings a pair to its target distance at `kern`, for
+/// pairs which kerning alone can't separate. Returns `None` if the pair is
+/// clear already, or no rise within the height of the two glyphs would
+/// clear it.
+pub(crate) fn rise_needed(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    kern: f32,
+) -> Option<f32> {
+    let height = parameters.left_offset(left);
+    let x_offset = kern + left.width;
+    // Raising the right glyph is lowering the left one against it.
+    let clear = |rise: f64| {
+        measure(left, right, x_offset, height - rise, parameters)
+            .map_or(true, |m| m.shortfall() <= 0.0)
+    };
+    if clear(0.0) {
+        return None;
+    }
+    let (left_bounds, right_bounds) = (left.bounds()?, right.bounds()?);
+    let reach = (left_bounds.height() + right_bounds.height()) * parameters.vertical_scale();
+    let first_step = (parameters.max_target() as f64).max(RISE_PRECISION);
+    [1.0, -1.0]
+        .iter()
+        .filter_map(|&direction: &f64| {
+            let (mut low, mut high) = (0.0, first_step);
+            while !clear(direction * high) {
+                if high > reach {
+                    return None;
+                }
+                low = high;
+                high *= 2.0;
+            }
+            while high - low > RISE_PRECISION {
+                let middle = (low + high) / 2.0;
+                if clear(direction * middle) {
+                    high = middle;
+                } else {
+                    low = middle;
+                }
+            }
+            Some(direction * high)
+        })
+        .min_by(|a, b| a.abs().partial_cmp(&b.abs()).unwrap_or(Ordering::Equal))
+        .map(|rise| (rise / parameters.vertical_scale()) as f32)
+}
+
+/// The distance between the outlines at each of the given kerns.
+pub(crate) fn profile(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    kerns: &[f32],
+) -> Vec<(f32, Option<f32>)> {
+    kerns
+        .iter()
+        .map(|&kern| {
+            let m = measure_at(left, right, parameters, kern);
+            (kern, m.map(|m| m.distance as f32))
+        })
+        .collect()
+}
+
+/// Measures the pair once, at the given kern.
+pub(crate) fn measure_at(
+    left: &Outline,
+    right: &Outline,
+    parameters: &Parameters,
+    kern: f32,
+) -> Option<Measurement> {
+    let height = parameters.left_offset(left);
+    measure(left, right, kern + left.width, height, parameters)
+}
+
+/// Measures the pair at each of the heights the parameters ask for, and
+/// returns the measurement which falls furthest short of its target. With
+/// a dot target, the left glyph's dots are held to it separately.
+fn measure(
+    left: &Outline,
+    right: &Outline,
+    x_offset: f32,
+    height: f64,
+    parameters: &Parameters,
+) -> Option<Measurement> {
+    measuring(parameters.timings, || {
+        if parameters.dot_target_distance.is_none() {
+            return measure_heights(left, right, x_offset, height, parameters);
+        }
+        let (body, dots) = measure_dots(left, right, x_offset, height, parameters);
+        match (body, dots) {
+            (Some(b), Some(d)) if d.shortfall() > b.shortfall() => Some(d),
+            (None, d) => d,
+            (b, _) => b,
+        }
+    })
+}
+
+/// Measures the body and the dots of the left glyph against the right
+/// glyph separately, the dots against the dot target.
+fn measure_dots(
+    left: &Outline,
+    right: &Outline,
+    x_offset: f32,
+    height: f64,
+    parameters: &Parameters,
+) -> (Option<Measurement>, Option<Measurement>) {
+    let dot_target = parameters.dot_target_distance.unwrap_or(0.0) as f64;
+    let (dots, body) = left.split_dots(parameters.dot_size as f64);
+    let dots = if dots.paths.is_empty() {
+        None
+    } else {
+        measure_heights(&dots, right, x_offset, height, parameters).map(|m| Measurement {
+            distance: m.distance,
+            target: dot_target,
+            ..m
+        })
+    };
+    (
+        measure_heights(&body, right, x_offset, height, parameters),
+        dots,
+    )
+}
+
+/// Whether the left glyph's dots, rather than its body, come closest to
+/// their target at a kern.
+fn dot_limited(left: &Outline, right: &Outline, parameters: &Parameters, kern: f32) -> bool {
+    if parameters.dot_target_distance.is_none() {
+        return false;
+    }
+    let height = parameters.left_offset(left);
+    match measure_dots(left, right, kern + left.width, height, parameters) {
+        (Some(body), Some(dots)) => dots.shortfall() > body.shortfall(),
+        (None, Some(_)) => true,
+        _ => false,
+    }
+}
+
+fn measure_heights(
+    left: &Outline,
+    right: &Outline,
+    x_offset: f32,
+    height: f64,
+    parameters: &Parameters,
+) -> Option<Measurement> {
+    let heights = parameters.heights(height);
+    let worst = |a: &Measurement, b: &Measurement| {
+        a.shortfall()
+            .partial_cmp(&b.shortfall())
+            .unwrap_or(Ordering::Equal)
+    };
+    // The GPU measures every height in one go.
+    #[cfg(feature = "gpu")]
+    {
+        if parameters.engine == Engine::Gpu && parameters.metric != Metric::HorizontalGap {
+            if let Some(measurements) =
+                gpu_distances(&left.paths, &right.paths, x_offset, &heights, parameters)
+            {
+                return measurements.into_iter().flatten().max_by(worst);
+            }
+        }
+    }
+    heights
+        .into_iter()
+        .filter_map(|y| match (parameters.metric, parameters.engine) {
+            (Metric::HorizontalGap, _) => {
+                horizontal_gap(&left.paths, &right.paths, x_offset, y as f32, parameters)
+            }
+            (_, Engine::Analytic) => path_distance(
+                &left.right_side,
+                &right.left_side,
+                Some((&left.right_bands, &right.left_bands)),
+                x_offset,
+                y as f32,
+                parameters,
+            ),
+            (_, Engine::Raster) => {
+                raster_distance(&left.paths, &right.paths, x_offset, y as f32, parameters)
+            }
+            (_, Engine::Flattened) => {
+                flat_distance(&left.paths, &right.paths, x_offset, y as f32, parameters)
+            }
+            // Without a GPU adapter, the flattened engine runs on the CPU.
+            #[cfg(feature = "gpu")]
+            (_, Engine::Gpu) => {
+                flat_distance(&left.paths, &right.paths, x_offset, y as f32, parameters)
+            }
+        })
+        .max_by(worst)
+}
@@ -0,0 +1,106 @@
+use crate::batch::KernResult;
+use core::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+/// How many rounds of alternately taking out the left and right glyphs'
+/// shares of the kerns to make.
+const ROUNDS: usize = 10;
+
+/// The part of a glyph's kerns which could instead be a change to its
+/// sidebearings.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SidebearingChange {
+    /// Added to the right sidebearing, for the pairs where it is on the left
+    pub rsb: f32,
+    /// Added to the left sidebearing, for the pairs where it is on the right
+    pub lsb: f32,
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let middle = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+/// Splits the converged kerns in each master into a share for each left
+/// glyph, which could move into its right sidebearing, and a share for
+/// each right glyph, which could move into its left sidebearing, by median
+/// polish: repeatedly taking out the median of what is left of each
+/// glyph's kerns. Medians make the split robust to the handful of pairs
+/// (the `T` and `o`s of the world) which need real kerns. Only glyphs with
+/// at least `min_partners` partners on a side are given a share on that
+/// side. The results are keyed by glyph and master name, and only include
+/// glyphs which would change.
+pub(crate) fn sidebearing_changes(
+    results: &[KernResult],
+    min_partners: usize,
+) -> BTreeMap<(String, String), SidebearingChange> {
+    let mut by_master: HashMap<&str, Vec<&KernResult>> = HashMap::new();
+    for result in results.iter().filter(|r| r.status.converged()) {
+        by_master.entry(&result.master).or_default().push(result);
+    }
+    let mut changes = BTreeMap::new();
+    for (master, results) in by_master {
+        let mut partners: [HashMap<&str, usize>; 2] = [HashMap::new(), HashMap::new()];
+        for result in results.iter() {
+            *partners[0].entry(&result.left).or_default() += 1;
+            *partners[1].entry(&result.right).or_default() += 1;
+        }
+        let mut rsb: HashMap<&str, f32> = HashMap::new();
+        let mut lsb: HashMap<&str, f32> = HashMap::new();
+        let residual = |r: &KernResult, rsb: &HashMap<&str, f32>, lsb: &HashMap<&str, f32>| {
+            r.kern
+                - rsb.get(r.left.as_str()).unwrap_or(&0.0)
+                - lsb.get(r.right.as_str()).unwrap_or(&0.0)
+        };
+        for _ in 0..ROUNDS {
+            let mut remaining: HashMap<&str, Vec<f32>> = HashMap::new();
+            for r in results
+                .iter()
+                .filter(|r| partners[0][r.left.as_str()] >= min_partners)
+            {
+                remaining
+                    .entry(&r.left)
+                    .or_default()
+                    .push(residual(r, &rsb, &lsb));
+            }
+            for (glyph, mut values) in remaining {
+                *rsb.entry(glyph).or_default() += median(&mut values);
+            }
+            let mut remaining: HashMap<&str, Vec<f32>> = HashMap::new();
+            for r in results
+                .iter()
+                .filter(|r| partners[1][r.right.as_str()] >= min_partners)
+            {
+                remaining
+                    .entry(&r.right)
+                    .or_default()
+                    .push(residual(r, &rsb, &lsb));
+            }
+            for (glyph, mut values) in remaining {
+                *lsb.entry(glyph).or_default() += median(&mut values);
+            }
+        }
+        for (glyph, change) in rsb {
+            changes
+                .entry((glyph.to_string(), master.to_string()))
+                .or_insert_with(SidebearingChange::default)
+                .rsb = change.round();
+        }
+        for (glyph, change) in lsb {
+            changes
+                .entry((glyph.to_string(), master.to_string()))
+                .or_insert_with(SidebearingChange::default)
+                .lsb = change.round();
+        }
+    }
+    changes.retain(|_, c| c.rsb != 0.0 || c.lsb != 0.0);
+    changes
+}
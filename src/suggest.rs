@@ -0,0 +1,82 @@
+use crate::backend::Backend;
+use crate::master_name;
+use babelfont::Master;
+
+/// How many names an error suggests at most.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// A note for an error about a name which wasn't found, suggesting the
+/// closest names which do exist: ` (did you mean 'BEi9.init'?)`, or
+/// nothing if none is close. A name is close if it differs only in case,
+/// or if about one edit for every three characters would make it match.
+pub(crate) fn did_you_mean<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> String {
+    let lower = name.to_lowercase();
+    let limit = (name.chars().count() / 3).max(1);
+    let mut close: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter(|c| *c != name)
+        .filter_map(|c| {
+            let distance = edit_distance(&lower, &c.to_lowercase());
+            if distance <= limit {
+                Some((distance, c))
+            } else {
+                None
+            }
+        })
+        .collect();
+    if close.is_empty() {
+        return String::new();
+    }
+    close.sort_unstable();
+    close.dedup_by(|a, b| a.1 == b.1);
+    let names: Vec<String> = close
+        .iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, c)| format!("'{}'", c))
+        .collect();
+    let list = match names.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+        _ => names.join(""),
+    };
+    format!(" (did you mean {}?)", list)
+}
+
+/// The message for a glyph whose outline couldn't be found in a master:
+/// either the font has no glyph by that name, in which case any close
+/// names are suggested, or the glyph has no layer for the master.
+pub(crate) fn missing_glyph<B: Backend + ?Sized>(
+    font: &B,
+    master: &Master,
+    glyph_name: &str,
+) -> String {
+    if font.has_glyph(glyph_name) {
+        return format!(
+            "Glyph {:} has no layer for master {:}",
+            glyph_name,
+            master_name(master)
+        );
+    }
+    format!(
+        "Couldn't find glyph {:}{}",
+        glyph_name,
+        did_you_mean(glyph_name, font.glyph_names().iter().map(|g| g.as_str()))
+    )
+}
+
+/// The Levenshtein distance between two strings, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
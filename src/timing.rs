@@ -0,0 +1,78 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// Time spent measuring distances on this thread since the current
+    /// pair's timer started.
+    static MEASURING: Cell<Duration> = Cell::new(Duration::default());
+}
+
+/// Runs a distance measurement, adding the time it takes to the current
+/// pair's if timings were asked for.
+pub(crate) fn measuring<T>(enabled: bool, measure: impl FnOnce() -> T) -> T {
+    if !enabled {
+        return measure();
+    }
+    let started = Instant::now();
+    let result = measure();
+    MEASURING.with(|m| m.set(m.get() + started.elapsed()));
+    result
+}
+
+/// Where the time went for one pair of a batch run, in seconds.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct PairTimings {
+    /// Converting the glyphs' layers to outlines (decomposing components
+    /// along the way), which is close to nothing once they are cached
+    pub conversion: f64,
+    /// Measuring the distances between the outlines
+    pub measurement: f64,
+    /// Everything else the solver did
+    pub solving: f64,
+}
+
+impl PairTimings {
+    pub fn add(&mut self, other: &PairTimings) {
+        self.conversion += other.conversion;
+        self.measurement += other.measurement;
+        self.solving += other.solving;
+    }
+}
+
+/// Times the stages of kerning a pair. A timer which was not enabled
+/// costs nothing and reports nothing.
+pub(crate) struct PairTimer {
+    started: Option<Instant>,
+    converted: Option<Instant>,
+}
+
+impl PairTimer {
+    pub fn start(enabled: bool) -> Self {
+        if enabled {
+            MEASURING.with(|m| m.set(Duration::default()));
+        }
+        PairTimer {
+            started: if enabled { Some(Instant::now()) } else { None },
+            converted: None,
+        }
+    }
+
+    /// Marks the outlines as converted, and the solver as starting.
+    pub fn converted(&mut self) {
+        if self.started.is_some() {
+            self.converted = Some(Instant::now());
+        }
+    }
+
+    /// Marks the solver as finished.
+    pub fn finish(self) -> Option<PairTimings> {
+        let started = self.started?;
+        let converted = self.converted.unwrap_or(started);
+        let measurement = MEASURING.with(|m| m.get()).as_secs_f64();
+        Some(PairTimings {
+            conversion: (converted - started).as_secs_f64(),
+            measurement,
+            solving: (converted.elapsed().as_secs_f64() - measurement).max(0.0),
+        })
+    }
+}
@@ -0,0 +1,188 @@
+use crate::batch::KernResult;
+use std::collections::{BTreeMap, HashSet};
+
+/// The most pairs a format 0 subtable can hold: its length field is 16
+/// bits, and each pair takes six bytes after a fourteen-byte header.
+const PAIRS_PER_SUBTABLE: usize = 10920;
+
+/// Writes an old-style `kern` table as a TTX document, which `ttx -m` can
+/// merge into a binary font, from flat pair results for one master (or
+/// all the results, if no master is given). Pairs with no kern are left
+/// out, as are repeats of a pair (from runs at other heights, say): the
+/// first result for a pair wins. Pairs which would overflow a subtable go
+/// into further subtables.
+pub(crate) fn kern_table_ttx(results: &[KernResult], master: Option<&str>) -> String {
+    let pairs = kerned_pairs(results, master);
+    let mut ttx = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ttFont>\n<kern>\n  <version value=\"0\"/>\n",
+    );
+    for chunk in pairs.chunks(PAIRS_PER_SUBTABLE) {
+        ttx.push_str("  <kernsubtable coverage=\"1\" format=\"0\">\n");
+        for (left, right, kern) in chunk {
+            ttx.push_str(&format!(
+                "    <pair l=\"{}\" r=\"{}\" v=\"{}\"/>\n",
+                escape(left),
+                escape(right),
+                kern
+            ));
+        }
+        ttx.push_str("  </kernsubtable>\n");
+    }
+    ttx.push_str("</kern>\n</ttFont>\n");
+    ttx
+}
+
+/// Writes a GPOS pair positioning lookup (format 1: a kern for each glyph
+/// pair) as a TTX fragment, to be pasted into the `LookupList` of a font
+/// dumped with `ttx` and compiled back in, from the same pairs as
+/// `kern_table_ttx`. Kerns go on the left glyph's advance. The lookup is
+/// numbered `index`, which should be renumbered to fit the list it goes
+/// into, and wants a feature record pointing at it.
+///
+/// Pair sets follow the order of their first glyphs in the coverage table,
+/// which must be in glyph ID order; pass the font's `glyph_order` to get
+/// it right, or first glyphs are sorted by name. Glyphs not in the order
+/// come last.
+pub(crate) fn pair_pos_ttx(
+    results: &[KernResult],
+    master: Option<&str>,
+    glyph_order: Option<&[String]>,
+    index: usize,
+) -> String {
+    let mut pair_sets: BTreeMap<(usize, &str), Vec<(&str, i32)>> = BTreeMap::new();
+    let position = |name: &str| {
+        glyph_order.map_or(0, |order| {
+            order.iter().position(|g| g == name).unwrap_or(order.len())
+        })
+    };
+    for (left, right, kern) in kerned_pairs(results, master) {
+        pair_sets
+            .entry((position(left), left))
+            .or_default()
+            .push((right, kern));
+    }
+    for pairs in pair_sets.values_mut() {
+        pairs.sort_by_key(|(right, _)| (position(right), *right));
+    }
+
+    let mut ttx = format!(
+        "<Lookup index=\"{}\">\n  <LookupType value=\"2\"/>\n  <LookupFlag value=\"0\"/>\n  <PairPos index=\"0\" Format=\"1\">\n    <Coverage>\n",
+        index
+    );
+    for (_, left) in pair_sets.keys() {
+        ttx.push_str(&format!("      <Glyph value=\"{}\"/>\n", escape(left)));
+    }
+    ttx.push_str(
+        "    </Coverage>\n    <ValueFormat1 value=\"4\"/>\n    <ValueFormat2 value=\"0\"/>\n",
+    );
+    for (i, pairs) in pair_sets.values().enumerate() {
+        ttx.push_str(&format!("    <PairSet index=\"{}\">\n", i));
+        for (j, (right, kern)) in pairs.iter().enumerate() {
+            ttx.push_str(&format!(
+                "      <PairValueRecord index=\"{}\">\n        <SecondGlyph value=\"{}\"/>\n        <Value1 XAdvance=\"{}\"/>\n      </PairValueRecord>\n",
+                j,
+                escape(right),
+                kern
+            ));
+        }
+        ttx.push_str("    </PairSet>\n");
+    }
+    ttx.push_str("  </PairPos>\n</Lookup>\n");
+    ttx
+}
+
+/// The rounded, non-zero kerns for a master (or all masters), each pair
+/// once: the first result for a pair wins.
+fn kerned_pairs<'a>(
+    results: &'a [KernResult],
+    master: Option<&str>,
+) -> Vec<(&'a str, &'a str, i32)> {
+    let mut seen: HashSet<(&str, &str)> = HashSet::new();
+    results
+        .iter()
+        .filter(|r| master.map_or(true, |m| r.master == m))
+        .map(|r| (r.left.as_str(), r.right.as_str(), r.kern.round() as i32))
+        .filter(|(left, right, kern)| *kern != 0 && seen.insert((*left, *right)))
+        .collect()
+}
+
+fn escape(name: &str) -> String {
+    name.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::{Solution, Status};
+
+    fn result(left: &str, right: &str, master: &str, kern: f32) -> KernResult {
+        KernResult::new(
+            left.to_string(),
+            right.to_string(),
+            master.to_string(),
+            0,
+            Solution {
+                kern,
+                status: Status::Converged,
+                iterations: 1,
+            },
+        )
+    }
+
+    #[test]
+    fn kern_table_keeps_the_first_non_zero_kern_for_each_pair() {
+        let results = vec![
+            result("A", "V", "Regular", -50.4),
+            result("A", "V", "Regular", -70.0),
+            result("T", "o", "Regular", 0.3),
+            result("A&B", "\"", "Regular", 12.0),
+            result("L", "T", "Bold", -90.0),
+        ];
+        let ttx = kern_table_ttx(&results, Some("Regular"));
+        assert_eq!(
+            ttx,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ttFont>\n<kern>\n  <version value=\"0\"/>\n  <kernsubtable coverage=\"1\" format=\"0\">\n    <pair l=\"A\" r=\"V\" v=\"-50\"/>\n    <pair l=\"A&amp;B\" r=\"&quot;\" v=\"12\"/>\n  </kernsubtable>\n</kern>\n</ttFont>\n"
+        );
+        assert!(kern_table_ttx(&results, None).contains("<pair l=\"L\" r=\"T\" v=\"-90\"/>"));
+    }
+
+    #[test]
+    fn kern_table_splits_full_subtables() {
+        let results: Vec<KernResult> = (0..=PAIRS_PER_SUBTABLE)
+            .map(|i| result(&format!("g{}", i), "V", "Regular", -10.0))
+            .collect();
+        let ttx = kern_table_ttx(&results, None);
+        assert_eq!(ttx.matches("<kernsubtable ").count(), 2);
+        assert_eq!(ttx.matches("<pair ").count(), PAIRS_PER_SUBTABLE + 1);
+    }
+
+    #[test]
+    fn pair_sets_follow_the_glyph_order() {
+        let results = vec![
+            result("A", "T", "Regular", -20.0),
+            result("V", "A", "Regular", -30.0),
+            result("A", "V", "Regular", -50.0),
+        ];
+        let order: Vec<String> = ["V", "A", "T"].iter().map(|g| g.to_string()).collect();
+        let ttx = pair_pos_ttx(&results, None, Some(&order), 3);
+        assert!(ttx.starts_with("<Lookup index=\"3\">\n"));
+        let position = |text: &str| ttx.find(text).unwrap();
+        assert!(position("<Glyph value=\"V\"/>") < position("<Glyph value=\"A\"/>"));
+        assert!(position("<PairSet index=\"0\">") < position("<SecondGlyph value=\"A\"/>"));
+        assert!(position("<PairSet index=\"1\">") < position("<SecondGlyph value=\"V\"/>"));
+        assert!(position("<SecondGlyph value=\"V\"/>") < position("<SecondGlyph value=\"T\"/>"));
+    }
+
+    #[test]
+    fn pair_sets_are_sorted_by_name_without_a_glyph_order() {
+        let results = vec![
+            result("V", "A", "Regular", -30.0),
+            result("A", "V", "Regular", -50.0),
+        ];
+        let ttx = pair_pos_ttx(&results, None, None, 0);
+        assert!(ttx.find("<Glyph value=\"A\"/>") < ttx.find("<Glyph value=\"V\"/>"));
+    }
+}